@@ -1,4 +1,4 @@
-use clap::{Args, Parser, Subcommand};
+use clap::{Args, Parser, Subcommand, ValueEnum};
 
 #[derive(Debug, Parser)]
 #[clap(author, version, about)]
@@ -22,10 +22,36 @@ pub enum ActionType {
 
     /// Verify package against hashes, it also rebuilds the package
     Verify(VerifyConfig),
+    /// manage the source of git packages
+    Git(GitCommand),
     // pkg-builder version
     Version
 }
 
+impl ActionType {
+    pub fn get_dry_run_args(&self) -> Option<&DryRunArgs> {
+        match self {
+            ActionType::Package(command) => Some(&command.dry_run),
+            ActionType::Env(command) => match &command.build_env_sub_command {
+                BuildEnvSubCommand::Create(sub_command) => Some(&sub_command.dry_run),
+                BuildEnvSubCommand::Clean(sub_command) => Some(&sub_command.dry_run),
+                BuildEnvSubCommand::List(sub_command) => Some(&sub_command.dry_run),
+                BuildEnvSubCommand::Update(sub_command) => Some(&sub_command.dry_run),
+                BuildEnvSubCommand::Info(sub_command) => Some(&sub_command.dry_run),
+                BuildEnvSubCommand::Rebuild(sub_command) => Some(&sub_command.dry_run),
+            },
+            ActionType::Piuparts(command)
+            | ActionType::Autopkgtest(command)
+            | ActionType::Lintian(command) => Some(&command.dry_run),
+            ActionType::Verify(command) => Some(&command.dry_run),
+            ActionType::Git(command) => match &command.git_sub_command {
+                GitSubCommand::Lock(sub_command) => Some(&sub_command.dry_run),
+            },
+            ActionType::Version => None,
+        }
+    }
+}
+
 #[derive(Debug, Args)]
 pub struct VerifyConfig {
     /// location of pkg-builder config_file, either full path
@@ -43,6 +69,8 @@ pub struct VerifyConfig {
     /// if given it won't repackage it
     #[clap(long)]
     pub no_package: Option<bool>,
+    #[clap(flatten)]
+    pub dry_run: DryRunArgs,
 }
 
 #[derive(Debug, Args)]
@@ -51,6 +79,8 @@ pub struct DefaultCommand {
     /// or directory to pkg-builder.toml is located
     /// if not given current directory is searched for pkg-builder.toml
     pub config: Option<String>,
+    #[clap(flatten)]
+    pub dry_run: DryRunArgs,
 }
 
 #[derive(Debug, Args)]
@@ -70,6 +100,12 @@ pub struct PackageCommand {
     #[clap(long)]
     /// runs lintian or not, based on value, overrides config value
     pub run_lintian: Option<bool>,
+    /// overrides config value
+    /// builds git packages from the git cache only, without using the network
+    #[clap(long)]
+    pub git_offline: Option<bool>,
+    #[clap(flatten)]
+    pub dry_run: DryRunArgs,
 }
 
 #[derive(Debug, Args)]
@@ -83,6 +119,14 @@ pub enum BuildEnvSubCommand {
     Create(CreateBuildEnvCommand),
     /// removes build env
     Clean(CleanBuildEnvCommand),
+    /// lists cached build envs with their size, creation date and hash
+    List(ListBuildEnvCommand),
+    /// upgrades the packages of the build env in place
+    Update(UpdateBuildEnvCommand),
+    /// shows the packages installed in the build env
+    Info(InfoBuildEnvCommand),
+    /// removes and creates the build env again, including the autopkgtest image
+    Rebuild(RebuildBuildEnvCommand),
 }
 
 #[derive(Debug, Args)]
@@ -91,6 +135,12 @@ pub struct CreateBuildEnvCommand {
     /// or directory to pkg-builder.toml is located
     /// if not given current directory is searched for pkg-builder.toml
     pub config: Option<String>,
+    /// also creates the image or tarball autopkgtest runs in,
+    /// even if run_autopkgtest is not set in the config
+    #[clap(long)]
+    pub with_autopkgtest: bool,
+    #[clap(flatten)]
+    pub dry_run: DryRunArgs,
 }
 #[derive(Debug, Args)]
 pub struct CleanBuildEnvCommand {
@@ -98,4 +148,83 @@ pub struct CleanBuildEnvCommand {
     /// or directory to pkg-builder.toml is located
     /// if not given current directory is searched for pkg-builder.toml
     pub config: Option<String>,
+    #[clap(flatten)]
+    pub dry_run: DryRunArgs,
+}
+#[derive(Debug, Args)]
+pub struct ListBuildEnvCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+    #[clap(flatten)]
+    pub dry_run: DryRunArgs,
+}
+#[derive(Debug, Args)]
+pub struct UpdateBuildEnvCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+    #[clap(flatten)]
+    pub dry_run: DryRunArgs,
+}
+#[derive(Debug, Args)]
+pub struct InfoBuildEnvCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+    #[clap(flatten)]
+    pub dry_run: DryRunArgs,
+}
+#[derive(Debug, Args)]
+pub struct RebuildBuildEnvCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+    /// also creates the image or tarball autopkgtest runs in,
+    /// even if run_autopkgtest is not set in the config
+    #[clap(long)]
+    pub with_autopkgtest: bool,
+    #[clap(flatten)]
+    pub dry_run: DryRunArgs,
+}
+
+#[derive(Debug, Args)]
+pub struct GitCommand {
+    #[clap(subcommand)]
+    pub git_sub_command: GitSubCommand,
+}
+#[derive(Debug, Subcommand)]
+pub enum GitSubCommand {
+    /// clones the tag and writes its submodules, at any depth, to the submodules of the config
+    Lock(LockGitCommand),
+}
+
+#[derive(Debug, Args)]
+pub struct LockGitCommand {
+    /// location of pkg-builder config_file, either full path
+    /// or directory to pkg-builder.toml is located
+    /// if not given current directory is searched for pkg-builder.toml
+    pub config: Option<String>,
+    #[clap(flatten)]
+    pub dry_run: DryRunArgs,
+}
+
+#[derive(Debug, Args, Clone)]
+pub struct DryRunArgs {
+    /// prints the commands and filesystem changes instead of executing them
+    #[clap(long)]
+    pub dry_run: bool,
+    /// format of the printed plan when using --dry-run
+    #[clap(long, value_enum, default_value = "text")]
+    pub plan_format: PlanFormat,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+pub enum PlanFormat {
+    Text,
+    Json,
 }
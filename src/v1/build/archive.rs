@@ -0,0 +1,443 @@
+use crate::v1::build::date::civil_to_epoch;
+use crate::v1::build::dry_run::{self, PlanStep};
+use eyre::{eyre, Result};
+use std::fs::{self, File};
+use std::io::{self, BufReader, Read};
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Component, Path, PathBuf};
+use std::time::UNIX_EPOCH;
+
+/// Compression of a tarball.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum Compression {
+    Gzip,
+    Bzip2,
+    Xz,
+    Zstd,
+}
+
+impl Compression {
+    pub fn extension(&self) -> &str {
+        match self {
+            Compression::Gzip => "gz",
+            Compression::Bzip2 => "bz2",
+            Compression::Xz => "xz",
+            Compression::Zstd => "zst",
+        }
+    }
+
+    /// Whether dpkg-source accepts it for the orig tarball of a 3.0 (quilt) source package.
+    pub fn is_orig_compression(&self) -> bool {
+        *self != Compression::Zstd
+    }
+}
+
+/// Format of an upstream source archive.
+#[derive(Debug, PartialEq, Clone, Copy)]
+pub enum ArchiveFormat {
+    Tar(Compression),
+    Zip,
+}
+
+impl ArchiveFormat {
+    /// From the extension of the file name, e.g. of the tarball url.
+    pub fn from_file_name(name: &str) -> Option<ArchiveFormat> {
+        let name = name.to_lowercase();
+        let formats = [
+            (&[".tar.gz", ".tgz"][..], ArchiveFormat::Tar(Compression::Gzip)),
+            (&[".tar.bz2", ".tbz2", ".tbz"][..], ArchiveFormat::Tar(Compression::Bzip2)),
+            (&[".tar.xz", ".txz"][..], ArchiveFormat::Tar(Compression::Xz)),
+            (&[".tar.zst", ".tzst"][..], ArchiveFormat::Tar(Compression::Zstd)),
+            (&[".zip"][..], ArchiveFormat::Zip),
+        ];
+        formats
+            .into_iter()
+            .find(|(extensions, _)| extensions.iter().any(|extension| name.ends_with(extension)))
+            .map(|(_, format)| format)
+    }
+
+    /// From the magic bytes at the start of the file.
+    pub fn detect(path: &Path) -> Result<ArchiveFormat> {
+        let mut magic = Vec::with_capacity(6);
+        File::open(path)
+            .map_err(|err| eyre!("Could not open {}: {}", path.display(), err))?
+            .take(6)
+            .read_to_end(&mut magic)?;
+        match magic.as_slice() {
+            [0x1f, 0x8b, ..] => Ok(ArchiveFormat::Tar(Compression::Gzip)),
+            [b'B', b'Z', b'h', ..] => Ok(ArchiveFormat::Tar(Compression::Bzip2)),
+            [0xfd, b'7', b'z', b'X', b'Z', 0x00] => Ok(ArchiveFormat::Tar(Compression::Xz)),
+            [0x28, 0xb5, 0x2f, 0xfd, ..] => Ok(ArchiveFormat::Tar(Compression::Zstd)),
+            [b'P', b'K', 0x03, 0x04, ..] | [b'P', b'K', 0x05, 0x06, ..] => Ok(ArchiveFormat::Zip),
+            _ => Err(eyre!(
+                "{} is not a supported archive, expected a .tar.gz, .tar.bz2, .tar.xz, .tar.zst or .zip",
+                path.display()
+            )),
+        }
+    }
+
+    pub fn name(&self) -> String {
+        match self {
+            ArchiveFormat::Tar(compression) => format!("tar.{}", compression.extension()),
+            ArchiveFormat::Zip => "zip".to_string(),
+        }
+    }
+
+    /// Compression of the orig tarball, the upstream one if dpkg-source accepts it, xz otherwise.
+    pub fn get_orig_compression(&self) -> Compression {
+        match self {
+            ArchiveFormat::Tar(compression) if compression.is_orig_compression() => *compression,
+            _ => Compression::Xz,
+        }
+    }
+
+    /// Whether the archive has to be repacked to become the orig tarball.
+    pub fn needs_repack(&self) -> bool {
+        *self != ArchiveFormat::Tar(self.get_orig_compression())
+    }
+}
+
+fn open_tar(path: &Path) -> Result<tar::Archive<Box<dyn Read>>> {
+    let file = BufReader::new(File::open(path).map_err(|err| eyre!("Could not open {}: {}", path.display(), err))?);
+    let reader: Box<dyn Read> = match ArchiveFormat::detect(path)? {
+        ArchiveFormat::Tar(Compression::Gzip) => Box::new(flate2::read::GzDecoder::new(file)),
+        ArchiveFormat::Tar(Compression::Bzip2) => Box::new(bzip2::read::BzDecoder::new(file)),
+        ArchiveFormat::Tar(Compression::Xz) => Box::new(xz2::read::XzDecoder::new(file)),
+        ArchiveFormat::Tar(Compression::Zstd) => Box::new(zstd::stream::read::Decoder::with_buffer(file)?),
+        ArchiveFormat::Zip => return Err(eyre!("{} is a zip archive, not a tarball", path.display())),
+    };
+    Ok(tar::Archive::new(reader))
+}
+
+/// Paths of the entries in the tarball, in the order they are stored.
+pub fn list_tar(path: &Path) -> Result<Vec<String>> {
+    let mut archive = open_tar(path)?;
+    let mut names = vec![];
+    for entry in archive.entries()? {
+        let entry = entry?;
+        let mut name = entry.path()?.to_string_lossy().to_string();
+        if entry.header().entry_type().is_dir() && !name.ends_with('/') {
+            name.push('/');
+        }
+        names.push(name);
+    }
+    Ok(names)
+}
+
+/// `path` without its first `strip` components, None for entries above or outside of the destination.
+fn strip_path(path: &Path, strip: usize) -> Option<PathBuf> {
+    let mut components = path.components().filter(|component| *component != Component::CurDir);
+    let mut stripped = PathBuf::new();
+    for component in components.by_ref().take(strip) {
+        if !matches!(component, Component::Normal(_)) {
+            return None;
+        }
+    }
+    for component in components {
+        match component {
+            Component::Normal(name) => stripped.push(name),
+            _ => return None,
+        }
+    }
+    if stripped.as_os_str().is_empty() {
+        None
+    } else {
+        Some(stripped)
+    }
+}
+
+/// Extracts the tarball into `destination` like `tar --strip-components`.
+pub fn extract_tar(path: &Path, destination: &Path, strip: usize) -> Result<()> {
+    if dry_run::record(PlanStep::Extract { archive: path.display().to_string(), path: destination.display().to_string() }) {
+        return Ok(());
+    }
+    fs::create_dir_all(destination)?;
+    let mut archive = open_tar(path)?;
+    archive.set_preserve_permissions(true);
+    for entry in archive.entries()? {
+        let mut entry = entry?;
+        let entry_path = entry.path()?.to_path_buf();
+        let Some(stripped) = strip_path(&entry_path, strip) else {
+            continue;
+        };
+        let target = destination.join(&stripped);
+        if let Some(parent) = target.parent() {
+            fs::create_dir_all(parent)?;
+        }
+        if entry.header().entry_type().is_hard_link() {
+            // the link name is relative to the archive root, so it is stripped like the paths
+            let link_name = entry
+                .link_name()?
+                .ok_or_else(|| eyre!("Hard link {} has no target", entry_path.display()))?
+                .to_path_buf();
+            let source = strip_path(&link_name, strip)
+                .ok_or_else(|| eyre!("Hard link {} points outside of the source", entry_path.display()))?;
+            fs::hard_link(destination.join(source), &target)?;
+            continue;
+        }
+        entry
+            .unpack(&target)
+            .map_err(|err| eyre!("Could not extract {}: {}", entry_path.display(), err))?;
+    }
+    Ok(())
+}
+
+/// Writes the content of `source_dir` as a .tar.gz, below `prefix` in the archive.
+///
+/// The tarball only depends on the files: entries are sorted by name, owned by root,
+/// their mtime is clamped to `source_date_epoch` and the gzip header has no timestamp.
+pub fn create_tar_gz(source_dir: &Path, prefix: &str, tarball: &Path, source_date_epoch: u64) -> Result<()> {
+    if dry_run::record(PlanStep::WriteFile { path: tarball.display().to_string() }) {
+        return Ok(());
+    }
+    let encoder = flate2::GzBuilder::new()
+        .mtime(0)
+        .write(File::create(tarball)?, flate2::Compression::default());
+    let mut builder = tar::Builder::new(encoder);
+    let mut paths = vec![];
+    if !prefix.is_empty() {
+        paths.push(PathBuf::new());
+    }
+    collect_paths(source_dir, &PathBuf::new(), &mut paths)?;
+    for relative_path in paths {
+        let path = source_dir.join(&relative_path);
+        let archive_path = Path::new(prefix).join(&relative_path);
+        let metadata = fs::symlink_metadata(&path)?;
+        let mut header = tar::Header::new_gnu();
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(metadata.modified()?.duration_since(UNIX_EPOCH).map(|mtime| mtime.as_secs()).unwrap_or_default().min(source_date_epoch));
+        header.set_mode(metadata.permissions().mode() & 0o7777);
+        if metadata.is_dir() {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_size(0);
+            builder.append_data(&mut header, &archive_path, io::empty())?;
+        } else if metadata.is_symlink() {
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_size(0);
+            builder.append_link(&mut header, &archive_path, fs::read_link(&path)?)?;
+        } else {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_size(metadata.len());
+            builder.append_data(&mut header, &archive_path, File::open(&path)?)?;
+        }
+    }
+    builder.into_inner()?.finish()?;
+    Ok(())
+}
+
+/// Paths below `dir` relative to `source_dir`, directories before their content, sorted by name.
+fn collect_paths(source_dir: &Path, dir: &Path, paths: &mut Vec<PathBuf>) -> Result<()> {
+    let mut entries: Vec<_> = fs::read_dir(source_dir.join(dir))?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = dir.join(entry.file_name());
+        paths.push(path.clone());
+        if entry.file_type()?.is_dir() {
+            collect_paths(source_dir, &path, paths)?;
+        }
+    }
+    Ok(())
+}
+
+/// Converts the upstream archive to an orig tarball dpkg-source accepts, compressed with xz.
+///
+/// zstd tarballs are recompressed as is, zip archives are converted with the entries
+/// sorted by name and owned by root, so the same zip always gives the same tarball.
+pub fn repack_as_orig(upstream: &Path, orig: &Path) -> Result<()> {
+    if dry_run::record(PlanStep::WriteFile { path: orig.display().to_string() }) {
+        return Ok(());
+    }
+    let format = ArchiveFormat::detect(upstream)?;
+    let mut encoder = xz2::write::XzEncoder::new(File::create(orig)?, 6);
+    match format {
+        ArchiveFormat::Zip => zip_to_tar(upstream, &mut encoder)?,
+        ArchiveFormat::Tar(Compression::Zstd) => {
+            let mut decoder = zstd::stream::read::Decoder::new(File::open(upstream)?)?;
+            io::copy(&mut decoder, &mut encoder)?;
+        }
+        ArchiveFormat::Tar(_) => return Err(eyre!("{} does not need to be repacked", upstream.display())),
+    }
+    encoder.finish()?;
+    Ok(())
+}
+
+fn zip_to_tar<W: io::Write>(zip_path: &Path, writer: W) -> Result<()> {
+    let mut zip = zip::ZipArchive::new(File::open(zip_path)?)
+        .map_err(|err| eyre!("Could not read {}: {}", zip_path.display(), err))?;
+    let mut names: Vec<String> = zip.file_names().map(|name| name.to_string()).collect();
+    names.sort();
+    let mut builder = tar::Builder::new(writer);
+    for name in names {
+        let mut file = zip.by_name(&name)?;
+        let path = file
+            .enclosed_name()
+            .ok_or_else(|| eyre!("{} in {} points outside of the archive", name, zip_path.display()))?;
+        let mode = file.unix_mode();
+        let mtime = file.last_modified().map(zip_time_to_unix).unwrap_or_default();
+        let mut header = tar::Header::new_gnu();
+        header.set_uid(0);
+        header.set_gid(0);
+        header.set_mtime(mtime);
+        if file.is_dir() {
+            header.set_entry_type(tar::EntryType::Directory);
+            header.set_mode(mode.unwrap_or(0o755) & 0o7777);
+            header.set_size(0);
+            builder.append_data(&mut header, &path, io::empty())?;
+        } else if mode.is_some_and(|mode| mode & 0o170000 == 0o120000) {
+            let mut target = String::new();
+            file.read_to_string(&mut target)?;
+            header.set_entry_type(tar::EntryType::Symlink);
+            header.set_mode(0o777);
+            header.set_size(0);
+            builder.append_link(&mut header, &path, target)?;
+        } else {
+            header.set_entry_type(tar::EntryType::Regular);
+            header.set_mode(mode.unwrap_or(0o644) & 0o7777);
+            header.set_size(file.size());
+            builder.append_data(&mut header, &path, &mut file)?;
+        }
+    }
+    builder.into_inner()?;
+    Ok(())
+}
+
+/// Seconds since the epoch of the local time a zip stores, taken as UTC.
+fn zip_time_to_unix(time: zip::DateTime) -> u64 {
+    let seconds = civil_to_epoch(
+        time.year() as i64,
+        time.month() as i64,
+        time.day() as i64,
+        time.hour() as i64,
+        time.minute() as i64,
+        time.second() as i64,
+    );
+    seconds.max(0) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::io::Write;
+    use tempfile::tempdir;
+
+    fn write_tar<W: io::Write>(writer: W) {
+        let mut builder = tar::Builder::new(writer);
+        let mut header = tar::Header::new_gnu();
+        header.set_entry_type(tar::EntryType::Directory);
+        header.set_mode(0o755);
+        header.set_size(0);
+        builder.append_data(&mut header, "hello-1.0/", io::empty()).unwrap();
+        let mut header = tar::Header::new_gnu();
+        header.set_mode(0o755);
+        header.set_size(5);
+        builder.append_data(&mut header, "hello-1.0/configure", "hello".as_bytes()).unwrap();
+        builder.into_inner().unwrap();
+    }
+
+    #[test]
+    fn test_format_from_name_and_content() {
+        assert_eq!(ArchiveFormat::from_file_name("https://example.com/hello-1.0.tar.xz"), Some(ArchiveFormat::Tar(Compression::Xz)));
+        assert_eq!(ArchiveFormat::from_file_name("hello-1.0.tgz"), Some(ArchiveFormat::Tar(Compression::Gzip)));
+        assert_eq!(ArchiveFormat::from_file_name("hello-1.0.zip"), Some(ArchiveFormat::Zip));
+        assert_eq!(ArchiveFormat::from_file_name("https://example.com/download?version=1.0"), None);
+        assert!(!ArchiveFormat::Tar(Compression::Bzip2).needs_repack());
+        assert!(ArchiveFormat::Tar(Compression::Zstd).needs_repack());
+        assert_eq!(ArchiveFormat::Zip.get_orig_compression(), Compression::Xz);
+
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("hello-1.0.tar.zst");
+        write_tar(zstd::stream::write::Encoder::new(File::create(&path).unwrap(), 3).unwrap().auto_finish());
+        assert_eq!(ArchiveFormat::detect(&path).unwrap(), ArchiveFormat::Tar(Compression::Zstd));
+        fs::write(&path, "not an archive").unwrap();
+        assert!(ArchiveFormat::detect(&path).unwrap_err().to_string().ends_with("is not a supported archive, expected a .tar.gz, .tar.bz2, .tar.xz, .tar.zst or .zip"));
+    }
+
+    #[test]
+    fn test_extract_xz_tarball_stripping_components() {
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("hello-1.0.tar.xz");
+        let mut encoder = xz2::write::XzEncoder::new(File::create(&path).unwrap(), 6);
+        write_tar(&mut encoder);
+        encoder.finish().unwrap();
+
+        assert_eq!(list_tar(&path).unwrap(), vec!["hello-1.0/", "hello-1.0/configure"]);
+        let destination = temp_dir.path().join("source");
+        extract_tar(&path, &destination, 1).unwrap();
+        let configure = destination.join("configure");
+        assert_eq!(fs::read_to_string(&configure).unwrap(), "hello");
+        assert_eq!(fs::metadata(&configure).unwrap().permissions().mode() & 0o777, 0o755);
+    }
+
+    #[test]
+    fn test_strip_path_rejects_escaping_entries() {
+        assert_eq!(strip_path(Path::new("./hello-1.0/src/main.c"), 1), Some(PathBuf::from("src/main.c")));
+        assert_eq!(strip_path(Path::new("hello-1.0/"), 1), None);
+        assert_eq!(strip_path(Path::new("hello-1.0/../../etc/passwd"), 1), None);
+        assert_eq!(strip_path(Path::new("/etc/passwd"), 0), None);
+    }
+
+    #[test]
+    fn test_create_tar_gz_is_reproducible() {
+        let temp_dir = tempdir().unwrap();
+        let source_dir = temp_dir.path().join("hello");
+        fs::create_dir_all(source_dir.join("src")).unwrap();
+        fs::write(source_dir.join("src/main.c"), "int main() {}").unwrap();
+        fs::write(source_dir.join("configure"), "#!/bin/sh").unwrap();
+        fs::set_permissions(source_dir.join("configure"), fs::Permissions::from_mode(0o755)).unwrap();
+        std::os::unix::fs::symlink("src/main.c", source_dir.join("main.c")).unwrap();
+
+        let tarball = temp_dir.path().join("hello_1.0.orig.tar.gz");
+        create_tar_gz(&source_dir, "hello", &tarball, 1709294400).unwrap();
+        assert_eq!(list_tar(&tarball).unwrap(), vec!["hello/", "hello/configure", "hello/main.c", "hello/src/", "hello/src/main.c"]);
+        let mut archive = open_tar(&tarball).unwrap();
+        for entry in archive.entries().unwrap() {
+            let header = entry.unwrap().header().clone();
+            assert_eq!(header.uid().unwrap(), 0);
+            assert_eq!(header.mtime().unwrap(), 1709294400);
+        }
+        // gzip header without a timestamp
+        let first = fs::read(&tarball).unwrap();
+        assert_eq!(first[4..8], [0, 0, 0, 0]);
+
+        // recreated files give the same tarball
+        fs::write(source_dir.join("src/main.c"), "int main() {}").unwrap();
+        create_tar_gz(&source_dir, "hello", &tarball, 1709294400).unwrap();
+        assert_eq!(fs::read(&tarball).unwrap(), first);
+
+        let destination = temp_dir.path().join("extracted");
+        extract_tar(&tarball, &destination, 1).unwrap();
+        assert_eq!(fs::metadata(destination.join("configure")).unwrap().permissions().mode() & 0o777, 0o755);
+        assert_eq!(fs::read_link(destination.join("main.c")).unwrap(), PathBuf::from("src/main.c"));
+    }
+
+    #[test]
+    fn test_repack_zip_is_reproducible() {
+        let temp_dir = tempdir().unwrap();
+        let zip_path = temp_dir.path().join("hello-1.0.zip");
+        let mut zip = zip::ZipWriter::new(File::create(&zip_path).unwrap());
+        let options = zip::write::SimpleFileOptions::default()
+            .last_modified_time(zip::DateTime::from_date_and_time(2024, 3, 1, 12, 0, 0).unwrap())
+            .unix_permissions(0o755);
+        // stored out of order, the tarball is sorted by name
+        zip.start_file("hello-1.0/src/main.c", options).unwrap();
+        zip.write_all(b"int main() {}").unwrap();
+        zip.add_directory("hello-1.0/", options).unwrap();
+        zip.finish().unwrap();
+
+        let orig = temp_dir.path().join("hello_1.0.orig.tar.xz");
+        repack_as_orig(&zip_path, &orig).unwrap();
+        assert_eq!(ArchiveFormat::detect(&orig).unwrap(), ArchiveFormat::Tar(Compression::Xz));
+        assert_eq!(list_tar(&orig).unwrap(), vec!["hello-1.0/", "hello-1.0/src/main.c"]);
+        let mut archive = open_tar(&orig).unwrap();
+        for entry in archive.entries().unwrap() {
+            let header = entry.unwrap().header().clone();
+            assert_eq!(header.uid().unwrap(), 0);
+            assert_eq!(header.mtime().unwrap(), 1709294400);
+        }
+
+        let first = fs::read(&orig).unwrap();
+        repack_as_orig(&zip_path, &orig).unwrap();
+        assert_eq!(fs::read(&orig).unwrap(), first);
+    }
+}
@@ -0,0 +1,199 @@
+use crate::v1::build::command_runner::Invocation;
+use crate::v1::build::dir_setup::calculate_sha256;
+use crate::v1::build::dry_run;
+use crate::v1::build::env_info::write_hash_file;
+use crate::v1::build::privilege::root_command;
+use crate::v1::build::sbuild::run_command;
+use crate::v1::distribution::Distribution;
+use crate::v1::pkg_config::AutopkgtestImageConfig;
+use eyre::{eyre, Result};
+use log::{info, warn};
+use std::fs;
+use std::os::unix::fs::PermissionsExt;
+use std::path::{Path, PathBuf};
+use std::process::Stdio;
+use std::time::SystemTime;
+
+const DEFAULT_MAX_AGE_DAYS: u64 = 30;
+
+/// The qemu image autopkgtest runs the tests in, created by `env create` next to the chroot tarball.
+pub struct AutopkgtestImage {
+    path: PathBuf,
+    suite: String,
+    arch: String,
+    mirror: String,
+    config: AutopkgtestImageConfig,
+}
+
+impl AutopkgtestImage {
+    pub fn new(cache_dir: &str, distribution: &Distribution, arch: &str, mirror: &str, config: AutopkgtestImageConfig) -> Self {
+        let file_name = format!("autopkgtest-{}-{}.img", distribution.suite, arch);
+        AutopkgtestImage {
+            path: Path::new(cache_dir).join(file_name),
+            suite: distribution.suite.clone(),
+            arch: arch.to_string(),
+            mirror: mirror.to_string(),
+            config,
+        }
+    }
+
+    pub fn get_path(&self) -> &Path {
+        &self.path
+    }
+
+    pub fn exists(&self) -> bool {
+        self.path.exists()
+    }
+
+    fn get_setup_script(&self) -> Option<String> {
+        if self.config.extra_packages.is_empty() {
+            return None;
+        }
+        Some(format!(
+            "#!/bin/sh\nset -e\nchroot \"$1\" apt-get install -y --no-install-recommends {}\n",
+            self.config.extra_packages.join(" ")
+        ))
+    }
+
+    pub fn get_build_args(&self, setup_script: Option<&Path>) -> Vec<String> {
+        let mut args = vec![
+            format!("--architecture={}", self.arch),
+            format!("--mirror={}", self.mirror),
+        ];
+        if let Some(size) = &self.config.size {
+            args.push(format!("--size={}", size));
+        }
+        if let Some(setup_script) = setup_script {
+            args.push(format!("--script={}", setup_script.display()));
+        }
+        args.push(self.suite.clone());
+        args.push(self.path.to_str().unwrap().to_string());
+        args
+    }
+
+    pub fn create(&self) -> Result<()> {
+        if self.exists() {
+            info!("Autopkgtest image {} already exists.", self.path.display());
+            return Ok(());
+        }
+        info!("Creating autopkgtest image {}", self.path.display());
+        dry_run::create_dir_all(self.path.parent().unwrap())?;
+
+        let script_dir = dry_run::tempdir()?;
+        let setup_script = match self.get_setup_script() {
+            Some(content) => {
+                let script_path = script_dir.path().join("setup.sh");
+                dry_run::write(&script_path, content)?;
+                dry_run::set_permissions(&script_path, fs::Permissions::from_mode(0o755))?;
+                Some(script_path)
+            }
+            None => None,
+        };
+        let cmd_args = self.get_build_args(setup_script.as_deref());
+        // vmdb2 partitions and mounts the image, which is not possible without root
+        let mut cmd = root_command("autopkgtest-build-qemu", "Creating the autopkgtest qemu image")?;
+        info!("Creating autopkgtest image by invoking: {} {}", Invocation::from_command(&cmd).command_line(), cmd_args.join(" "));
+        cmd.args(&cmd_args)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        run_command(&mut cmd)?;
+        if !dry_run::is_enabled() {
+            write_hash_file(&self.path, &calculate_sha256(fs::File::open(&self.path)?)?)?;
+        }
+        Ok(())
+    }
+
+    pub fn clean(&self) -> Result<()> {
+        if self.exists() {
+            info!("Removing autopkgtest image {}", self.path.display());
+            dry_run::remove_file(&self.path)?;
+        }
+        let hash_file = format!("{}.sha256", self.path.display());
+        if Path::new(&hash_file).exists() {
+            dry_run::remove_file(&hash_file)?;
+        }
+        Ok(())
+    }
+
+    /// Fails if the image is missing and warns if it is older than `max_age_days`,
+    /// as tests in an outdated image can hide regressions.
+    pub fn check(&self) -> Result<()> {
+        if dry_run::is_enabled() {
+            return Ok(());
+        }
+        if !self.exists() {
+            return Err(eyre!(
+                "Autopkgtest image {} does not exist, please create it with: pkg-builder env create --with-autopkgtest",
+                self.path.display()
+            ));
+        }
+        let max_age_days = self.config.max_age_days.unwrap_or(DEFAULT_MAX_AGE_DAYS);
+        let age_days = self.get_age_days()?;
+        if age_days > max_age_days {
+            warn!(
+                "Autopkgtest image {} is {} days old, refresh it with: pkg-builder env rebuild",
+                self.path.display(),
+                age_days
+            );
+        }
+        Ok(())
+    }
+
+    fn get_age_days(&self) -> Result<u64> {
+        let modified = fs::metadata(&self.path)?.modified()?;
+        let age = SystemTime::now().duration_since(modified).unwrap_or_default();
+        Ok(age.as_secs() / 86400)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use crate::v1::distribution::builtin_distributions;
+    use filetime::{set_file_mtime, FileTime};
+
+    fn image(cache_dir: &str, config: AutopkgtestImageConfig) -> AutopkgtestImage {
+        let bookworm = &builtin_distributions()[0];
+        AutopkgtestImage::new(cache_dir, bookworm, "arm64", "http://deb.debian.org/debian", config)
+    }
+
+    #[test]
+    fn test_build_args() {
+        let config = AutopkgtestImageConfig {
+            size: Some("30G".to_string()),
+            extra_packages: vec!["ca-certificates".to_string(), "curl".to_string()],
+            max_age_days: None,
+        };
+        let image = image("/cache", config);
+        assert_eq!(image.get_path(), Path::new("/cache/autopkgtest-bookworm-arm64.img"));
+        assert_eq!(image.get_setup_script().unwrap(),
+                   "#!/bin/sh\nset -e\nchroot \"$1\" apt-get install -y --no-install-recommends ca-certificates curl\n");
+        assert_eq!(image.get_build_args(Some(Path::new("/tmp/setup.sh"))), vec![
+            "--architecture=arm64",
+            "--mirror=http://deb.debian.org/debian",
+            "--size=30G",
+            "--script=/tmp/setup.sh",
+            "bookworm",
+            "/cache/autopkgtest-bookworm-arm64.img",
+        ]);
+    }
+
+    #[test]
+    fn test_check_missing_and_stale_image() {
+        let cache_dir = tempdir().unwrap();
+        let image = image(cache_dir.path().to_str().unwrap(), AutopkgtestImageConfig::default());
+        let err = image.check().unwrap_err().to_string();
+        assert!(err.contains("does not exist, please create it with: pkg-builder env create --with-autopkgtest"));
+
+        fs::write(image.get_path(), "image").unwrap();
+        assert_eq!(image.get_age_days().unwrap(), 0);
+        let forty_days_ago = SystemTime::now() - std::time::Duration::from_secs(40 * 86400);
+        set_file_mtime(image.get_path(), FileTime::from_system_time(forty_days_ago)).unwrap();
+        assert_eq!(image.get_age_days().unwrap(), 40);
+        assert!(image.check().is_ok());
+
+        image.clean().unwrap();
+        assert!(!image.exists());
+    }
+}
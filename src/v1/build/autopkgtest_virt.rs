@@ -0,0 +1,264 @@
+use crate::v1::build::autopkgtest_image::AutopkgtestImage;
+use crate::v1::build::dry_run;
+use crate::v1::build::sbuild::run_command;
+use crate::v1::distribution::{Distribution, Vendor};
+use crate::v1::pkg_config::{AutopkgtestBackend, AutopkgtestOptions, BuildEnv};
+use eyre::{eyre, Result};
+use log::{info, warn};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+/// The virtualization server autopkgtest runs the tests in, the part of the command after `--`.
+pub struct AutopkgtestVirt {
+    backend: AutopkgtestBackend,
+    options: AutopkgtestOptions,
+    distribution: Distribution,
+    arch: String,
+    build_arch: String,
+    mirror: String,
+    image: AutopkgtestImage,
+    chroot_tarball: String,
+}
+
+impl AutopkgtestVirt {
+    pub fn new(
+        build_env: &BuildEnv,
+        distribution: Distribution,
+        mirror: &str,
+        image: AutopkgtestImage,
+        chroot_tarball: &str,
+    ) -> Self {
+        AutopkgtestVirt {
+            backend: build_env.autopkgtest_backend.clone().unwrap_or_default(),
+            options: build_env.autopkgtest_options.clone().unwrap_or_default(),
+            distribution,
+            arch: build_env.arch.clone(),
+            build_arch: build_env.get_build_arch(),
+            mirror: mirror.to_string(),
+            image,
+            chroot_tarball: chroot_tarball.to_string(),
+        }
+    }
+
+    fn get_tarball(&self) -> String {
+        match &self.options.tarball {
+            Some(tarball) => shellexpand::tilde(tarball).to_string(),
+            None => self.chroot_tarball.clone(),
+        }
+    }
+
+    fn get_schroot(&self) -> String {
+        match &self.options.schroot {
+            Some(schroot) => schroot.clone(),
+            None => format!("{}-{}-sbuild", self.distribution.suite, self.arch),
+        }
+    }
+
+    fn get_podman_image(&self) -> String {
+        match &self.options.image {
+            Some(image) => image.clone(),
+            // the tag autopkgtest-build-podman uses by default
+            None => format!("autopkgtest/{}:{}", self.get_vendor(), self.distribution.suite),
+        }
+    }
+
+    fn get_vendor(&self) -> &str {
+        match self.distribution.vendor {
+            Vendor::Debian => "debian",
+            Vendor::Ubuntu => "ubuntu",
+        }
+    }
+
+    pub fn get_virt_args(&self) -> Vec<String> {
+        let mut args = vec![self.backend.name().to_string()];
+        match self.backend {
+            AutopkgtestBackend::Qemu => {
+                if let Some(ram_size) = self.options.ram_size {
+                    args.push(format!("--ram-size={}", ram_size));
+                }
+                if let Some(cpus) = self.options.cpus {
+                    args.push(format!("--cpus={}", cpus));
+                }
+                if self.arch != self.build_arch {
+                    // the image of a cross build is of the host architecture, which qemu emulates
+                    args.push(format!("--qemu-architecture={}", self.arch));
+                }
+                args.push(self.image.get_path().to_str().unwrap().to_string());
+            }
+            AutopkgtestBackend::Unshare => {
+                args.push(format!("--release={}", self.distribution.suite));
+                args.push(format!("--arch={}", self.arch));
+                args.push(format!("--tarball={}", self.get_tarball()));
+            }
+            AutopkgtestBackend::Schroot => args.push(self.get_schroot()),
+            AutopkgtestBackend::Podman => args.push(self.get_podman_image()),
+            AutopkgtestBackend::Null => {}
+        }
+        args
+    }
+
+    fn podman_image_exists(&self) -> Result<bool> {
+        let output = dry_run::output(Command::new("podman")
+            .args(["image", "inspect", &self.get_podman_image()]))
+            .map_err(|err| eyre!("Failed to run podman, is it installed? {}", err))?;
+        Ok(output.status.success())
+    }
+
+    /// Provisions the image the tests run in, the chroot tarball for unshare is created with the build env.
+    pub fn create(&self) -> Result<()> {
+        match self.backend {
+            AutopkgtestBackend::Qemu => self.image.create(),
+            AutopkgtestBackend::Podman => {
+                if self.podman_image_exists()? {
+                    info!("Autopkgtest image {} already exists.", self.get_podman_image());
+                    return Ok(());
+                }
+                let cmd_args = vec![
+                    format!("--vendor={}", self.get_vendor()),
+                    format!("--release={}", self.distribution.suite),
+                    format!("--mirror={}", self.mirror),
+                    format!("--tag={}", self.get_podman_image()),
+                ];
+                info!("Creating autopkgtest image by invoking: autopkgtest-build-podman {}", cmd_args.join(" "));
+                let mut cmd = Command::new("autopkgtest-build-podman");
+                cmd.args(&cmd_args)
+                    .stdout(Stdio::inherit())
+                    .stderr(Stdio::inherit());
+                run_command(&mut cmd)
+            }
+            AutopkgtestBackend::Unshare | AutopkgtestBackend::Schroot | AutopkgtestBackend::Null => {
+                info!("Autopkgtest backend {} has no image to create.", self.backend.name());
+                Ok(())
+            }
+        }
+    }
+
+    pub fn clean(&self) -> Result<()> {
+        match self.backend {
+            AutopkgtestBackend::Qemu => self.image.clean(),
+            AutopkgtestBackend::Podman => {
+                if self.podman_image_exists()? {
+                    info!("Removing autopkgtest image {}", self.get_podman_image());
+                    run_command(Command::new("podman").args(["rmi", &self.get_podman_image()]))?;
+                }
+                Ok(())
+            }
+            AutopkgtestBackend::Unshare | AutopkgtestBackend::Schroot | AutopkgtestBackend::Null => Ok(()),
+        }
+    }
+
+    /// Fails early if the image or tarball the tests run in is missing.
+    pub fn check(&self) -> Result<()> {
+        match self.backend {
+            AutopkgtestBackend::Qemu => self.image.check(),
+            AutopkgtestBackend::Unshare => {
+                let tarball = self.get_tarball();
+                if !dry_run::is_enabled() && !Path::new(&tarball).exists() {
+                    return Err(eyre!(
+                        "Autopkgtest tarball {} does not exist, please create it with: pkg-builder env create",
+                        tarball
+                    ));
+                }
+                Ok(())
+            }
+            AutopkgtestBackend::Podman => {
+                if !self.podman_image_exists()? {
+                    return Err(eyre!(
+                        "Autopkgtest image {} does not exist, please create it with: pkg-builder env create --with-autopkgtest",
+                        self.get_podman_image()
+                    ));
+                }
+                Ok(())
+            }
+            AutopkgtestBackend::Schroot => Ok(()),
+            AutopkgtestBackend::Null => {
+                warn!("Autopkgtest backend null runs the tests directly on this machine, with the test dependencies installed on it.");
+                Ok(())
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::build::command_runner::{self, Invocation, ReplayCommandRunner};
+    use crate::v1::distribution::builtin_distributions;
+    use crate::v1::pkg_config::AutopkgtestImageConfig;
+    use std::rc::Rc;
+
+    fn virt(backend: AutopkgtestBackend, options: AutopkgtestOptions) -> AutopkgtestVirt {
+        let build_env = BuildEnv {
+            arch: "amd64".to_string(),
+            autopkgtest_backend: Some(backend),
+            autopkgtest_options: Some(options),
+            ..Default::default()
+        };
+        let bookworm = builtin_distributions()[0].clone();
+        let mirror = "http://deb.debian.org/debian";
+        let image = AutopkgtestImage::new("/cache", &bookworm, "amd64", mirror, AutopkgtestImageConfig::default());
+        AutopkgtestVirt::new(&build_env, bookworm, mirror, image, "/cache/bookworm-amd64.tar.gz")
+    }
+
+    #[test]
+    fn test_virt_args() {
+        let qemu_options = AutopkgtestOptions { ram_size: Some(4096), cpus: Some(4), ..Default::default() };
+        assert_eq!(virt(AutopkgtestBackend::Qemu, qemu_options).get_virt_args(),
+                   vec!["qemu", "--ram-size=4096", "--cpus=4", "/cache/autopkgtest-bookworm-amd64.img"]);
+        assert_eq!(virt(AutopkgtestBackend::Unshare, AutopkgtestOptions::default()).get_virt_args(),
+                   vec!["unshare", "--release=bookworm", "--arch=amd64", "--tarball=/cache/bookworm-amd64.tar.gz"]);
+        assert_eq!(virt(AutopkgtestBackend::Schroot, AutopkgtestOptions::default()).get_virt_args(),
+                   vec!["schroot", "bookworm-amd64-sbuild"]);
+        assert_eq!(virt(AutopkgtestBackend::Podman, AutopkgtestOptions::default()).get_virt_args(),
+                   vec!["podman", "autopkgtest/debian:bookworm"]);
+        assert_eq!(virt(AutopkgtestBackend::Null, AutopkgtestOptions::default()).get_virt_args(), vec!["null"]);
+    }
+
+    #[test]
+    fn test_qemu_emulates_host_arch_of_cross_build() {
+        let build_env = BuildEnv {
+            arch: "arm64".to_string(),
+            build_arch: Some("amd64".to_string()),
+            ..Default::default()
+        };
+        let bookworm = builtin_distributions()[0].clone();
+        let mirror = "http://deb.debian.org/debian";
+        let image = AutopkgtestImage::new("/cache", &bookworm, "arm64", mirror, AutopkgtestImageConfig::default());
+        let virt = AutopkgtestVirt::new(&build_env, bookworm, mirror, image, "/cache/bookworm-amd64.tar.gz");
+        assert_eq!(virt.get_virt_args(),
+                   vec!["qemu", "--qemu-architecture=arm64", "/cache/autopkgtest-bookworm-arm64.img"]);
+    }
+
+    #[test]
+    fn test_create_podman_image() {
+        let runner = Rc::new(ReplayCommandRunner::new(vec![
+            Invocation {
+                program: "podman".to_string(),
+                args: vec!["image".to_string(), "inspect".to_string(), "autopkgtest/debian:bookworm".to_string()],
+                exit_code: 125,
+                ..Default::default()
+            },
+            Invocation {
+                program: "autopkgtest-build-podman".to_string(),
+                args: vec![
+                    "--vendor=debian".to_string(),
+                    "--release=bookworm".to_string(),
+                    "--mirror=http://deb.debian.org/debian".to_string(),
+                    "--tag=autopkgtest/debian:bookworm".to_string(),
+                ],
+                ..Default::default()
+            },
+        ]));
+        command_runner::set_runner(runner.clone());
+        let result = virt(AutopkgtestBackend::Podman, AutopkgtestOptions::default()).create();
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_check_unshare_tarball() {
+        let options = AutopkgtestOptions { tarball: Some("/nonexistent/bookworm-amd64.tar.gz".to_string()), ..Default::default() };
+        let err = virt(AutopkgtestBackend::Unshare, options).check().unwrap_err().to_string();
+        assert!(err.starts_with("Autopkgtest tarball /nonexistent/bookworm-amd64.tar.gz does not exist"));
+    }
+}
@@ -0,0 +1,39 @@
+use crate::v1::build::sbuild_packager::SbuildPackager;
+use crate::v1::packager::{BackendBuildEnv, Packager};
+use crate::v1::pkg_config::PkgConfig;
+use eyre::Result;
+
+/// Creates the build env of a backend from the config and the build files dir.
+pub type BuildEnvConstructor = fn(PkgConfig, String) -> Box<dyn BackendBuildEnv>;
+
+/// Packager of the backends other than sbuild, which only differ in the build env.
+pub struct BackendPackager {
+    // source preparation is the same as for sbuild
+    source_packager: SbuildPackager,
+    build_env: BuildEnvConstructor,
+}
+
+impl BackendPackager {
+    pub fn new(config: PkgConfig, config_root: String, build_env: BuildEnvConstructor) -> Self {
+        BackendPackager {
+            source_packager: SbuildPackager::new(config, config_root),
+            build_env,
+        }
+    }
+}
+
+impl Packager for BackendPackager {
+    fn package(&self) -> Result<()> {
+        self.source_packager.prepare_source()?;
+        let build_env = self.get_build_env()?;
+        build_env.package()?;
+        Ok(())
+    }
+
+    fn get_build_env(&self) -> Result<Box<dyn BackendBuildEnv>> {
+        Ok((self.build_env)(
+            self.source_packager.get_config().clone(),
+            self.source_packager.get_build_files_dir().to_string(),
+        ))
+    }
+}
@@ -0,0 +1,197 @@
+use crate::v1::build::date::civil_to_epoch;
+use crate::v1::build::dir_setup::calculate_sha256;
+use crate::v1::build::privilege::{root_command, user_namespaces_available, UNSHARE_ARGS};
+use crate::v1::distribution::{Distribution, Vendor};
+use crate::v1::pkg_config::ChrootConfig;
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::process::{Command, Stdio};
+
+/// Mirror the chroot is created from, in order of precedence the configured mirror,
+/// the snapshot of the distribution's archive and the distribution's mirror.
+pub fn get_chroot_mirror(chroot: &ChrootConfig, distribution: &Distribution) -> String {
+    if let Some(mirror) = &chroot.mirror {
+        return mirror.clone();
+    }
+    match &chroot.snapshot {
+        Some(snapshot) => match distribution.vendor {
+            Vendor::Debian => format!("http://snapshot.debian.org/archive/debian/{}/", snapshot),
+            Vendor::Ubuntu => format!("http://snapshot.ubuntu.com/ubuntu/{}/", snapshot),
+        },
+        None => distribution.mirror.clone(),
+    }
+}
+
+/// Seconds since epoch of a snapshot timestamp like 20240601T000000Z.
+pub fn snapshot_to_epoch(snapshot: &str) -> Result<i64> {
+    let invalid = || eyre!("Invalid snapshot timestamp {}, expected a timestamp like 20240601T000000Z", snapshot);
+    if snapshot.len() != 16 || &snapshot[8..9] != "T" || !snapshot.ends_with('Z') {
+        return Err(invalid());
+    }
+    let number = |range: std::ops::Range<usize>| -> Result<i64> {
+        snapshot[range].parse::<i64>().map_err(|_| invalid())
+    };
+    let (year, month, day) = (number(0..4)?, number(4..6)?, number(6..8)?);
+    let (hour, minute, second) = (number(9..11)?, number(11..13)?, number(13..15)?);
+    if !(1..=12).contains(&month) || !(1..=31).contains(&day) || hour > 23 || minute > 59 || second > 60 {
+        return Err(invalid());
+    }
+    Ok(civil_to_epoch(year, month, day, hour, minute, second))
+}
+
+/// `architectures` is a comma separated list, the first one is the native architecture of the chroot.
+pub fn get_mmdebstrap_args(chroot: &ChrootConfig, distribution: &Distribution, architectures: &str, cache_file: &str) -> Vec<String> {
+    let mut args = vec![
+        "--variant=buildd".to_string(),
+        format!("--architectures={}", architectures),
+        format!("--components={}", distribution.components.join(",")),
+        format!("--keyring={}", distribution.keyring),
+    ];
+    if chroot.snapshot.is_some() {
+        // snapshots are signed once, their Release files are expired later on
+        args.push("--aptopt=Acquire::Check-Valid-Until \"false\"".to_string());
+    }
+    args.push(distribution.suite.clone());
+    args.push(cache_file.to_string());
+    args.push(get_chroot_mirror(chroot, distribution));
+    args
+}
+
+// the tarball is unpacked and packed again inside of a user namespace, like sbuild's unshare mode,
+// the mounts are removed before packing and cleaning up, even if the upgrade fails
+const UPDATE_SCRIPT: &str = r#"CACHE_FILE="$1"
+rootfs="$(mktemp -d)"
+tar --extract --file "$CACHE_FILE" --directory "$rootfs" --exclude './dev/*'
+cp /etc/resolv.conf "$rootfs/etc/resolv.conf"
+mount --rbind /dev "$rootfs/dev"
+mount -t proc proc "$rootfs/proc"
+status=0
+(chroot "$rootfs" apt-get update \
+    && chroot "$rootfs" env DEBIAN_FRONTEND=noninteractive apt-get -y dist-upgrade \
+    && chroot "$rootfs" apt-get clean) || status=$?
+umount "$rootfs/proc"
+umount -R "$rootfs/dev"
+if [ "$status" -eq 0 ]; then
+    tar --create --gzip --sort=name --file "$CACHE_FILE.new" --directory "$rootfs" . \
+        && chown --reference="$CACHE_FILE" "$CACHE_FILE.new" \
+        && mv "$CACHE_FILE.new" "$CACHE_FILE" || status=$?
+fi
+rm -rf "$rootfs"
+exit "$status"
+"#;
+
+/// Upgrades the packages of the chroot tarball in place, as root without unprivileged user namespaces.
+pub fn get_update_command(cache_file: &str) -> Result<Command> {
+    let mut cmd = if user_namespaces_available() {
+        let mut cmd = Command::new("unshare");
+        cmd.args(UNSHARE_ARGS);
+        cmd
+    } else {
+        // sudo resets the environment, so the cache file is passed as an argument
+        let mut cmd = root_command("unshare", "Updating the chroot without unprivileged user namespaces")?;
+        cmd.args(["--mount", "--pid", "--fork"]);
+        cmd
+    };
+    cmd.args(["sh", "-c", UPDATE_SCRIPT, "sh", cache_file])
+        .stdout(Stdio::inherit())
+        .stderr(Stdio::inherit());
+    Ok(cmd)
+}
+
+/// Checks the chroot tarball against `tarball_hash`, returns the actual sha256.
+pub fn verify_tarball_hash(cache_file: &str, chroot: &ChrootConfig) -> Result<String> {
+    let file = fs::File::open(cache_file)
+        .map_err(|err| eyre!("Could not open chroot tarball {}: {}", cache_file, err))?;
+    let actual_hash = calculate_sha256(file)?;
+    match &chroot.tarball_hash {
+        Some(expected_hash) if expected_hash != &actual_hash => Err(eyre!(
+            "Chroot tarball {} has sha256 {}, but tarball_hash is {}. Recreate it with: pkg-builder env create",
+            cache_file,
+            actual_hash,
+            expected_hash
+        )),
+        Some(_) => {
+            info!("Chroot tarball {} matches tarball_hash", cache_file);
+            Ok(actual_hash)
+        }
+        None => {
+            info!(
+                "Chroot tarball {} has sha256 {}, pin it with tarball_hash = \"{}\" in [build_env.chroot]",
+                cache_file, actual_hash, actual_hash
+            );
+            Ok(actual_hash)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::distribution::builtin_distributions;
+    use crate::v1::pkg_config::ChrootCreator;
+    use tempfile::tempdir;
+
+    fn snapshot_chroot() -> ChrootConfig {
+        ChrootConfig {
+            creator: ChrootCreator::Mmdebstrap,
+            snapshot: Some("20240601T000000Z".to_string()),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_chroot_mirror() {
+        let bookworm = &builtin_distributions()[0];
+        let jammy = &builtin_distributions()[1];
+        assert_eq!(get_chroot_mirror(&ChrootConfig::default(), bookworm), "http://deb.debian.org/debian");
+        assert_eq!(get_chroot_mirror(&snapshot_chroot(), bookworm), "http://snapshot.debian.org/archive/debian/20240601T000000Z/");
+        assert_eq!(get_chroot_mirror(&snapshot_chroot(), jammy), "http://snapshot.ubuntu.com/ubuntu/20240601T000000Z/");
+        let local_mirror = ChrootConfig {
+            mirror: Some("http://localhost:3142/debian".to_string()),
+            ..snapshot_chroot()
+        };
+        assert_eq!(get_chroot_mirror(&local_mirror, bookworm), "http://localhost:3142/debian");
+    }
+
+    #[test]
+    fn test_snapshot_to_epoch() {
+        assert_eq!(snapshot_to_epoch("19700101T000000Z").unwrap(), 0);
+        assert_eq!(snapshot_to_epoch("20240601T000000Z").unwrap(), 1717200000);
+        assert_eq!(snapshot_to_epoch("20240229T123456Z").unwrap(), 1709210096);
+        assert!(snapshot_to_epoch("2024-06-01").is_err());
+        assert!(snapshot_to_epoch("20241301T000000Z").is_err());
+    }
+
+    #[test]
+    fn test_mmdebstrap_args() {
+        let bookworm = &builtin_distributions()[0];
+        let args = get_mmdebstrap_args(&snapshot_chroot(), bookworm, "amd64", "/tmp/bookworm-amd64.tar.gz");
+        assert_eq!(args, vec![
+            "--variant=buildd",
+            "--architectures=amd64",
+            "--components=main",
+            "--keyring=/usr/share/keyrings/debian-archive-keyring.gpg",
+            "--aptopt=Acquire::Check-Valid-Until \"false\"",
+            "bookworm",
+            "/tmp/bookworm-amd64.tar.gz",
+            "http://snapshot.debian.org/archive/debian/20240601T000000Z/",
+        ]);
+    }
+
+    #[test]
+    fn test_verify_tarball_hash() {
+        let temp_dir = tempdir().unwrap();
+        let cache_file = temp_dir.path().join("bookworm-amd64.tar.gz");
+        fs::write(&cache_file, "chroot").unwrap();
+        let cache_file = cache_file.to_str().unwrap();
+        let wrong_hash = "5ab6b0d6bb1e5a4a5a2e4b9fb4b0ea0bd7b8d7e6b8f3f54f40ed0d5f5f0e5d7b";
+
+        let actual_hash = verify_tarball_hash(cache_file, &ChrootConfig::default()).unwrap();
+        let pinned = ChrootConfig { tarball_hash: Some(actual_hash.clone()), ..Default::default() };
+        assert!(verify_tarball_hash(cache_file, &pinned).is_ok());
+        let mismatch = ChrootConfig { tarball_hash: Some(wrong_hash.to_string()), ..Default::default() };
+        let err = verify_tarball_hash(cache_file, &mismatch).unwrap_err().to_string();
+        assert!(err.contains(&format!("has sha256 {}, but tarball_hash is {}", actual_hash, wrong_hash)));
+    }
+}
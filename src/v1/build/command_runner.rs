@@ -0,0 +1,336 @@
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+use std::cell::RefCell;
+use std::collections::{BTreeMap, VecDeque};
+use std::fs;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+use std::rc::Rc;
+
+/// Executes the external tools of the build.
+///
+/// Every command of the build goes through the runner of the current thread,
+/// see `set_runner`, which allows tests to run the pipeline without the tools installed.
+pub trait CommandRunner {
+    /// Runs the command and captures its output, like `Command::output`.
+    fn output(&self, cmd: &mut Command) -> io::Result<Output>;
+    /// Runs the command with inherited stdout and stderr, like `Command::status`.
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus>;
+}
+
+pub struct RealCommandRunner;
+
+impl CommandRunner for RealCommandRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        cmd.output()
+    }
+
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        cmd.status()
+    }
+}
+
+/// A single command with its result, the entries of a fixture file.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+pub struct Invocation {
+    pub program: String,
+    #[serde(default)]
+    pub args: Vec<String>,
+    #[serde(default)]
+    pub cwd: Option<String>,
+    #[serde(default)]
+    pub env: BTreeMap<String, Option<String>>,
+    #[serde(default)]
+    pub exit_code: i32,
+    #[serde(default)]
+    pub stdout: String,
+    #[serde(default)]
+    pub stderr: String,
+}
+
+impl Invocation {
+    pub fn from_command(cmd: &Command) -> Invocation {
+        Invocation {
+            program: cmd.get_program().to_string_lossy().to_string(),
+            args: cmd.get_args().map(|arg| arg.to_string_lossy().to_string()).collect(),
+            cwd: cmd.get_current_dir().map(|dir| dir.display().to_string()),
+            env: cmd.get_envs()
+                .map(|(key, value)| {
+                    (key.to_string_lossy().to_string(), value.map(|value| value.to_string_lossy().to_string()))
+                })
+                .collect(),
+            ..Default::default()
+        }
+    }
+
+    pub fn command_line(&self) -> String {
+        let mut command_line = self.program.clone();
+        for arg in self.args.iter() {
+            command_line.push(' ');
+            command_line.push_str(arg);
+        }
+        command_line
+    }
+
+    fn exit_status(&self) -> ExitStatus {
+        ExitStatus::from_raw((self.exit_code & 0xff) << 8)
+    }
+
+    /// Whether the recorded invocation, with `*` arguments matching anything, matches `actual`.
+    ///
+    /// The recorded environment variables have to be set, a `*` value to anything, other variables are ignored.
+    fn matches(&self, actual: &Invocation) -> bool {
+        self.program == actual.program
+            && self.args.len() == actual.args.len()
+            && self.args.iter().zip(actual.args.iter()).all(|(expected, actual)| expected == "*" || expected == actual)
+            && (self.cwd.is_none() || self.cwd == actual.cwd)
+            && self.env.iter().all(|(key, value)| match actual.env.get(key) {
+                Some(Some(_)) if value.as_deref() == Some("*") => true,
+                actual_value => actual_value == Some(value),
+            })
+    }
+
+    fn map_strings(&self, map: impl Fn(&str) -> String) -> Invocation {
+        Invocation {
+            program: map(&self.program),
+            args: self.args.iter().map(|arg| map(arg)).collect(),
+            cwd: self.cwd.as_deref().map(&map),
+            env: self.env.iter()
+                .map(|(key, value)| (key.clone(), value.as_deref().map(&map)))
+                .collect(),
+            ..self.clone()
+        }
+    }
+}
+
+/// Machine specific values in fixtures are written as `${NAME}`.
+#[derive(Default)]
+struct Variables(Vec<(String, String)>);
+
+impl Variables {
+    fn add(&mut self, name: &str, value: &str) {
+        self.0.push((format!("${{{}}}", name), value.to_string()));
+        // longer values first, so a value containing another one is replaced as a whole
+        self.0.sort_by_key(|(_, value)| std::cmp::Reverse(value.len()));
+    }
+
+    fn expand(&self, text: &str) -> String {
+        self.0.iter().fold(text.to_string(), |text, (name, value)| text.replace(name, value))
+    }
+
+    fn collapse(&self, text: &str) -> String {
+        self.0.iter().fold(text.to_string(), |text, (name, value)| text.replace(value, name))
+    }
+}
+
+/// Runs the commands and writes every invocation to a fixture file for `ReplayCommandRunner`.
+pub struct RecordingCommandRunner {
+    fixture_file: PathBuf,
+    variables: Variables,
+    invocations: RefCell<Vec<Invocation>>,
+}
+
+impl RecordingCommandRunner {
+    pub fn new<P: AsRef<Path>>(fixture_file: P) -> Self {
+        RecordingCommandRunner {
+            fixture_file: fixture_file.as_ref().to_path_buf(),
+            variables: Variables::default(),
+            invocations: RefCell::new(vec![]),
+        }
+    }
+
+    /// Writes `${name}` instead of `value` to the fixture.
+    pub fn with_variable(mut self, name: &str, value: &str) -> Self {
+        self.variables.add(name, value);
+        self
+    }
+
+    fn record(&self, mut invocation: Invocation, status: ExitStatus) -> io::Result<()> {
+        invocation.exit_code = status.code().unwrap_or(-1);
+        let invocation = invocation.map_strings(|text| self.variables.collapse(text));
+        let mut invocations = self.invocations.borrow_mut();
+        invocations.push(invocation);
+        // written after every command, so the fixture is complete even if the build fails later on
+        let content = serde_json::to_string_pretty(&*invocations).map_err(io::Error::other)?;
+        fs::write(&self.fixture_file, content)
+    }
+}
+
+impl CommandRunner for RecordingCommandRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        let output = cmd.output()?;
+        let mut invocation = Invocation::from_command(cmd);
+        invocation.stdout = String::from_utf8_lossy(&output.stdout).to_string();
+        invocation.stderr = String::from_utf8_lossy(&output.stderr).to_string();
+        self.record(invocation, output.status)?;
+        Ok(output)
+    }
+
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        let status = cmd.status()?;
+        self.record(Invocation::from_command(cmd), status)?;
+        Ok(status)
+    }
+}
+
+/// Answers commands from a fixture instead of running them.
+///
+/// Commands have to be called in the recorded order with the recorded arguments,
+/// a recorded argument of `*` matches any argument.
+pub struct ReplayCommandRunner {
+    invocations: RefCell<VecDeque<Invocation>>,
+}
+
+impl ReplayCommandRunner {
+    pub fn new(invocations: Vec<Invocation>) -> Self {
+        ReplayCommandRunner {
+            invocations: RefCell::new(invocations.into()),
+        }
+    }
+
+    /// Loads a fixture, `variables` replace the `${NAME}` placeholders in it.
+    pub fn from_file<P: AsRef<Path>>(fixture_file: P, variables: &[(&str, &str)]) -> io::Result<Self> {
+        let content = fs::read_to_string(fixture_file)?;
+        let invocations: Vec<Invocation> = serde_json::from_str(&content).map_err(io::Error::other)?;
+        let mut fixture_variables = Variables::default();
+        for (name, value) in variables {
+            fixture_variables.add(name, value);
+        }
+        let invocations = invocations
+            .iter()
+            .map(|invocation| invocation.map_strings(|text| fixture_variables.expand(text)))
+            .collect();
+        Ok(ReplayCommandRunner::new(invocations))
+    }
+
+    /// Commands of the fixture that were not called.
+    pub fn remaining(&self) -> Vec<Invocation> {
+        self.invocations.borrow().iter().cloned().collect()
+    }
+
+    fn next(&self, cmd: &Command) -> io::Result<Invocation> {
+        let actual = Invocation::from_command(cmd);
+        let expected = self.invocations.borrow_mut().pop_front().ok_or_else(|| {
+            io::Error::other(format!("Unexpected command, the fixture has no more commands: {}", actual.command_line()))
+        })?;
+        if !expected.matches(&actual) {
+            return Err(io::Error::other(format!(
+                "Unexpected command: {}\nexpected by the fixture: {}",
+                actual.command_line(),
+                expected.command_line()
+            )));
+        }
+        Ok(expected)
+    }
+}
+
+impl CommandRunner for ReplayCommandRunner {
+    fn output(&self, cmd: &mut Command) -> io::Result<Output> {
+        let invocation = self.next(cmd)?;
+        Ok(Output {
+            status: invocation.exit_status(),
+            stdout: invocation.stdout.into_bytes(),
+            stderr: invocation.stderr.into_bytes(),
+        })
+    }
+
+    fn status(&self, cmd: &mut Command) -> io::Result<ExitStatus> {
+        let invocation = self.next(cmd)?;
+        // logged like the rest of the crate, so tests capture the output
+        for line in invocation.stdout.lines() {
+            info!("{}", line);
+        }
+        for line in invocation.stderr.lines() {
+            warn!("{}", line);
+        }
+        Ok(invocation.exit_status())
+    }
+}
+
+thread_local! {
+    static RUNNER: RefCell<Rc<dyn CommandRunner>> = RefCell::new(Rc::new(RealCommandRunner));
+}
+
+/// Replaces the runner of the current thread.
+pub fn set_runner(runner: Rc<dyn CommandRunner>) {
+    RUNNER.with(|current| *current.borrow_mut() = runner);
+}
+
+fn current_runner() -> Rc<dyn CommandRunner> {
+    RUNNER.with(|current| current.borrow().clone())
+}
+
+pub fn output(cmd: &mut Command) -> io::Result<Output> {
+    current_runner().output(cmd)
+}
+
+pub fn status(cmd: &mut Command) -> io::Result<ExitStatus> {
+    current_runner().status(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_record_and_replay() {
+        let temp_dir = tempdir().unwrap();
+        let fixture_file = temp_dir.path().join("fixture.json");
+        let work_dir = temp_dir.path().to_str().unwrap();
+        let recorder = RecordingCommandRunner::new(&fixture_file).with_variable("WORK_DIR", work_dir);
+        let output = recorder.output(Command::new("echo").arg("hello").current_dir(work_dir)).unwrap();
+        assert_eq!(output.stdout, b"hello\n");
+        let status = recorder.status(Command::new("sh").args(["-c", "exit 3"])).unwrap();
+        assert_eq!(status.code(), Some(3));
+
+        let content = fs::read_to_string(&fixture_file).unwrap();
+        assert!(content.contains("\"cwd\": \"${WORK_DIR}\""));
+
+        let replay = ReplayCommandRunner::from_file(&fixture_file, &[("WORK_DIR", work_dir)]).unwrap();
+        let output = replay.output(Command::new("echo").arg("hello").current_dir(work_dir)).unwrap();
+        assert!(output.status.success());
+        assert_eq!(output.stdout, b"hello\n");
+        let status = replay.status(Command::new("sh").args(["-c", "exit 3"])).unwrap();
+        assert_eq!(status.code(), Some(3));
+        assert!(replay.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_replay_fails_on_unexpected_command() {
+        let replay = ReplayCommandRunner::new(vec![Invocation {
+            program: "sbuild".to_string(),
+            args: vec!["-d".to_string(), "*".to_string()],
+            ..Default::default()
+        }]);
+        let err = replay.status(Command::new("sbuild").args(["-c", "bookworm"])).unwrap_err();
+        assert!(err.to_string().contains("expected by the fixture: sbuild -d *"));
+        let err = replay.status(&mut Command::new("lintian")).unwrap_err();
+        assert!(err.to_string().contains("the fixture has no more commands"));
+    }
+
+    #[test]
+    fn test_replay_wildcard_argument() {
+        let replay = ReplayCommandRunner::new(vec![Invocation {
+            program: "sbuild".to_string(),
+            args: vec!["-d".to_string(), "*".to_string()],
+            exit_code: 1,
+            ..Default::default()
+        }]);
+        let status = replay.status(Command::new("sbuild").args(["-d", "bookworm"])).unwrap();
+        assert!(!status.success());
+    }
+
+    #[test]
+    fn test_replay_wildcard_env() {
+        let replay = ReplayCommandRunner::new(vec![Invocation {
+            program: "sbuild".to_string(),
+            env: [("SBUILD_CONFIG".to_string(), Some("*".to_string()))].into(),
+            ..Default::default()
+        }; 2]);
+        assert!(replay.status(Command::new("sbuild").env("SBUILD_CONFIG", "/tmp/sbuildrc")).is_ok());
+        assert!(replay.status(&mut Command::new("sbuild")).is_err());
+    }
+}
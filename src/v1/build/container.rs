@@ -0,0 +1,407 @@
+use crate::v1::build::dry_run;
+use crate::v1::build::env_info::{parse_dpkg_query, EnvInfo, InstalledPackage};
+use crate::v1::build::sbuild::{run_command, Sbuild};
+use crate::v1::distribution::{resolve_distribution, Vendor};
+use crate::v1::packager::BackendBuildEnv;
+use crate::v1::pkg_config::{ContainerConfig, PkgConfig};
+use crate::v1::pkg_config_verify::PkgVerifyConfig;
+use eyre::{eyre, Result};
+use log::{info, warn};
+use std::path::Path;
+use std::process::{Command, Stdio};
+
+const BUILD_DIR: &str = "/build";
+// piuparts needs to create chroots, which is not possible in an unprivileged container
+pub const PIUPARTS_UNSUPPORTED: &str = "piuparts is not supported by the container backend, set run_piuparts = false or use the sbuild backend.";
+
+/// Builds, lints and tests the package inside a podman or docker container,
+/// for hosts where sbuild's unshare mode is not available.
+pub struct Container {
+    config: PkgConfig,
+    container_config: ContainerConfig,
+    build_files_dir: String,
+    // output layout, language setup and lintian arguments are shared with sbuild
+    sbuild: Sbuild,
+}
+
+impl Container {
+    pub fn new(config: PkgConfig, build_files_dir: String) -> Container {
+        Container {
+            container_config: config.build_env.container.clone().unwrap_or_default(),
+            sbuild: Sbuild::new(config.clone(), build_files_dir.clone()),
+            config,
+            build_files_dir,
+        }
+    }
+
+    /// Fails for configs running piuparts, before anything is built.
+    pub fn check_config(config: &PkgConfig) -> Result<()> {
+        if let Some(true) = config.build_env.run_piuparts {
+            return Err(eyre!(PIUPARTS_UNSUPPORTED));
+        }
+        Ok(())
+    }
+
+    pub fn get_runtime(&self) -> &str {
+        self.container_config.runtime.binary()
+    }
+
+    pub fn get_base_image(&self) -> Result<String> {
+        let image = match &self.container_config.image {
+            Some(image) => image.clone(),
+            None => {
+                let distribution = resolve_distribution(&self.config)?;
+                let repository = match distribution.vendor {
+                    Vendor::Debian => "debian",
+                    Vendor::Ubuntu => "ubuntu",
+                };
+                format!("docker.io/library/{}:{}", repository, distribution.suite)
+            }
+        };
+        if !image.contains("@sha256:") {
+            if !self.container_config.allow_unpinned_image.unwrap_or_default() {
+                return Err(eyre!(
+                    "Container image {} is not pinned by digest, set build_env.container.image to an image@sha256:<digest> or allow_unpinned_image = true.",
+                    image
+                ));
+            }
+            warn!("Using container image {} which is not pinned by digest.", image);
+        }
+        Ok(image)
+    }
+
+    /// Name of the image with the build tools installed, created by `env create`.
+    pub fn get_image_tag(&self) -> Result<String> {
+        let distribution = resolve_distribution(&self.config)?;
+        Ok(format!("localhost/pkg-builder-{}-{}:latest", distribution.suite, self.config.build_env.arch))
+    }
+
+    pub fn get_platform(&self) -> String {
+        let platform = match self.config.build_env.arch.as_str() {
+            "i386" => "386",
+            "armhf" => "arm/v7",
+            "armel" => "arm/v5",
+            "ppc64el" => "ppc64le",
+            arch => arch,
+        };
+        format!("linux/{}", platform)
+    }
+
+    fn get_source_dir_name(&self) -> String {
+        Path::new(&self.build_files_dir)
+            .file_name()
+            .unwrap()
+            .to_str()
+            .unwrap()
+            .to_string()
+    }
+
+    fn get_containerfile(&self) -> Result<String> {
+        let content = format!(
+            "FROM {}\n\
+             ENV DEBIAN_FRONTEND=noninteractive\n\
+             RUN apt-get update \\\n    \
+             && apt-get install -y --no-install-recommends build-essential dpkg-dev fakeroot devscripts equivs lintian autopkgtest ca-certificates \\\n    \
+             && apt-get clean\n",
+            self.get_base_image()?
+        );
+        Ok(content)
+    }
+
+    /// Arguments for `<runtime> run` that mount the package directory to /build.
+    pub fn get_run_args(&self, workdir: &str, script: &str) -> Result<Vec<String>> {
+        let deb_dir = self.sbuild.get_deb_dir();
+        Ok(vec![
+            "run".to_string(),
+            "--rm".to_string(),
+            format!("--platform={}", self.get_platform()),
+            "-v".to_string(),
+            format!("{}:{}:Z", deb_dir.display(), BUILD_DIR),
+            "-w".to_string(),
+            workdir.to_string(),
+            self.get_image_tag()?,
+            "bash".to_string(),
+            "-euxc".to_string(),
+            script.to_string(),
+        ])
+    }
+
+    fn image_exists(&self) -> Result<bool> {
+        let output = dry_run::output(Command::new(self.get_runtime())
+            .args(["image", "inspect", &self.get_image_tag()?]))
+            .map_err(|err| eyre!("Failed to run {}, is it installed? {}", self.get_runtime(), err))?;
+        Ok(output.status.success())
+    }
+
+    fn run_in_container(&self, workdir: &str, script: &str) -> Result<()> {
+        if !self.image_exists()? {
+            return Err(eyre!(
+                "Container image {} does not exist, please create it with: pkg-builder env create",
+                self.get_image_tag()?
+            ));
+        }
+        let cmd_args = self.get_run_args(workdir, script)?;
+        info!(
+            "Running in container by invoking: {} {}",
+            self.get_runtime(),
+            cmd_args.join(" ")
+        );
+        let mut cmd = Command::new(self.get_runtime());
+        cmd.args(&cmd_args)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        run_command(&mut cmd)
+    }
+
+    /// Files created inside of the container are owned by root when using docker,
+    /// hand them back to the owner of the package directory, also when the build fails.
+    fn get_chown_trap(&self) -> String {
+        format!("trap 'chown -R \"$(stat -c %u:%g {0})\" {0}' EXIT", BUILD_DIR)
+    }
+
+    fn build_image(&self, extra_args: &[&str]) -> Result<()> {
+        let context_dir = dry_run::tempdir()?;
+        let containerfile = context_dir.path().join("Containerfile");
+        dry_run::write(&containerfile, self.get_containerfile()?)?;
+
+        let mut cmd_args = vec![
+            "build".to_string(),
+            format!("--platform={}", self.get_platform()),
+            "-t".to_string(),
+            self.get_image_tag()?,
+            "-f".to_string(),
+            containerfile.to_str().unwrap().to_string(),
+        ];
+        cmd_args.extend(extra_args.iter().map(|arg| arg.to_string()));
+        cmd_args.push(context_dir.path().to_str().unwrap().to_string());
+        info!(
+            "Creating container image by invoking: {} {}",
+            self.get_runtime(),
+            cmd_args.join(" ")
+        );
+        let mut cmd = Command::new(self.get_runtime());
+        cmd.args(&cmd_args)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        run_command(&mut cmd)
+            .map_err(|err| eyre!("Failed to run {}, is it installed? {}", self.get_runtime(), err))
+    }
+
+    fn get_file_name(path: &Path) -> String {
+        path.file_name().unwrap().to_str().unwrap().to_string()
+    }
+}
+
+/// Parses `image ls` output of the images created by `env create`,
+/// named localhost/pkg-builder-{suite}-{arch}.
+fn parse_image_list(output: &str) -> Vec<EnvInfo> {
+    output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            let image = fields.next()?;
+            let (suite, arch) = image
+                .strip_prefix("localhost/pkg-builder-")?
+                .split(':')
+                .next()?
+                .rsplit_once('-')?;
+            Some(EnvInfo {
+                kind: "container image".to_string(),
+                suite: suite.to_string(),
+                arch: arch.to_string(),
+                size: fields.next().unwrap_or_default().to_string(),
+                created: fields.next().unwrap_or_default().to_string(),
+                hash: fields.next().unwrap_or_default().to_string(),
+                location: image.to_string(),
+            })
+        })
+        .collect()
+}
+
+impl BackendBuildEnv for Container {
+    fn clean(&self) -> Result<()> {
+        let image_tag = self.get_image_tag()?;
+        info!("Removing container image: {}", image_tag);
+        if !self.image_exists()? {
+            return Ok(());
+        }
+        let status = dry_run::status(Command::new(self.get_runtime())
+            .args(["image", "rm", "-f", &image_tag]))?;
+        if !status.success() {
+            return Err(eyre!("Could not remove container image {}", image_tag));
+        }
+        Ok(())
+    }
+
+    fn create(&self) -> Result<()> {
+        self.build_image(&[])
+    }
+
+    fn update(&self) -> Result<()> {
+        // rebuilding from the latest base image is how container images are upgraded
+        self.build_image(&["--pull=true", "--no-cache"])
+    }
+
+    fn list(&self) -> Result<Vec<EnvInfo>> {
+        let output = dry_run::output(Command::new(self.get_runtime())
+            .args(["image", "ls", "--filter", "reference=localhost/pkg-builder-*"])
+            .arg("--format={{.Repository}}:{{.Tag}}\t{{.Size}}\t{{.CreatedAt}}\t{{.ID}}"))
+            .map_err(|err| eyre!("Failed to run {}, is it installed? {}", self.get_runtime(), err))?;
+        if !output.status.success() {
+            return Err(eyre!("Could not list container images: {}", String::from_utf8_lossy(&output.stderr)));
+        }
+        Ok(parse_image_list(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn info(&self) -> Result<Vec<InstalledPackage>> {
+        let output = dry_run::output(Command::new(self.get_runtime())
+            .args(["run", "--rm", &format!("--platform={}", self.get_platform()), &self.get_image_tag()?])
+            .args(["dpkg-query", "-W", "-f=${Package}\t${Version}\t${Architecture}\n"]))
+            .map_err(|err| eyre!("Failed to run {}, is it installed? {}", self.get_runtime(), err))?;
+        if !output.status.success() {
+            return Err(eyre!(
+                "Could not read the package list of {}, create it with: pkg-builder env create. {}",
+                self.get_image_tag()?,
+                String::from_utf8_lossy(&output.stderr)
+            ));
+        }
+        Ok(parse_dpkg_query(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn package(&self) -> Result<()> {
+        if self.config.build_env.is_cross_build() {
+            return Err(eyre!("Cross builds are only supported by the sbuild backend, build_arch differs from arch {}.", self.config.build_env.arch));
+        }
+        let mut script = vec![self.get_chown_trap()];
+        script.extend(self.sbuild.get_build_deps_not_in_debian());
+        script.push("apt-get update".to_string());
+        script.push("apt-get build-dep -y ./".to_string());
+        script.push("dpkg-buildpackage -us -uc".to_string());
+
+        let workdir = format!("{}/{}", BUILD_DIR, self.get_source_dir_name());
+        self.run_in_container(&workdir, &script.join("\n"))?;
+
+        if let Some(true) = self.config.build_env.run_lintian {
+            self.run_lintian()?;
+        };
+        if let Some(true) = self.config.build_env.run_autopkgtest {
+            self.run_autopkgtests()?;
+        }
+        Ok(())
+    }
+
+    fn verify(&self, verify_config: PkgVerifyConfig) -> Result<()> {
+        self.sbuild.verify(verify_config)
+    }
+
+    fn run_lintian(&self) -> Result<()> {
+        info!("Running lintian in container..");
+        let changes_file = Container::get_file_name(&self.sbuild.get_changes_file());
+        let cmd_args = self.sbuild.get_lintian_args(&changes_file)?;
+        self.run_in_container(BUILD_DIR, &format!("lintian {}", cmd_args.join(" ")))
+    }
+
+    fn run_piuparts(&self) -> Result<()> {
+        Err(eyre!(PIUPARTS_UNSUPPORTED))
+    }
+
+    fn run_autopkgtests(&self) -> Result<()> {
+        // the container itself is the testbed
+        info!("Running autopkgtests in container..");
+        let changes_file = Container::get_file_name(&self.sbuild.get_changes_file());
+        let mut script = self.sbuild.get_test_deps_not_in_debian();
+        script.push("apt-get update".to_string());
+        script.push(format!("autopkgtest {} --no-built-binaries -- null", changes_file));
+        self.run_in_container(BUILD_DIR, &script.join("\n"))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::ContainerRuntime;
+
+    fn container(container_config: Option<ContainerConfig>) -> Container {
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = "jammy jellyfish".to_string();
+        pkg_config.build_env.arch = "arm64".to_string();
+        pkg_config.build_env.container = container_config;
+        Container::new(pkg_config, "/tmp/packages/hello-world-1.0.0-1/hello-world-1.0.0".to_string())
+    }
+
+    #[test]
+    fn test_defaults_to_podman_and_distribution_image() {
+        let container = container(Some(ContainerConfig {
+            allow_unpinned_image: Some(true),
+            ..Default::default()
+        }));
+        assert_eq!(container.get_runtime(), "podman");
+        assert_eq!(container.get_base_image().unwrap(), "docker.io/library/ubuntu:jammy");
+        assert_eq!(container.get_image_tag().unwrap(), "localhost/pkg-builder-jammy-arm64:latest");
+        assert_eq!(container.get_platform(), "linux/arm64");
+    }
+
+    #[test]
+    fn test_uses_configured_image_and_runtime() {
+        let container = container(Some(ContainerConfig {
+            runtime: ContainerRuntime::Docker,
+            image: Some("docker.io/library/ubuntu:jammy@sha256:abc".to_string()),
+            allow_unpinned_image: None,
+        }));
+        assert_eq!(container.get_runtime(), "docker");
+        assert_eq!(container.get_base_image().unwrap(), "docker.io/library/ubuntu:jammy@sha256:abc");
+        assert!(container.get_containerfile().unwrap().starts_with("FROM docker.io/library/ubuntu:jammy@sha256:abc\n"));
+    }
+
+    #[test]
+    fn test_unpinned_image_is_error() {
+        let err = container(None).get_base_image().unwrap_err();
+        assert!(err.to_string().starts_with("Container image docker.io/library/ubuntu:jammy is not pinned by digest"), "{}", err);
+        let container = container(Some(ContainerConfig {
+            image: Some("docker.io/library/ubuntu:jammy".to_string()),
+            ..Default::default()
+        }));
+        assert!(container.get_base_image().is_err());
+    }
+
+    #[test]
+    fn test_piuparts_is_error() {
+        let err = container(None).run_piuparts().unwrap_err();
+        assert_eq!(err.to_string(), PIUPARTS_UNSUPPORTED);
+    }
+
+    #[test]
+    fn test_run_args_mount_package_dir() {
+        let container = container(None);
+        let args = container.get_run_args("/build/hello-world-1.0.0", "true").unwrap();
+        assert_eq!(args, vec![
+            "run", "--rm", "--platform=linux/arm64",
+            "-v", "/tmp/packages/hello-world-1.0.0-1:/build:Z",
+            "-w", "/build/hello-world-1.0.0",
+            "localhost/pkg-builder-jammy-arm64:latest",
+            "bash", "-euxc", "true",
+        ]);
+    }
+
+    #[test]
+    fn test_chown_runs_on_exit() {
+        let container = container(None);
+        assert_eq!(container.get_chown_trap(), "trap 'chown -R \"$(stat -c %u:%g /build)\" /build' EXIT");
+    }
+
+    #[test]
+    fn test_parse_image_list() {
+        let output = "localhost/pkg-builder-jammy-arm64:latest\t512 MB\t2024-06-01 10:00:00 +0000 UTC\t0123abcd\n\
+                      localhost/other:latest\t1 GB\t2024-06-01 10:00:00 +0000 UTC\t4567ef01\n";
+        let envs = parse_image_list(output);
+        assert_eq!(envs, vec![EnvInfo {
+            kind: "container image".to_string(),
+            suite: "jammy".to_string(),
+            arch: "arm64".to_string(),
+            size: "512 MB".to_string(),
+            created: "2024-06-01 10:00:00 +0000 UTC".to_string(),
+            hash: "0123abcd".to_string(),
+            location: "localhost/pkg-builder-jammy-arm64:latest".to_string(),
+        }]);
+    }
+}
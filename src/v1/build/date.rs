@@ -0,0 +1,46 @@
+/// Seconds since 1970-01-01 of a UTC date and time.
+pub fn civil_to_epoch(year: i64, month: i64, day: i64, hour: i64, minute: i64, second: i64) -> i64 {
+    // days from civil, see http://howardhinnant.github.io/date_algorithms.html
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let year_of_era = year - era * 400;
+    let day_of_year = (153 * ((month + 9) % 12) + 2) / 5 + day - 1;
+    let day_of_era = year_of_era * 365 + year_of_era / 4 - year_of_era / 100 + day_of_year;
+    let days = era * 146097 + day_of_era - 719468;
+    days * 86400 + hour * 3600 + minute * 60 + second
+}
+
+/// UTC date and time of seconds since 1970-01-01, as (year, month, day, hour, minute, second).
+pub fn epoch_to_civil(seconds: i64) -> (i64, i64, i64, i64, i64, i64) {
+    // civil from days, see http://howardhinnant.github.io/date_algorithms.html
+    let days = seconds.div_euclid(86400) + 719468;
+    let seconds_of_day = seconds.rem_euclid(86400);
+    let era = days.div_euclid(146097);
+    let day_of_era = days - era * 146097;
+    let year_of_era = (day_of_era - day_of_era / 1460 + day_of_era / 36524 - day_of_era / 146096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    let month_index = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * month_index + 2) / 5 + 1;
+    let month = if month_index < 10 { month_index + 3 } else { month_index - 9 };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day, seconds_of_day / 3600, seconds_of_day % 3600 / 60, seconds_of_day % 60)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_civil_to_epoch() {
+        assert_eq!(civil_to_epoch(1970, 1, 1, 0, 0, 0), 0);
+        assert_eq!(civil_to_epoch(2024, 2, 29, 12, 34, 56), 1709210096);
+        assert_eq!(civil_to_epoch(1969, 12, 31, 23, 59, 59), -1);
+    }
+
+    #[test]
+    fn test_epoch_to_civil() {
+        assert_eq!(epoch_to_civil(0), (1970, 1, 1, 0, 0, 0));
+        assert_eq!(epoch_to_civil(1709210096), (2024, 2, 29, 12, 34, 56));
+        assert_eq!(epoch_to_civil(-1), (1969, 12, 31, 23, 59, 59));
+    }
+}
@@ -7,6 +7,7 @@ use std::path::PathBuf;
 use std::process::Command;
 use tempfile::tempdir;
 use thiserror::Error;
+use crate::v1::build::dry_run::{self, PlanStep};
 
 #[derive(Debug, Error)]
 pub enum Error {
@@ -53,7 +54,7 @@ pub fn check_if_dpkg_parsechangelog_installed() -> Result<(), Error> {
 }
 
 pub fn check_if_installed() -> bool {
-    match Command::new("which").arg("debcrafter").output() {
+    match dry_run::output(Command::new("which").arg("debcrafter")) {
         Ok(output) => output.status.success(),
         Err(_) => false, // Assuming debcrafter is not installed if an error occurs
     }
@@ -107,7 +108,7 @@ pub fn install() -> Result<(), Error> {
 // }
 
 pub fn create_debian_dir(specification_file: &str, target_dir: &str) -> Result<(), Error> {
-    let debcrafter_dir = tempdir().expect("Failed to create temporary directory");
+    let debcrafter_dir = dry_run::tempdir().expect("Failed to create temporary directory");
 
     let spec_file_path = fs::canonicalize(PathBuf::from(specification_file)).map_err(|_| {
         Error::CommandFailed(format!("{} spec_file doesn't exist", specification_file).into())
@@ -129,9 +130,16 @@ pub fn create_debian_dir(specification_file: &str, target_dir: &str) -> Result<(
 
     handle_failure(&mut cmd, "Debcrafter error".to_string())?;
 
+    let dest_dir = Path::new(target_dir).join("debian");
+    if dry_run::record(PlanStep::CopyDir {
+        from: format!("{}/<generated package dir>/debian", debcrafter_dir.path().display()),
+        to: dest_dir.display().to_string(),
+    }) {
+        return Ok(());
+    }
+
     if let Some(first_directory) = get_first_directory(debcrafter_dir.path()) {
         let tmp_debian_dir = first_directory.join("debian");
-        let dest_dir = Path::new(target_dir).join("debian");
         copy_dir_contents_recursive(&tmp_debian_dir, &dest_dir)
             .map_err(|err| Error::CommandFailed(err.into()))?;
     } else {
@@ -170,8 +178,7 @@ fn copy_dir_contents_recursive(src_dir: &Path, dest_dir: &Path) -> io::Result<()
     Ok(())
 }
 fn handle_failure(cmd: &mut Command, error: String) -> Result<(), Error> {
-    let output = cmd
-        .output()
+    let output = dry_run::output(cmd)
         .map_err(|_| Error::CommandFailed(error.clone().into()))?;
 
     if !output.status.success() {
@@ -1,66 +1,84 @@
-use std::io::BufRead;
-use std::io::BufReader;
 use std::{env, fs, io};
 
 use eyre::{eyre, Result};
 
-use crate::v1::pkg_config::{SubModule};
+use crate::v1::pkg_config::{GitPackageTypeConfig, SignatureFormat, SubModule, TagSignatureConfig};
 use dirs::home_dir;
 use log::info;
 use log::warn;
-use std::io::{Write, Read};
+use std::io::Read;
 use std::os::unix::fs::PermissionsExt;
 use std::path::Path;
 use std::path::PathBuf;
 use std::process::Command;
-use filetime::FileTime;
 use sha2::{Digest, Sha256, Sha512};
+use crate::v1::build::git_cache::GitCache;
+use crate::v1::build::archive::{create_tar_gz, extract_tar, list_tar, ArchiveFormat, Compression};
+use crate::v1::build::date::civil_to_epoch;
 use crate::v1::build::debcrafter_helper;
+use crate::v1::build::download::{DownloadCache, Downloader};
+use crate::v1::build::dry_run::{self, PlanStep};
+use crate::v1::deb822::Control;
 
 pub fn create_package_dir(build_artifacts_dir: &String) -> Result<()> {
     if fs::metadata(build_artifacts_dir).is_ok() {
         info!("Remove previous package folder {}", &build_artifacts_dir);
-        fs::remove_dir_all(build_artifacts_dir)?;
+        dry_run::remove_dir_all(build_artifacts_dir)?;
     }
     info!("Creating package folder {}", &build_artifacts_dir);
-    fs::create_dir_all(build_artifacts_dir)?;
+    dry_run::create_dir_all(build_artifacts_dir)?;
     Ok(())
 }
 
-pub fn download_source(tarball_path: &str, tarball_url: &str, config_root: &str) -> Result<()> {
+/// Downloads or copies the tarball to `tarball_path`.
+///
+/// Downloads with a `tarball_hash` are kept in the download cache, so they are fetched only once.
+pub fn download_source(tarball_path: &str, tarball_url: &str, config_root: &str, tarball_hash: Option<&str>, download_cache_dir: &str) -> Result<()> {
     info!("Downloading source {}", tarball_path);
     let is_web = tarball_url.starts_with("http");
     let tarball_url = get_tarball_url(tarball_url, config_root);
     if is_web {
-        info!(
-            "Downloading tar: {} to location: {}",
-            tarball_url, tarball_path
-        );
-        let status = Command::new("wget")
-            .arg("-q")
-            .arg("-O")
-            .arg(tarball_path)
-            .arg(tarball_url)
-            .status()?;
-        if !status.success() {
-            return Err(eyre!("Download failed".to_string()));
+        let downloader = Downloader::default();
+        match tarball_hash {
+            Some(tarball_hash) => {
+                let cache = DownloadCache::new(download_cache_dir);
+                if cache.fetch(tarball_hash, Path::new(tarball_path))? {
+                    info!("Using cached download of {}", tarball_url);
+                    return Ok(());
+                }
+                info!(
+                    "Downloading tar: {} to location: {}",
+                    tarball_url, tarball_path
+                );
+                downloader.download(&tarball_url, Path::new(tarball_path), &cache.get_partial_path(tarball_hash)?)?;
+                verify_hash(tarball_path, Some(tarball_hash.to_string()))?;
+                cache.store(tarball_hash, Path::new(tarball_path))?;
+            }
+            None => {
+                info!(
+                    "Downloading tar: {} to location: {}",
+                    tarball_url, tarball_path
+                );
+                let partial_path = PathBuf::from(format!("{}.part", tarball_path));
+                downloader.download(&tarball_url, Path::new(tarball_path), &partial_path)?;
+            }
         }
     } else {
         info!("Copying tar: {} to location: {}", tarball_url, tarball_path);
-        fs::copy(tarball_url, tarball_path)?;
+        dry_run::copy(tarball_url, tarball_path)?;
     }
     Ok(())
 }
 
-pub fn update_submodules(git_submodules: &Vec<SubModule>, current_dir: &str) -> Result<()> {
+pub fn update_submodules(git_submodules: &[SubModule], current_dir: &str) -> Result<()> {
     // DO not use git2, it has very little git supported functionality
     // Initialize all submodules if they are not already initialized
     // Update submodules to specific commits
-    for submodule in git_submodules.clone() {
-        let output = Command::new("git")
+    for submodule in git_submodules.iter() {
+        let output = dry_run::output(Command::new("git")
             .current_dir(Path::new(current_dir).join(submodule.path.clone()))
-            .args(&["checkout", &submodule.commit.clone()])
-            .output()
+            .env("GIT_LFS_SKIP_SMUDGE", "1")
+            .args(["checkout", &submodule.commit]))
             .map_err(|err| eyre!(format!("Failed to checkout submodule {}", err)))?;
         if !output.status.success() {
             return Err(eyre!(
@@ -75,120 +93,217 @@ pub fn update_submodules(git_submodules: &Vec<SubModule>, current_dir: &str) ->
     Ok(())
 }
 
-pub fn clone_and_checkout_tag(git_url: &str, tag_version: &str, path: &str, git_submodules: &Vec<SubModule>) -> Result<()> {
-    match Command::new("which").arg("git-lfs").output() {
-        Ok(_) => Ok(()),
-        Err(_) => Err(eyre!("git-lfs is not installed, please install it!")),
-    }?;
+/// Submodules checked out below `path`, at any depth, with the commit they are at.
+pub fn list_submodules(path: &str) -> Result<Vec<SubModule>> {
+    let output = dry_run::output(Command::new("git")
+        .current_dir(path)
+        .args(["submodule", "status", "--recursive"]))?;
+    if !output.status.success() {
+        return Err(eyre!(
+            "Failed to list submodules: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    let mut submodules = vec![];
+    // e.g. "+72523ee3f865e09f8a6117c1b5e74cbb2df4f60e vendor/EIPs (heads/master)", the first char is the state
+    for line in String::from_utf8_lossy(&output.stdout).lines().filter(|line| !line.is_empty()) {
+        let (commit, path) = line
+            .get(1..)
+            .and_then(|line| line.split_once(' '))
+            .ok_or_else(|| eyre!("Could not parse submodule status: {}", line))?;
+        let path = match path.rfind(" (") {
+            Some(index) if path.ends_with(')') => &path[..index],
+            _ => path,
+        };
+        submodules.push(SubModule {
+            commit: commit.to_string(),
+            path: path.to_string(),
+        });
+    }
+    Ok(submodules)
+}
 
-    let output = Command::new("git")
-        .args(&["clone", "--depth", "1", "--branch", tag_version, git_url, path])
-        .output()
-        .expect("Failed to execute git clone command");
+/// Fails if a submodule below `path` is not pinned in `git_submodules`.
+pub fn check_submodules_pinned(git_submodules: &[SubModule], path: &str) -> Result<()> {
+    let unpinned: Vec<String> = list_submodules(path)?
+        .into_iter()
+        .filter(|submodule| !git_submodules.iter().any(|pinned| pinned.path == submodule.path))
+        .map(|submodule| submodule.path)
+        .collect();
+    if !unpinned.is_empty() {
+        return Err(eyre!(
+            "Submodules are not pinned in submodules, run pkg-builder git lock: {}",
+            unpinned.join(", ")
+        ));
+    }
+    Ok(())
+}
+
+/// Fails unless the tag points to `expected`, e.g. when it was force-pushed.
+pub fn verify_git_commit(path: &str, tag_version: &str, expected: &str) -> Result<()> {
+    let output = dry_run::output(Command::new("git")
+        .current_dir(path)
+        .args(["rev-parse", "HEAD"]))?;
+    if dry_run::is_enabled() {
+        return Ok(());
+    }
     if !output.status.success() {
         return Err(eyre!(
-            "Failed to checkout tag {}: {}",
+            "Failed to resolve tag {}: {}",
             tag_version,
             String::from_utf8_lossy(&output.stderr)
         ));
     }
+    let commit = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    if !commit.eq_ignore_ascii_case(expected) {
+        return Err(eyre!(
+            "Tag {} points to commit {}, but git_commit is {}. Was the tag moved?",
+            tag_version,
+            commit,
+            expected
+        ));
+    }
+    info!("Tag {} points to commit {}", tag_version, commit);
+    Ok(())
+}
 
-    // Initialize submodules
-    let output = Command::new("git")
+/// Verifies the signature of the tag against the keys in `keyring` only, not the keys of the user.
+pub fn verify_tag_signature(path: &str, tag_version: &str, signature: &TagSignatureConfig, keyring: &str) -> Result<()> {
+    let gnupg_home = dry_run::tempdir()?;
+    let mut args = vec![];
+    match signature.format {
+        SignatureFormat::Gpg => {
+            let output = dry_run::output(Command::new("gpg")
+                .env("GNUPGHOME", gnupg_home.path())
+                .args(["--batch", "--import", keyring]))?;
+            if !output.status.success() {
+                return Err(eyre!(
+                    "Failed to import keyring {}: {}",
+                    keyring,
+                    String::from_utf8_lossy(&output.stderr)
+                ));
+            }
+        }
+        SignatureFormat::Ssh => {
+            args.push("-c".to_string());
+            args.push(format!("gpg.ssh.allowedSignersFile={}", keyring));
+        }
+    }
+    args.extend(["verify-tag".to_string(), tag_version.to_string()]);
+    let output = dry_run::output(Command::new("git")
         .current_dir(path)
-        .args(&["submodule", "update", "--init", "--recursive"])
-        .output()?;
-
+        .env("GNUPGHOME", gnupg_home.path())
+        .args(&args))?;
     if !output.status.success() {
         return Err(eyre!(
-            "Failed to initialize submodules: {}",
+            "Signature verification of tag {} against {} failed: {}",
+            tag_version,
+            keyring,
             String::from_utf8_lossy(&output.stderr)
         ));
     }
+    info!("Tag {} has a valid signature", tag_version);
+    Ok(())
+}
 
-    update_submodules(git_submodules, path)?;
+/// Fails unless `git lfs` can be run.
+pub fn check_git_lfs_installed() -> Result<()> {
+    match dry_run::output(Command::new("git").args(["lfs", "version"])) {
+        Ok(output) if output.status.success() => Ok(()),
+        _ => Err(eyre!("git-lfs is not installed, please install it, it is needed for lfs = true!")),
+    }
+}
 
+/// Replaces the LFS pointer files of the checkout at `path` and of all of its submodules with their content.
+fn checkout_lfs_objects(path: &str, git_cache: &GitCache) -> Result<()> {
+    git_cache.checkout_lfs_objects(path)?;
+    for submodule in list_submodules(path)? {
+        git_cache.checkout_lfs_objects(Path::new(path).join(&submodule.path).to_str().unwrap())?;
+    }
     Ok(())
 }
 
-fn set_creation_time<P: AsRef<Path>>(dir_path: P, timestamp: FileTime) -> io::Result<()> {
-    filetime::set_file_mtime(&dir_path, timestamp)?;
-    filetime::set_file_atime(&dir_path, timestamp)?;
+pub fn clone_and_checkout_tag(config: &GitPackageTypeConfig, path: &str, config_root: &str, git_cache: &GitCache) -> Result<()> {
+    let tag_version = &config.git_tag;
+    let lfs = config.lfs.unwrap_or_default();
+    if lfs {
+        check_git_lfs_installed()?;
+    }
 
-    let mut stack = vec![PathBuf::from(dir_path.as_ref())];
+    git_cache.clone_tag(&config.git_url, tag_version, path)?;
 
-    while let Some(current) = stack.pop() {
-        for entry in fs::read_dir(&current)? {
-            let entry = entry?;
-            let file_type = entry.file_type()?;
-            let file_path = entry.path();
+    // verified before the submodules are fetched from the urls in the checked out tree
+    if let Some(git_commit) = &config.git_commit {
+        verify_git_commit(path, tag_version, git_commit)?;
+    }
+    if let Some(tag_signature) = &config.tag_signature {
+        let keyring = expand_path(&tag_signature.keyring, Some(config_root));
+        verify_tag_signature(path, tag_version, tag_signature, &keyring)?;
+    }
 
-            if file_type.is_dir() {
-                stack.push(file_path.clone()); // Push directory onto stack for processing
-                filetime::set_file_mtime(&file_path, timestamp)?;
-                filetime::set_file_atime(&file_path, timestamp)?;
-            } else if file_type.is_file() {
-                filetime::set_file_mtime(&file_path, timestamp)?;
-                filetime::set_file_atime(&file_path, timestamp)?;
-            } else if file_type.is_symlink() {
-                filetime::set_symlink_file_times(&file_path, timestamp, timestamp)?;
-            }
-        }
+    git_cache.update_submodules(path)?;
+    update_submodules(&config.submodules, path)?;
+    if config.strict_submodules.unwrap_or_default() {
+        check_submodules_pinned(&config.submodules, path)?;
+    }
+    // once the submodules are at their pinned commits, which are checked out without LFS objects as well
+    if lfs {
+        checkout_lfs_objects(path, git_cache)?;
     }
 
     Ok(())
 }
 
+/// Committer timestamp of the checked out commit.
+pub fn get_git_commit_epoch(path: &str) -> Result<u64> {
+    let output = dry_run::output(Command::new("git")
+        .current_dir(path)
+        .args(["log", "-1", "--format=%ct"]))?;
+    if dry_run::is_enabled() {
+        // nothing is cloned in a dry run
+        return Ok(0);
+    }
+    if !output.status.success() {
+        return Err(eyre!(
+            "Failed to get the commit timestamp: {}",
+            String::from_utf8_lossy(&output.stderr)
+        ));
+    }
+    let timestamp = String::from_utf8_lossy(&output.stdout).trim().to_string();
+    timestamp
+        .parse()
+        .map_err(|_| eyre!("Invalid commit timestamp: {}", timestamp))
+}
 
-pub fn download_git(build_artifacts_dir: &str, tarball_path: &str, package_name: &str, git_url: &str, tag_version: &str, git_submodules: &Vec<SubModule>) -> Result<()> {
+/// Creates the orig tarball from the tag, file mtimes are clamped to `source_date_epoch`
+/// or to the timestamp of the tagged commit.
+pub fn download_git(build_artifacts_dir: &str, tarball_path: &str, package_name: &str, config: &GitPackageTypeConfig, config_root: &str, git_cache: &GitCache, source_date_epoch: Option<u64>) -> Result<()> {
     let path = Path::new(build_artifacts_dir).join(package_name);
     if path.exists() {
-        fs::remove_dir_all(path.clone())?;
+        dry_run::remove_dir_all(path.clone())?;
     }
-    fs::create_dir_all(&path.clone())?;
-    //let path = Path::new("/tmp/nimbus");
-    clone_and_checkout_tag(git_url, tag_version, path.clone().to_str().unwrap(), &git_submodules)?;
+    dry_run::create_dir_all(&path)?;
+    clone_and_checkout_tag(config, path.clone().to_str().unwrap(), config_root, git_cache)?;
+    let source_date_epoch = match source_date_epoch {
+        Some(epoch) => epoch,
+        None => get_git_commit_epoch(path.to_str().unwrap())?,
+    };
+    info!("Using SOURCE_DATE_EPOCH {} for the orig tarball", source_date_epoch);
     // remove .git directory, no need to package it
-    fs::remove_dir_all(path.join(".git"))?;
-
-    // // Back in the path for reproducibility: January 1, 2022
-    let timestamp = FileTime::from_unix_time(1640995200, 0);
-    set_creation_time(path.clone(), timestamp)?;
+    dry_run::remove_dir_all(path.join(".git"))?;
 
     info!("Creating tar from git repo from {}", path.display());
-    let output = Command::new("tar")
-        .args(&[
-            "--sort=name",
-            "--owner=0",
-            "--group=0",
-            "--numeric-owner",
-            // does not work
-            // "--mtime='2019-01-01 00:00'",
-            "--pax-option=exthdr.name=%d/PaxHeaders/%f,delete=atime,delete=ctime",
-            "-czf", tarball_path, package_name,
-        ])
-        .current_dir(build_artifacts_dir)
-        .output()?;
-    if !output.status.success() {
-        return Err(eyre!(format!(
-            "Failed to create tarball: {}",
-            String::from_utf8_lossy(&output.stderr)
-        ))
-            .into());
-    }
+    create_tar_gz(&path, package_name, Path::new(tarball_path), source_date_epoch)
+        .map_err(|err| eyre!("Failed to create tarball: {}", err))?;
 
     Ok(())
 }
 
-pub fn create_empty_tar(build_artifacts_dir: &str, tarball_path: &str) -> Result<()> {
+pub fn create_empty_tar(tarball_path: &str) -> Result<()> {
     info!("Creating empty .tar.gz for virtual package");
-    let output = Command::new("tar")
-        .args(["czvf", tarball_path, "--files-from", "/dev/null"])
-        .current_dir(build_artifacts_dir)
-        .output()?;
-    if !output.status.success() {
-        return Err(eyre!("Virtual package .tar.gz creation failed".to_string(),));
-    }
+    let empty_dir = dry_run::tempdir()?;
+    create_tar_gz(empty_dir.path(), "", Path::new(tarball_path), 0)
+        .map_err(|err| eyre!("Virtual package .tar.gz creation failed: {}", err))?;
 
     Ok(())
 }
@@ -233,6 +348,13 @@ pub fn verify_tarball_checksum(tarball_path: &str, expected_checksum: &str) -> R
 }
 
 pub fn verify_hash(tarball_path: &str, expected_checksum: Option<String>) -> Result<()> {
+    if let Some(tarball_hash) = &expected_checksum {
+        if dry_run::record(PlanStep::Check {
+            description: format!("{} has hash {}", tarball_path, tarball_hash),
+        }) {
+            return Ok(());
+        }
+    }
     match expected_checksum {
         Some(tarball_hash) => {
             match verify_tarball_checksum(tarball_path, &tarball_hash) {
@@ -247,22 +369,15 @@ pub fn verify_hash(tarball_path: &str, expected_checksum: Option<String>) -> Res
 
 pub fn extract_source(tarball_path: &str, build_files_dir: &str) -> Result<()> {
     info!("Extracting source {}", &build_files_dir);
-    fs::create_dir_all(build_files_dir)?;
+    dry_run::create_dir_all(build_files_dir)?;
 
-    let mut args = vec!["zxvf", &tarball_path, "-C", &build_files_dir];
-    let numbers_to_strip = components_to_strip(tarball_path.to_string().clone());
-    let numbers_to_strip = numbers_to_strip.unwrap_or_default();
-    let strip = format!("--strip-components={}", numbers_to_strip);
-    if numbers_to_strip > 0 {
-        args.push(&strip);
-    }
-    info!("Stripping components: {} {:?}", numbers_to_strip, args);
-    let output = Command::new("tar").args(args).output()?;
-    if !output.status.success() {
-        let error_message = String::from_utf8(output.stderr)
-            .unwrap_or_else(|_| "Unknown error occurred during extraction".to_string());
-        return Err(eyre!(error_message));
-    }
+    let numbers_to_strip = if dry_run::is_enabled() {
+        0
+    } else {
+        components_to_strip(tarball_path.to_string())?
+    };
+    info!("Stripping components: {}", numbers_to_strip);
+    extract_tar(Path::new(tarball_path), Path::new(build_files_dir), numbers_to_strip)?;
     info!("Extracted source to build_files_dir: {:?}", build_files_dir);
 
     Ok(())
@@ -299,7 +414,7 @@ pub fn patch_quilt(build_files_dir: &String) -> Result<()> {
     );
     let debian_source_dir = PathBuf::from(&build_files_dir).join("debian/source");
     if !debian_source_dir.exists() {
-        fs::create_dir_all(&debian_source_dir)?;
+        dry_run::create_dir_all(&debian_source_dir)?;
         info!(
             "Created debian/source directory at: {:?}",
             debian_source_dir
@@ -307,7 +422,7 @@ pub fn patch_quilt(build_files_dir: &String) -> Result<()> {
     }
 
     if !Path::new(&debian_source_format_path).exists() {
-        fs::write(&debian_source_format_path, "3.0 (quilt)\n")?;
+        dry_run::write(&debian_source_format_path, "3.0 (quilt)\n")?;
         info!(
             "Quilt format file created at: {}",
             debian_source_format_path
@@ -324,44 +439,31 @@ pub fn patch_quilt(build_files_dir: &String) -> Result<()> {
 pub fn patch_pc_dir(build_files_dir: &String) -> Result<()> {
     let pc_version_path = format!("{}/.pc/.version", &build_files_dir);
     info!("Creating necessary directories for patching");
-    fs::create_dir_all(format!("{}/.pc", &build_files_dir))?;
-    let mut pc_version_file = fs::File::create(pc_version_path)?;
-    writeln!(pc_version_file, "2")?;
+    dry_run::create_dir_all(format!("{}/.pc", &build_files_dir))?;
+    dry_run::write(pc_version_path, "2\n")?;
     Ok(())
 }
 
-pub fn patch_standards_version(build_files_dir: &String, homepage: &String) -> Result<()> {
+pub fn patch_standards_version(build_files_dir: &String, homepage: &str) -> Result<()> {
     let debian_control_path = format!("{}/debian/control", build_files_dir);
     info!(
         "Adding Standards-Version to the control file. Debian control path: {}",
         debian_control_path
     );
-    let input_file = fs::File::open(&debian_control_path)?;
-    let reader = BufReader::new(input_file);
-
-    let original_content: Vec<String> = reader.lines().map(|line| line.unwrap()).collect();
-    let has_standards_version = original_content
-        .iter()
-        .any(|line| line.starts_with("Standards-Version"));
-    let standards_version_line = "Standards-Version: 4.5.1";
-    let homepage_line = format!("Homepage: {}", homepage);
-    if !has_standards_version {
-        let mut insert_index = 0;
-        for (i, line) in original_content.iter().enumerate() {
-            if line.starts_with("Priority:") {
-                insert_index = i + 1;
-                break;
-            }
-        }
-
-        let mut updated_content = original_content.clone();
-        updated_content.insert(insert_index, standards_version_line.to_string());
-        updated_content.insert(insert_index + 1, homepage_line.to_string());
-
-        let mut output_file = fs::File::create(&debian_control_path)?;
-        for line in updated_content {
-            writeln!(output_file, "{}", line)?;
+    if dry_run::record(PlanStep::WriteFile { path: debian_control_path.clone() }) {
+        return Ok(());
+    }
+    let content = fs::read_to_string(&debian_control_path)?;
+    let mut control: Control = content
+        .parse()
+        .map_err(|err| eyre!("Could not parse {}: {}", debian_control_path, err))?;
+    let source = control.source_mut();
+    if source.get("Standards-Version").is_none() {
+        source.insert_after("Priority", "Standards-Version", "4.5.1");
+        if source.get("Homepage").is_none() {
+            source.insert_after("Standards-Version", "Homepage", homepage);
         }
+        fs::write(&debian_control_path, control.to_string())?;
 
         info!("Standards-Version added to the control file.");
     } else {
@@ -370,9 +472,80 @@ pub fn patch_standards_version(build_files_dir: &String, homepage: &String) -> R
     Ok(())
 }
 
+/// Appends `version_suffix` to the version of the latest changelog entry, which dpkg-buildpackage names the packages by.
+pub fn patch_changelog_version(build_files_dir: &str, version_suffix: &str) -> Result<()> {
+    let changelog_path = format!("{}/debian/changelog", build_files_dir);
+    info!("Adding version suffix {} to the changelog: {}", version_suffix, changelog_path);
+    if dry_run::record(PlanStep::WriteFile { path: changelog_path.clone() }) {
+        return Ok(());
+    }
+    let content = fs::read_to_string(&changelog_path)?;
+    let (first_line, rest) = content.split_once('\n').unwrap_or((&content, ""));
+    let (version_start, version_end) = first_line
+        .find('(')
+        .zip(first_line.find(')'))
+        .ok_or_else(|| eyre!("Could not find the version in the first line of {}: {}", changelog_path, first_line))?;
+    let version = &first_line[version_start + 1..version_end];
+    if version.ends_with(version_suffix) {
+        info!("Changelog version {} already has the suffix. No changes made.", version);
+        return Ok(());
+    }
+    let patched = format!(
+        "{}{}{}{}\n{}",
+        &first_line[..version_start + 1],
+        version,
+        version_suffix,
+        &first_line[version_end..],
+        rest
+    );
+    fs::write(&changelog_path, patched)?;
+    Ok(())
+}
+
+/// Unix timestamp of the latest changelog entry, like `dpkg-parsechangelog -STimestamp`.
+pub fn get_changelog_epoch(build_files_dir: &str) -> Result<u64> {
+    let changelog_path = format!("{}/debian/changelog", build_files_dir);
+    let content = fs::read_to_string(&changelog_path)
+        .map_err(|err| eyre!("Could not read {}: {}", changelog_path, err))?;
+    // the trailer line of an entry: " -- Maintainer <email>  Mon, 01 Jan 2024 12:00:00 +0000"
+    let trailer = content
+        .lines()
+        .find(|line| line.starts_with(" -- "))
+        .ok_or_else(|| eyre!("Could not find the trailer line of the latest entry in {}", changelog_path))?;
+    let date = trailer.split_once(">  ").map(|(_, date)| date.trim()).unwrap_or_default();
+    parse_changelog_date(date).ok_or_else(|| eyre!("Could not parse the date of the latest entry in {}: {}", changelog_path, trailer))
+}
+
+/// Parses an RFC 2822 date, e.g. "Mon, 01 Jan 2024 12:00:00 +0100", to a unix timestamp.
+fn parse_changelog_date(date: &str) -> Option<u64> {
+    let date = date.split_once(", ").map_or(date, |(_, date)| date);
+    let [day, month, year, time, zone] = date.split_whitespace().collect::<Vec<_>>()[..] else {
+        return None;
+    };
+    let months = ["Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec"];
+    let month = months.iter().position(|name| *name == month)? as i64 + 1;
+    let day: i64 = day.parse().ok()?;
+    let year: i64 = year.parse().ok()?;
+    let time = time.split(':').map(|part| part.parse::<i64>().ok()).collect::<Option<Vec<_>>>()?;
+    let [hours, minutes, seconds] = time[..] else {
+        return None;
+    };
+    let sign = match zone.get(..1)? {
+        "+" => 1,
+        "-" => -1,
+        _ => return None,
+    };
+    let zone: i64 = zone.get(1..).filter(|zone| zone.len() == 4)?.parse().ok()?;
+    let offset = sign * (zone / 100 * 3600 + zone % 100 * 60);
+    u64::try_from(civil_to_epoch(year, month, day, hours, minutes, seconds) - offset).ok()
+}
+
 pub fn copy_src_dir(build_files_dir: &String, src_dir: &String) -> Result<()> {
     let src_dir_path = Path::new(src_dir);
     if src_dir_path.exists() {
+        if dry_run::record(PlanStep::CopyDir { from: src_dir.clone(), to: build_files_dir.clone() }) {
+            return Ok(());
+        }
         copy_directory_recursive(Path::new(src_dir), Path::new(&build_files_dir))
             .map_err(|err| eyre!(format!("Failed to copy src directory: {}", err)))?;
     }
@@ -386,17 +559,25 @@ pub fn patch_rules_permission(build_files_dir: &str) -> Result<()> {
     );
 
     let debian_rules = format!("{}/debian/rules", build_files_dir);
+    if dry_run::record(PlanStep::SetPermissions { path: debian_rules.clone(), mode: "+x".to_string() }) {
+        return Ok(());
+    }
     let mut permissions = fs::metadata(debian_rules.clone())
         .map_err(|_| eyre!("Failed to get debian/rules permission."))?.permissions();
     permissions.set_mode(permissions.mode() | 0o111);
-    fs::set_permissions(debian_rules, permissions).map_err(|_| eyre!("Failed to set debian/rules permission."))?;
+    dry_run::set_permissions(debian_rules, permissions).map_err(|_| eyre!("Failed to set debian/rules permission."))?;
     Ok(())
 }
 
-pub fn patch_source(build_files_dir: &String, homepage: &String, src_dir: &String) -> Result<()> {
+pub fn patch_source(build_files_dir: &String, homepage: &str, src_dir: &String, version_suffix: Option<&str>) -> Result<()> {
     // Patch quilt
     patch_quilt(build_files_dir)?;
 
+    // Patch distribution specific version
+    if let Some(version_suffix) = version_suffix {
+        patch_changelog_version(build_files_dir, version_suffix)?;
+    }
+
     // Patch .pc dir setup
     patch_pc_dir(build_files_dir)?;
 
@@ -412,18 +593,31 @@ pub fn patch_source(build_files_dir: &String, homepage: &String, src_dir: &Strin
     Ok(())
 }
 
-pub fn setup_sbuild() -> Result<()> {
+pub fn setup_sbuild(source_date_epoch: Option<u64>) -> Result<()> {
     let home_dir = home_dir().expect("Home dir is empty");
     let dest_path = home_dir.join(".sbuildrc");
-    let content = include_str!(".sbuildrc");
     let home_dir = home_dir.to_str().unwrap_or("/home/runner").to_string();
-    let replaced_contents = content.replace("<HOME>", &home_dir);
-    let mut file = fs::File::create(dest_path).map_err(|_| eyre!("Failed to create ~/.sbuildrc."))?;
-    file.write_all(replaced_contents.as_bytes()).map_err(|_| eyre!("Failed to write ~/.sbuildrc."))?;
+    let replaced_contents = get_sbuildrc(&home_dir, source_date_epoch);
+    dry_run::write(dest_path, replaced_contents).map_err(|_| eyre!("Failed to write ~/.sbuildrc."))?;
 
     Ok(())
 }
 
+/// sbuild filters the environment of the build, only $build_environment reaches it.
+fn get_sbuildrc(home_dir: &str, source_date_epoch: Option<u64>) -> String {
+    let content = include_str!(".sbuildrc").replace("<HOME>", home_dir);
+    match source_date_epoch {
+        Some(source_date_epoch) => {
+            info!("Using SOURCE_DATE_EPOCH {} for the build", source_date_epoch);
+            content.replace(
+                "<SOURCE_DATE_EPOCH>",
+                &format!(",\n'SOURCE_DATE_EPOCH' => '{}'", source_date_epoch),
+            )
+        }
+        None => content.replace("<SOURCE_DATE_EPOCH>", ""),
+    }
+}
+
 pub fn copy_directory_recursive(src_dir: &Path, dest_dir: &Path) -> Result<(), io::Error> {
     if !dest_dir.exists() {
         fs::create_dir_all(dest_dir)?;
@@ -446,16 +640,9 @@ pub fn copy_directory_recursive(src_dir: &Path, dest_dir: &Path) -> Result<(), i
     Ok(())
 }
 
-pub fn components_to_strip(tar_gz_file: String) -> Result<usize, io::Error> {
-    let output = Command::new("tar")
-        .arg("--list")
-        .arg("-z")
-        .arg("-f")
-        .arg(tar_gz_file)
-        .output()?;
-
-    let output_str = String::from_utf8_lossy(&output.stdout);
-    let lines: Vec<&str> = output_str.lines().filter(|l| !l.ends_with('/')).collect();
+pub fn components_to_strip(tarball_file: String) -> Result<usize> {
+    let entries = list_tar(Path::new(&tarball_file))?;
+    let lines: Vec<&str> = entries.iter().map(|entry| entry.as_str()).filter(|l| !l.ends_with('/')).collect();
 
     let common_prefix = longest_common_prefix(&lines.clone());
 
@@ -505,14 +692,28 @@ pub fn get_tarball_path(
     package_name: &str,
     version_number: &str,
     build_artifacts_dir: &str,
+    compression: Compression,
 ) -> String {
     let tarball_path = format!(
-        "{}/{}_{}.orig.tar.gz",
-        &build_artifacts_dir, &package_name, &version_number
+        "{}/{}_{}.orig.tar.{}",
+        &build_artifacts_dir, &package_name, &version_number, compression.extension()
     );
     tarball_path
 }
 
+/// Where an upstream archive dpkg-source does not accept is downloaded to, before it is repacked.
+pub fn get_upstream_tarball_path(
+    package_name: &str,
+    version_number: &str,
+    build_artifacts_dir: &str,
+    format: ArchiveFormat,
+) -> String {
+    format!(
+        "{}/{}_{}.upstream.{}",
+        &build_artifacts_dir, &package_name, &version_number, format.name()
+    )
+}
+
 pub fn get_build_files_dir(
     package_name: &str,
     version_number: &str,
@@ -560,7 +761,10 @@ mod tests {
     // use std::sync::Once;
     // use env_logger::Env;
     use tempfile::tempdir;
+    use crate::v1::build::command_runner::{self, Invocation, ReplayCommandRunner};
     use crate::v1::pkg_config::{PackageType, PkgConfig};
+    use crate::v1::build::sbuild::calculate_sha1;
+    use std::rc::Rc;
 
     // static INIT: Once = Once::new();
 
@@ -653,15 +857,18 @@ mod tests {
 
         let temp_dir = tempdir().expect("Failed to create temporary directory");
 
-        let build_artifacts_dir = String::from(temp_dir.path().to_str().unwrap());
         let tarball_name = "test_package.tar.gz";
         let tarball_path = temp_dir.path().join(tarball_name);
         let tarball_path_str = String::from(temp_dir.path().join(tarball_name).to_str().unwrap());
 
-        let result = create_empty_tar(&build_artifacts_dir, &tarball_path_str);
+        let result = create_empty_tar(&tarball_path_str);
 
         assert!(result.is_ok());
         assert!(tarball_path.exists());
+        // the hash pinned for the orig tarball in the verify configs of the virtual examples
+        let hash = calculate_sha1(File::open(&tarball_path).unwrap()).unwrap();
+        assert_eq!(hash, "d26b15dcffb3f8de4b386808d1435a75b6fc307f");
+        assert_eq!(list_tar(&tarball_path).unwrap(), Vec::<String>::new());
     }
 
     #[test]
@@ -676,10 +883,22 @@ mod tests {
         let tarball_path = temp_dir.path().join(tarball_name);
         let tarball_url = format!("{}/{}", server.base_url(), tarball_name);
 
-        let result = download_source(tarball_path.to_str().unwrap(), &tarball_url, "/examples");
+        let download_cache_dir = temp_dir.path().join("cache");
+        let download_cache_dir = download_cache_dir.to_str().unwrap();
+        let tarball_hash = "abd0b8e99f983926dbf60bdcbaef13f83ec7b31d56e68f6252ed05981b237c837044ce768038fc34b71f925e2fb19b7dee451897db512bb4a99e0e1bc96d8ab3";
 
+        let result = download_source(tarball_path.to_str().unwrap(), &tarball_url, "/examples", None, download_cache_dir);
         assert!(result.is_ok());
         assert!(tarball_path.exists());
+
+        // downloaded once, then copied from the cache
+        let result = download_source(tarball_path.to_str().unwrap(), &tarball_url, "/examples", Some(tarball_hash), download_cache_dir);
+        assert!(result.is_ok(), "{:?}", result);
+        // not served, found in the cache by its hash
+        let moved_url = format!("{}/moved.tar.gz", server.base_url());
+        let result = download_source(tarball_path.to_str().unwrap(), &moved_url, "/examples", Some(tarball_hash), download_cache_dir);
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(Path::new(download_cache_dir).join(tarball_hash).exists());
     }
 
     #[test]
@@ -781,6 +1000,38 @@ mod tests {
     }
 
 
+    #[test]
+    fn test_patch_standards_version() {
+        let build_files_dir = tempdir().unwrap();
+        fs::create_dir_all(build_files_dir.path().join("debian")).unwrap();
+        let control_file = build_files_dir.path().join("debian/control");
+        fs::write(&control_file, "Source: hello-world\nPriority: optional\nBuild-Depends: debhelper (>= 12),\n cargo\n\nPackage: hello-world\nArchitecture: any\n").unwrap();
+        let build_files_dir = build_files_dir.path().to_str().unwrap().to_string();
+
+        patch_standards_version(&build_files_dir, "https://example.com").unwrap();
+        let patched = fs::read_to_string(&control_file).unwrap();
+        assert_eq!(patched, "Source: hello-world\nPriority: optional\nStandards-Version: 4.5.1\nHomepage: https://example.com\n\
+                             Build-Depends: debhelper (>= 12),\n cargo\n\nPackage: hello-world\nArchitecture: any\n");
+        patch_standards_version(&build_files_dir, "https://example.com").unwrap();
+        assert_eq!(fs::read_to_string(&control_file).unwrap(), patched);
+    }
+
+    #[test]
+    fn test_patch_changelog_version() {
+        let build_files_dir = tempdir().unwrap();
+        fs::create_dir_all(build_files_dir.path().join("debian")).unwrap();
+        let changelog_file = build_files_dir.path().join("debian/changelog");
+        fs::write(&changelog_file, "hello-world (1.0.0-1) stable; urgency=medium\n\n  * Release\n\n \
+                                    -- Maintainer <m@example.com>  Mon, 01 Jan 2024 00:00:00 +0000\n").unwrap();
+        let build_files_dir = build_files_dir.path().to_str().unwrap();
+
+        patch_changelog_version(build_files_dir, "~jammy1").unwrap();
+        let patched = fs::read_to_string(&changelog_file).unwrap();
+        assert!(patched.starts_with("hello-world (1.0.0-1~jammy1) stable; urgency=medium\n\n  * Release\n"));
+        patch_changelog_version(build_files_dir, "~jammy1").unwrap();
+        assert_eq!(fs::read_to_string(&changelog_file).unwrap(), patched);
+    }
+
     #[test]
     fn test_verify_hash_valid_checksum_512() {
         setup();
@@ -840,7 +1091,10 @@ mod tests {
             .expect("Cannot parse file.");
         match config.package_type {
             PackageType::Git(gitconfig) => {
-                let result = clone_and_checkout_tag(url, tag_version, repo_path_str, &gitconfig.submodules);
+                assert_eq!(gitconfig.git_url, url);
+                assert_eq!(gitconfig.git_tag, tag_version);
+                let git_cache = GitCache::new(temp_dir.path().join("git").to_str().unwrap(), false);
+                let result = clone_and_checkout_tag(&gitconfig, repo_path_str, "examples/bookworm/git-package/nimbus", &git_cache);
                 assert!(result.is_ok(), "Failed to clone and checkout tag: {:?}", result);
             }
             _ => panic!("Wrong type of file."),
@@ -849,4 +1103,193 @@ mod tests {
 
         fs::remove_dir_all(temp_dir).unwrap();
     }
+
+    #[test]
+    fn test_get_changelog_epoch() {
+        let temp_dir = tempdir().unwrap();
+        let build_files_dir = temp_dir.path().to_str().unwrap();
+        fs::create_dir_all(temp_dir.path().join("debian")).unwrap();
+        fs::write(
+            temp_dir.path().join("debian/changelog"),
+            "hello (1.0.1-1) bookworm; urgency=medium\n\n  * New release.\n\n \
+             -- Jane Doe <jane@example.com>  Thu, 29 Feb 2024 18:30:00 -0130\n\n\
+             hello (1.0.0-1) bookworm; urgency=medium\n\n  * Initial release.\n\n \
+             -- Jane Doe <jane@example.com>  Mon, 01 Jan 2024 00:00:00 +0000\n",
+        ).unwrap();
+        assert_eq!(get_changelog_epoch(build_files_dir).unwrap(), 1709236800);
+
+        assert_eq!(parse_changelog_date("Thu, 01 Jan 1970 00:00:00 +0000"), Some(0));
+        assert_eq!(parse_changelog_date("Sat, 01 Jan 2022 01:00:00 +0100"), Some(1640995200));
+        assert_eq!(parse_changelog_date("Mon, 01 Jan 2024 12:00"), None);
+        assert_eq!(parse_changelog_date("Mon, 01 Foo 2024 12:00:00 +0000"), None);
+    }
+
+    #[test]
+    fn test_get_git_commit_epoch() {
+        let runner = Rc::new(ReplayCommandRunner::new(vec![Invocation {
+            program: "git".to_string(),
+            args: vec!["log".to_string(), "-1".to_string(), "--format=%ct".to_string()],
+            cwd: Some("/tmp/hello".to_string()),
+            stdout: "1709294400\n".to_string(),
+            ..Default::default()
+        }]));
+        command_runner::set_runner(runner.clone());
+        assert_eq!(get_git_commit_epoch("/tmp/hello").unwrap(), 1709294400);
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_verify_git_commit_fails_for_moved_tag() {
+        let rev_parse = |stdout: &str| Invocation {
+            program: "git".to_string(),
+            args: vec!["rev-parse".to_string(), "HEAD".to_string()],
+            cwd: Some("/tmp/hello".to_string()),
+            stdout: stdout.to_string(),
+            ..Default::default()
+        };
+        let commit = "72523ee3f865e09f8a6117c1b5e74cbb2df4f60e";
+        let runner = Rc::new(ReplayCommandRunner::new(vec![
+            rev_parse("72523ee3f865e09f8a6117c1b5e74cbb2df4f60e\n"),
+            rev_parse("ab3ff9fad45fa7e20d749d0a03a7567225f5dd4a\n"),
+        ]));
+        command_runner::set_runner(runner.clone());
+
+        assert!(verify_git_commit("/tmp/hello", "v1.0.0", commit).is_ok());
+        let err = verify_git_commit("/tmp/hello", "v1.0.0", commit).unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Tag v1.0.0 points to commit ab3ff9fad45fa7e20d749d0a03a7567225f5dd4a, \
+             but git_commit is 72523ee3f865e09f8a6117c1b5e74cbb2df4f60e. Was the tag moved?"
+        );
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_verify_tag_signature() {
+        let runner = Rc::new(ReplayCommandRunner::new(vec![
+            Invocation {
+                program: "git".to_string(),
+                args: vec![
+                    "-c".to_string(),
+                    "gpg.ssh.allowedSignersFile=/keys/allowed_signers".to_string(),
+                    "verify-tag".to_string(),
+                    "v1.0.0".to_string(),
+                ],
+                cwd: Some("/tmp/hello".to_string()),
+                ..Default::default()
+            },
+            Invocation {
+                program: "gpg".to_string(),
+                args: vec!["--batch".to_string(), "--import".to_string(), "/keys/hello.asc".to_string()],
+                ..Default::default()
+            },
+            Invocation {
+                program: "git".to_string(),
+                args: vec!["verify-tag".to_string(), "v1.0.0".to_string()],
+                cwd: Some("/tmp/hello".to_string()),
+                exit_code: 1,
+                stderr: "gpg: Can't check signature: No public key\n".to_string(),
+                ..Default::default()
+            },
+        ]));
+        command_runner::set_runner(runner.clone());
+
+        let ssh = TagSignatureConfig { format: SignatureFormat::Ssh, keyring: "allowed_signers".to_string() };
+        assert!(verify_tag_signature("/tmp/hello", "v1.0.0", &ssh, "/keys/allowed_signers").is_ok());
+        let gpg = TagSignatureConfig { format: SignatureFormat::Gpg, keyring: "hello.asc".to_string() };
+        let err = verify_tag_signature("/tmp/hello", "v1.0.0", &gpg, "/keys/hello.asc").unwrap_err();
+        assert!(err.to_string().starts_with("Signature verification of tag v1.0.0 against /keys/hello.asc failed"), "{}", err);
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_check_submodules_pinned() {
+        let status = Invocation {
+            program: "git".to_string(),
+            args: vec!["submodule".to_string(), "status".to_string(), "--recursive".to_string()],
+            cwd: Some("/tmp/hello".to_string()),
+            stdout: " 72523ee3f865e09f8a6117c1b5e74cbb2df4f60e vendor/EIPs (heads/master)\n\
+                     +ab3ff9fad45fa7e20d749d0a03a7567225f5dd4a vendor/nim-eth\n\
+                     -ab581251bcda11e3cc120cc9e9ad1ad679340949 vendor/nim-eth/vendor/nim-web3\n".to_string(),
+            ..Default::default()
+        };
+        let runner = Rc::new(ReplayCommandRunner::new(vec![status.clone(), status]));
+        command_runner::set_runner(runner.clone());
+        let mut submodules = vec![
+            SubModule { commit: "72523ee3f865e09f8a6117c1b5e74cbb2df4f60e".to_string(), path: "vendor/EIPs".to_string() },
+            SubModule { commit: "ab3ff9fad45fa7e20d749d0a03a7567225f5dd4a".to_string(), path: "vendor/nim-eth".to_string() },
+        ];
+
+        let err = check_submodules_pinned(&submodules, "/tmp/hello").unwrap_err();
+        assert_eq!(
+            err.to_string(),
+            "Submodules are not pinned in submodules, run pkg-builder git lock: vendor/nim-eth/vendor/nim-web3"
+        );
+        submodules.push(SubModule {
+            commit: "ab581251bcda11e3cc120cc9e9ad1ad679340949".to_string(),
+            path: "vendor/nim-eth/vendor/nim-web3".to_string(),
+        });
+        assert_eq!(list_submodules("/tmp/hello").unwrap(), submodules);
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_checkout_lfs_objects_of_submodules() {
+        let temp_dir = tempdir().unwrap();
+        let cache_dir = temp_dir.path().join("git");
+        let lfs_storage = format!("lfs.storage={}", cache_dir.join("lfs").display());
+        let git = |cwd: &str, args: &[&str], stdout: &str| Invocation {
+            program: "git".to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            cwd: Some(cwd.to_string()),
+            stdout: stdout.to_string(),
+            ..Default::default()
+        };
+        let mut invocations = vec![];
+        for path in ["/tmp/hello", "/tmp/hello/vendor/world"] {
+            if path == "/tmp/hello/vendor/world" {
+                invocations.push(git("/tmp/hello", &["submodule", "status", "--recursive"],
+                                     " 72523ee3f865e09f8a6117c1b5e74cbb2df4f60e vendor/world (v1.0.0)\n"));
+            }
+            invocations.push(git(path, &["-c", &lfs_storage, "lfs", "fetch", "origin", "HEAD"], ""));
+            invocations.push(git(path, &["-c", &lfs_storage, "lfs", "checkout"], ""));
+            invocations.push(git(path, &["-c", &lfs_storage, "lfs", "ls-files"], "4d7a214614 * assets/logo.png\n"));
+        }
+        let runner = Rc::new(ReplayCommandRunner::new(invocations));
+        command_runner::set_runner(runner.clone());
+
+        checkout_lfs_objects("/tmp/hello", &GitCache::new(cache_dir.to_str().unwrap(), false)).unwrap();
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_check_git_lfs_installed() {
+        let git_lfs_version = |exit_code: i32| Invocation {
+            program: "git".to_string(),
+            args: vec!["lfs".to_string(), "version".to_string()],
+            exit_code,
+            stdout: "git-lfs/3.3.0 (GitHub; linux amd64; go 1.19.8)\n".to_string(),
+            ..Default::default()
+        };
+        let runner = Rc::new(ReplayCommandRunner::new(vec![git_lfs_version(0), git_lfs_version(1)]));
+        command_runner::set_runner(runner.clone());
+
+        assert!(check_git_lfs_installed().is_ok());
+        // git exits with 1 for an unknown subcommand
+        let err = check_git_lfs_installed().unwrap_err();
+        assert!(err.to_string().starts_with("git-lfs is not installed"), "{}", err);
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_sbuildrc_sets_source_date_epoch_next_to_user_settings() {
+        let sbuildrc = get_sbuildrc("/home/runner", Some(1704067200));
+        assert!(sbuildrc.contains("$build_environment = {\n'HOME' => '/home/runner',\n'SOURCE_DATE_EPOCH' => '1704067200'\n};"), "{}", sbuildrc);
+        assert!(sbuildrc.contains("$lintian_require_success = 1;"));
+        assert!(sbuildrc.contains("$piuparts_require_success = 1;"));
+        assert!(sbuildrc.contains("$autopkgtest_require_success = 1;"));
+
+        let sbuildrc = get_sbuildrc("/home/runner", None);
+        assert!(sbuildrc.contains("$build_environment = {\n'HOME' => '/home/runner'\n};"), "{}", sbuildrc);
+    }
 }
@@ -0,0 +1,333 @@
+use crate::v1::build::dir_setup::verify_tarball_checksum;
+use crate::v1::build::dry_run::{self, PlanStep};
+use eyre::{eyre, Report, Result};
+use log::{info, warn};
+use std::fs::{self, File, OpenOptions};
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::thread;
+use std::time::{Duration, Instant};
+
+/// Downloads over HTTP, retrying failed attempts with exponential backoff.
+///
+/// The bytes received so far are kept in a partial file, so a retry or the next run
+/// continues where the previous attempt stopped if the server supports range requests.
+pub struct Downloader {
+    retries: u32,
+    backoff: Duration,
+    /// how long the server may not send anything before the attempt is retried
+    read_timeout: Duration,
+    /// the longest an attempt may take, a stalled but not silent server is retried as well
+    timeout: Duration,
+}
+
+impl Default for Downloader {
+    fn default() -> Self {
+        Downloader {
+            retries: 5,
+            backoff: Duration::from_secs(1),
+            read_timeout: Duration::from_secs(60),
+            timeout: Duration::from_secs(60 * 60),
+        }
+    }
+}
+
+enum DownloadError {
+    /// the next attempt may succeed, e.g. after a dropped connection or a 503
+    Retry(Report),
+    Fatal(Report),
+}
+
+impl Downloader {
+    pub fn download(&self, url: &str, path: &Path, partial_path: &Path) -> Result<()> {
+        if dry_run::record(PlanStep::Download { url: url.to_string(), path: path.display().to_string() }) {
+            return Ok(());
+        }
+        // the overall timeout of ureq replaces the read timeout, so it is checked while reading
+        let agent = ureq::AgentBuilder::new()
+            .timeout_connect(self.read_timeout)
+            .timeout_read(self.read_timeout)
+            .build();
+        let mut attempt = 0;
+        loop {
+            match self.try_download(&agent, url, partial_path) {
+                Ok(()) => break,
+                Err(DownloadError::Retry(err)) if attempt < self.retries => {
+                    let delay = self.backoff * 2u32.pow(attempt);
+                    attempt += 1;
+                    warn!("Download of {} failed: {}. Retrying in {:?} ({}/{})", url, err, delay, attempt, self.retries);
+                    thread::sleep(delay);
+                }
+                Err(DownloadError::Retry(err)) | Err(DownloadError::Fatal(err)) => {
+                    return Err(eyre!("Download of {} failed: {}", url, err));
+                }
+            }
+        }
+        move_file(partial_path, path)?;
+        Ok(())
+    }
+
+    fn try_download(&self, agent: &ureq::Agent, url: &str, partial_path: &Path) -> Result<(), DownloadError> {
+        let started = Instant::now();
+        let offset = fs::metadata(partial_path).map(|metadata| metadata.len()).unwrap_or_default();
+        let mut request = agent.get(url);
+        if offset > 0 {
+            request = request.set("Range", &format!("bytes={}-", offset));
+        }
+        let response = match request.call() {
+            Ok(response) => response,
+            Err(ureq::Error::Status(416, _)) => {
+                // the partial file does not fit the file on the server anymore
+                fs::remove_file(partial_path).map_err(|err| DownloadError::Fatal(err.into()))?;
+                return Err(DownloadError::Retry(eyre!("server rejected resuming at byte {}", offset)));
+            }
+            Err(ureq::Error::Status(status, _)) if status == 408 || status == 429 || status >= 500 => {
+                return Err(DownloadError::Retry(eyre!("server responded with status {}", status)));
+            }
+            Err(ureq::Error::Status(status, _)) => {
+                return Err(DownloadError::Fatal(eyre!("server responded with status {}", status)));
+            }
+            // connection errors and timeouts
+            Err(err) => return Err(DownloadError::Retry(err.into())),
+        };
+        let resumed = offset > 0 && response.status() == 206;
+        if resumed {
+            info!("Resuming download of {} at byte {}", url, offset);
+        } else if offset > 0 {
+            info!("Server does not support resuming, restarting download of {}", url);
+        }
+        let mut downloaded = if resumed { offset } else { 0 };
+        let total = response
+            .header("Content-Length")
+            .and_then(|length| length.parse::<u64>().ok())
+            .map(|length| length + downloaded);
+        let mut file = if resumed {
+            OpenOptions::new().append(true).open(partial_path)
+        } else {
+            File::create(partial_path)
+        }
+        .map_err(|err| DownloadError::Fatal(err.into()))?;
+
+        let mut progress = Progress::new(url, total);
+        let mut reader = response.into_reader();
+        let mut buffer = vec![0; 64 * 1024];
+        loop {
+            // a timed out read is an error here as well, the bytes received so far are kept
+            let read = reader.read(&mut buffer).map_err(|err| DownloadError::Retry(err.into()))?;
+            if read == 0 {
+                break;
+            }
+            file.write_all(&buffer[..read]).map_err(|err| DownloadError::Fatal(err.into()))?;
+            downloaded += read as u64;
+            progress.update(downloaded);
+            if started.elapsed() > self.timeout {
+                return Err(DownloadError::Retry(eyre!("timed out after {:?} and {} bytes", self.timeout, downloaded)));
+            }
+        }
+        if let Some(total) = total {
+            if downloaded < total {
+                return Err(DownloadError::Retry(eyre!("connection closed after {} of {} bytes", downloaded, total)));
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Logs the download progress in steps of 10%, or every 10 MB if the size is unknown.
+struct Progress<'a> {
+    url: &'a str,
+    total: Option<u64>,
+    reported: u64,
+}
+
+impl<'a> Progress<'a> {
+    fn new(url: &'a str, total: Option<u64>) -> Self {
+        Progress { url, total, reported: 0 }
+    }
+
+    fn update(&mut self, downloaded: u64) {
+        match self.total {
+            Some(total) if total > 0 => {
+                let percent = downloaded * 100 / total;
+                if percent / 10 > self.reported / 10 {
+                    self.reported = percent;
+                    info!("Downloading {}: {}% of {} bytes", self.url, percent, total);
+                }
+            }
+            _ => {
+                let step = 10 * 1024 * 1024;
+                if downloaded / step > self.reported / step {
+                    self.reported = downloaded;
+                    info!("Downloading {}: {} bytes", self.url, downloaded);
+                }
+            }
+        }
+    }
+}
+
+/// Downloaded files stored under the hash they were verified against.
+pub struct DownloadCache {
+    dir: PathBuf,
+}
+
+impl DownloadCache {
+    pub fn new(dir: &str) -> Self {
+        DownloadCache {
+            dir: PathBuf::from(shellexpand::tilde(dir).to_string()),
+        }
+    }
+
+    fn get_path(&self, hash: &str) -> PathBuf {
+        self.dir.join(hash)
+    }
+
+    /// Where the download of the file with `hash` is kept until it is complete.
+    pub fn get_partial_path(&self, hash: &str) -> Result<PathBuf> {
+        dry_run::create_dir_all(&self.dir)?;
+        Ok(self.dir.join(format!("{}.part", hash)))
+    }
+
+    /// Copies the cached file to `path`, returns false if it is not cached.
+    ///
+    /// A cached file not matching its hash is removed, so it is downloaded again.
+    pub fn fetch(&self, hash: &str, path: &Path) -> Result<bool> {
+        let cached = self.get_path(hash);
+        if !cached.exists() {
+            return Ok(false);
+        }
+        if verify_tarball_checksum(cached.to_str().unwrap(), hash).is_err() {
+            warn!("Cached download {} does not match its hash, removing it.", cached.display());
+            dry_run::remove_file(&cached)?;
+            return Ok(false);
+        }
+        dry_run::copy(&cached, path)?;
+        Ok(true)
+    }
+
+    /// Stores `path`, whose content was verified to match `hash`.
+    pub fn store(&self, hash: &str, path: &Path) -> Result<()> {
+        let cached = self.get_path(hash);
+        if dry_run::record(PlanStep::CopyFile { from: path.display().to_string(), to: cached.display().to_string() }) {
+            return Ok(());
+        }
+        fs::create_dir_all(&self.dir)?;
+        // copied next to the cached file first, so it is never seen half written
+        let partial = self.dir.join(format!("{}.tmp", hash));
+        fs::copy(path, &partial)?;
+        fs::rename(&partial, &cached)?;
+        Ok(())
+    }
+}
+
+fn move_file(from: &Path, to: &Path) -> Result<()> {
+    // the partial file can be on another filesystem in the cache dir
+    if fs::rename(from, to).is_err() {
+        fs::copy(from, to)?;
+        fs::remove_file(from)?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::build::dir_setup::calculate_sha256;
+    use httpmock::prelude::*;
+    use tempfile::tempdir;
+
+    fn downloader() -> Downloader {
+        Downloader {
+            retries: 2,
+            backoff: Duration::ZERO,
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_download_resumes_partial_file() {
+        let server = MockServer::start();
+        let mock = server.mock(|when, then| {
+            when.method(GET).path("/source.tar.gz").header("Range", "bytes=6-");
+            then.status(206).body("world");
+        });
+        let temp_dir = tempdir().unwrap();
+        let partial_path = temp_dir.path().join("source.tar.gz.part");
+        let path = temp_dir.path().join("source.tar.gz");
+        fs::write(&partial_path, "hello ").unwrap();
+
+        downloader().download(&server.url("/source.tar.gz"), &path, &partial_path).unwrap();
+        mock.assert();
+        assert_eq!(fs::read_to_string(&path).unwrap(), "hello world");
+        assert!(!partial_path.exists());
+    }
+
+    #[test]
+    fn test_download_retries_server_errors_only() {
+        let server = MockServer::start();
+        let unavailable = server.mock(|when, then| {
+            when.method(GET).path("/unavailable.tar.gz");
+            then.status(503);
+        });
+        let missing = server.mock(|when, then| {
+            when.method(GET).path("/missing.tar.gz");
+            then.status(404);
+        });
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("source.tar.gz");
+        let partial_path = temp_dir.path().join("source.tar.gz.part");
+
+        let err = downloader().download(&server.url("/unavailable.tar.gz"), &path, &partial_path).unwrap_err();
+        assert!(err.to_string().ends_with("server responded with status 503"), "{}", err);
+        unavailable.assert_hits(3);
+
+        let err = downloader().download(&server.url("/missing.tar.gz"), &path, &partial_path).unwrap_err();
+        assert!(err.to_string().ends_with("server responded with status 404"), "{}", err);
+        missing.assert_hits(1);
+    }
+
+    #[test]
+    fn test_download_retries_timeouts() {
+        let server = MockServer::start();
+        let slow = server.mock(|when, then| {
+            when.method(GET).path("/slow.tar.gz");
+            then.status(200).body("source").delay(Duration::from_millis(500));
+        });
+        let temp_dir = tempdir().unwrap();
+        let path = temp_dir.path().join("source.tar.gz");
+        let partial_path = temp_dir.path().join("source.tar.gz.part");
+        let silent = Downloader {
+            read_timeout: Duration::from_millis(100),
+            ..downloader()
+        };
+        let err = silent.download(&server.url("/slow.tar.gz"), &path, &partial_path).unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+        slow.assert_hits(3);
+
+        let stalled = Downloader {
+            timeout: Duration::ZERO,
+            ..downloader()
+        };
+        let err = stalled.download(&server.url("/slow.tar.gz"), &path, &partial_path).unwrap_err();
+        assert!(err.to_string().ends_with("timed out after 0ns and 6 bytes"), "{}", err);
+    }
+
+    #[test]
+    fn test_cache_stores_and_fetches_by_hash() {
+        let temp_dir = tempdir().unwrap();
+        let cache = DownloadCache::new(temp_dir.path().join("cache").to_str().unwrap());
+        let source = temp_dir.path().join("source.tar.gz");
+        fs::write(&source, "source").unwrap();
+        let hash = calculate_sha256("source".as_bytes()).unwrap();
+
+        let fetched = temp_dir.path().join("fetched.tar.gz");
+        assert!(!cache.fetch(&hash, &fetched).unwrap());
+        cache.store(&hash, &source).unwrap();
+        assert!(cache.fetch(&hash, &fetched).unwrap());
+        assert_eq!(fs::read_to_string(&fetched).unwrap(), "source");
+
+        // a corrupted cache entry is dropped
+        fs::write(cache.get_path(&hash), "corrupted").unwrap();
+        assert!(!cache.fetch(&hash, &fetched).unwrap());
+        assert!(!cache.get_path(&hash).exists());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::v1::build::command_runner::{self, Invocation};
+use serde::Serialize;
+use std::cell::RefCell;
+use std::collections::BTreeMap;
+use std::fmt::Write as _;
+use rand::random;
+use std::env;
+use std::fs;
+use std::io;
+use std::os::unix::process::ExitStatusExt;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Output};
+
+#[derive(Debug, Serialize, Clone, PartialEq)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum PlanStep {
+    RunCommand {
+        program: String,
+        args: Vec<String>,
+        cwd: Option<String>,
+        /// variables set for the command, `None` means the variable is removed
+        env: BTreeMap<String, Option<String>>,
+    },
+    CreateDir { path: String },
+    RemoveDir { path: String },
+    RemoveFile { path: String },
+    WriteFile { path: String },
+    CopyFile { from: String, to: String },
+    CopyDir { from: String, to: String },
+    Download { url: String, path: String },
+    Extract { archive: String, path: String },
+    SetPermissions { path: String, mode: String },
+    /// read-only check that cannot be done as the files it needs are not created
+    Check { description: String },
+}
+
+impl PlanStep {
+    pub fn from_command(cmd: &Command) -> PlanStep {
+        let invocation = Invocation::from_command(cmd);
+        PlanStep::RunCommand {
+            program: invocation.program,
+            args: invocation.args,
+            cwd: invocation.cwd,
+            env: invocation.env,
+        }
+    }
+
+    pub fn describe(&self) -> String {
+        match self {
+            PlanStep::RunCommand { program, args, cwd, env } => {
+                let mut description = String::from("run: ");
+                for (key, value) in env.iter() {
+                    match value {
+                        Some(value) => write!(description, "{}={} ", key, value).unwrap(),
+                        None => write!(description, "-u {} ", key).unwrap(),
+                    }
+                }
+                description.push_str(program);
+                for arg in args.iter() {
+                    description.push(' ');
+                    description.push_str(&quote(arg));
+                }
+                if let Some(cwd) = cwd {
+                    write!(description, "\n     in: {}", cwd).unwrap();
+                }
+                description
+            }
+            PlanStep::CreateDir { path } => format!("create directory {}", path),
+            PlanStep::RemoveDir { path } => format!("remove directory {}", path),
+            PlanStep::RemoveFile { path } => format!("remove file {}", path),
+            PlanStep::WriteFile { path } => format!("write file {}", path),
+            PlanStep::CopyFile { from, to } => format!("copy file {} to {}", from, to),
+            PlanStep::CopyDir { from, to } => format!("copy directory {} to {}", from, to),
+            PlanStep::Download { url, path } => format!("download {} to {}", url, path),
+            PlanStep::Extract { archive, path } => format!("extract {} to {}", archive, path),
+            PlanStep::SetPermissions { path, mode } => format!("set permissions of {} to {}", path, mode),
+            PlanStep::Check { description } => format!("check: {}", description),
+        }
+    }
+}
+
+fn quote(arg: &str) -> String {
+    if !arg.is_empty() && arg.chars().all(|c| c.is_ascii_alphanumeric() || "-_./=:,+@%~".contains(c)) {
+        arg.to_string()
+    } else {
+        format!("'{}'", arg.replace('\'', "'\\''"))
+    }
+}
+
+#[derive(Debug, Serialize, Clone, PartialEq, Default)]
+pub struct BuildPlan {
+    pub steps: Vec<PlanStep>,
+}
+
+impl BuildPlan {
+    pub fn to_text(&self) -> String {
+        let mut text = format!("Build plan ({} steps):\n", self.steps.len());
+        for (i, step) in self.steps.iter().enumerate() {
+            writeln!(text, "{:>3}. {}", i + 1, step.describe()).unwrap();
+        }
+        text
+    }
+
+    pub fn to_json(&self) -> String {
+        serde_json::to_string_pretty(self).expect("Build plan is always serializable")
+    }
+}
+
+thread_local! {
+    static PLAN: RefCell<Option<BuildPlan>> = const { RefCell::new(None) };
+}
+
+/// Starts recording instead of executing on the current thread.
+///
+/// Filesystem mutations and external commands of the build go through the functions below,
+/// while the dry run is active they only add a step to the plan.
+pub fn enable() {
+    PLAN.with(|plan| *plan.borrow_mut() = Some(BuildPlan::default()));
+}
+
+pub fn is_enabled() -> bool {
+    PLAN.with(|plan| plan.borrow().is_some())
+}
+
+/// Stops the dry run and returns what was recorded.
+pub fn take_plan() -> Option<BuildPlan> {
+    PLAN.with(|plan| plan.borrow_mut().take())
+}
+
+/// Records the step if a dry run is active, returns whether it was recorded.
+pub fn record(step: PlanStep) -> bool {
+    PLAN.with(|plan| match plan.borrow_mut().as_mut() {
+        Some(plan) => {
+            plan.steps.push(step);
+            true
+        }
+        None => false,
+    })
+}
+
+pub fn record_command(cmd: &Command) -> bool {
+    is_enabled() && record(PlanStep::from_command(cmd))
+}
+
+fn display<P: AsRef<Path>>(path: P) -> String {
+    path.as_ref().display().to_string()
+}
+
+/// A temporary directory, removed when it is dropped.
+#[derive(Debug)]
+pub struct TempDir {
+    path: PathBuf,
+    _dir: Option<tempfile::TempDir>,
+}
+
+impl TempDir {
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+/// Like `tempfile::tempdir`, in a dry run the directory is only named, not created.
+pub fn tempdir() -> io::Result<TempDir> {
+    if is_enabled() {
+        let path = env::temp_dir().join(format!(".tmp{}", random::<u32>()));
+        record(PlanStep::CreateDir { path: display(&path) });
+        return Ok(TempDir { path, _dir: None });
+    }
+    let dir = tempfile::tempdir()?;
+    Ok(TempDir { path: dir.path().to_path_buf(), _dir: Some(dir) })
+}
+
+pub fn create_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    if record(PlanStep::CreateDir { path: display(&path) }) {
+        return Ok(());
+    }
+    fs::create_dir_all(path)
+}
+
+pub fn remove_dir_all<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    if record(PlanStep::RemoveDir { path: display(&path) }) {
+        return Ok(());
+    }
+    fs::remove_dir_all(path)
+}
+
+pub fn remove_file<P: AsRef<Path>>(path: P) -> io::Result<()> {
+    if record(PlanStep::RemoveFile { path: display(&path) }) {
+        return Ok(());
+    }
+    fs::remove_file(path)
+}
+
+pub fn write<P: AsRef<Path>, C: AsRef<[u8]>>(path: P, contents: C) -> io::Result<()> {
+    if record(PlanStep::WriteFile { path: display(&path) }) {
+        return Ok(());
+    }
+    fs::write(path, contents)
+}
+
+pub fn copy<P: AsRef<Path>, Q: AsRef<Path>>(from: P, to: Q) -> io::Result<()> {
+    if record(PlanStep::CopyFile { from: display(&from), to: display(&to) }) {
+        return Ok(());
+    }
+    fs::copy(from, to)?;
+    Ok(())
+}
+
+pub fn set_permissions<P: AsRef<Path>>(path: P, permissions: fs::Permissions) -> io::Result<()> {
+    use std::os::unix::fs::PermissionsExt;
+    let mode = format!("{:o}", permissions.mode());
+    if record(PlanStep::SetPermissions { path: display(&path), mode }) {
+        return Ok(());
+    }
+    fs::set_permissions(path, permissions)
+}
+
+/// Like `Command::output`, in a dry run the command succeeds without output.
+pub fn output(cmd: &mut Command) -> io::Result<Output> {
+    if record_command(cmd) {
+        return Ok(Output {
+            status: ExitStatus::from_raw(0),
+            stdout: vec![],
+            stderr: vec![],
+        });
+    }
+    command_runner::output(cmd)
+}
+
+/// Like `Command::status`, in a dry run the command succeeds.
+pub fn status(cmd: &mut Command) -> io::Result<ExitStatus> {
+    if record_command(cmd) {
+        return Ok(ExitStatus::from_raw(0));
+    }
+    command_runner::status(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_nothing_is_recorded_without_dry_run() {
+        let temp_dir = tempdir().unwrap();
+        let dir = temp_dir.path().join("created");
+        create_dir_all(&dir).unwrap();
+        assert!(dir.exists());
+        assert!(take_plan().is_none());
+    }
+
+    #[test]
+    fn test_dry_run_records_instead_of_executing() {
+        let temp_dir = tempdir().unwrap();
+        let dir = temp_dir.path().join("created");
+        enable();
+        create_dir_all(&dir).unwrap();
+        let output = output(Command::new("false").arg("--flag").current_dir("/tmp").env("LANG", "C")).unwrap();
+        let plan = take_plan().unwrap();
+
+        assert!(!dir.exists());
+        assert!(output.status.success());
+        assert_eq!(plan.steps, vec![
+            PlanStep::CreateDir { path: dir.display().to_string() },
+            PlanStep::RunCommand {
+                program: "false".to_string(),
+                args: vec!["--flag".to_string()],
+                cwd: Some("/tmp".to_string()),
+                env: BTreeMap::from([("LANG".to_string(), Some("C".to_string()))]),
+            },
+        ]);
+        assert!(!is_enabled());
+    }
+
+    #[test]
+    fn test_dry_run_tempdir_is_not_created() {
+        enable();
+        let dir = super::tempdir().unwrap();
+        let plan = take_plan().unwrap();
+        assert!(!dir.path().exists());
+        assert_eq!(plan.steps, vec![PlanStep::CreateDir { path: dir.path().display().to_string() }]);
+
+        let dir = super::tempdir().unwrap();
+        assert!(dir.path().is_dir());
+    }
+
+    #[test]
+    fn test_plan_as_text_and_json() {
+        let plan = BuildPlan {
+            steps: vec![
+                PlanStep::RemoveDir { path: "/tmp/pkg".to_string() },
+                PlanStep::RunCommand {
+                    program: "sbuild".to_string(),
+                    args: vec!["-d".to_string(), "bookworm".to_string(), "--chroot-setup-commands=apt install -y curl".to_string()],
+                    cwd: Some("/tmp/pkg/src".to_string()),
+                    env: BTreeMap::new(),
+                },
+            ],
+        };
+        assert_eq!(plan.to_text(), "Build plan (2 steps):\n  \
+            1. remove directory /tmp/pkg\n  \
+            2. run: sbuild -d bookworm '--chroot-setup-commands=apt install -y curl'\n     in: /tmp/pkg/src\n");
+        let json = plan.to_json();
+        assert!(json.contains("\"action\": \"remove_dir\""));
+        assert!(json.contains("\"program\": \"sbuild\""));
+    }
+}
@@ -0,0 +1,276 @@
+use crate::v1::build::date::epoch_to_civil;
+use crate::v1::build::dry_run;
+use eyre::Result;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::Path;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+/// A build environment found in the cache, a chroot tarball, an autopkgtest image or a container image.
+#[derive(Debug, PartialEq, Clone)]
+pub struct EnvInfo {
+    pub kind: String,
+    pub suite: String,
+    pub arch: String,
+    pub size: String,
+    pub created: String,
+    pub hash: String,
+    pub location: String,
+}
+
+#[derive(Debug, PartialEq, Clone)]
+pub struct InstalledPackage {
+    pub name: String,
+    pub version: String,
+    pub arch: String,
+}
+
+/// Chroot tarballs and autopkgtest images in the cache dir, sorted by file name.
+pub fn list_cache_dir(cache_dir: &Path) -> Result<Vec<EnvInfo>> {
+    let mut envs = vec![];
+    if !cache_dir.exists() {
+        return Ok(envs);
+    }
+    let mut entries: Vec<_> = fs::read_dir(cache_dir)?.collect::<Result<_, _>>()?;
+    entries.sort_by_key(|entry| entry.file_name());
+    for entry in entries {
+        let path = entry.path();
+        let file_name = entry.file_name().to_string_lossy().to_string();
+        let (kind, suite, arch) = if let Some(name) = file_name
+            .strip_prefix("autopkgtest-")
+            .and_then(|name| name.strip_suffix(".img")) {
+            match name.rsplit_once('-') {
+                Some((suite, arch)) => ("autopkgtest image", suite.to_string(), arch.to_string()),
+                // named by codename only before images were created per arch
+                None => ("autopkgtest image", name.to_string(), "-".to_string()),
+            }
+        } else if let Some((suite, arch)) = file_name
+            .strip_suffix(".tar.gz")
+            .and_then(|name| name.rsplit_once('-')) {
+            ("chroot", suite.to_string(), arch.to_string())
+        } else {
+            continue;
+        };
+        let metadata = entry.metadata()?;
+        let created = metadata.created().or_else(|_| metadata.modified())?;
+        envs.push(EnvInfo {
+            kind: kind.to_string(),
+            suite,
+            arch,
+            size: format_size(metadata.len()),
+            created: format_time(created),
+            hash: read_hash_file(&path)?.unwrap_or_else(|| "-".to_string()),
+            location: path.display().to_string(),
+        });
+    }
+    Ok(envs)
+}
+
+fn get_hash_file(path: &Path) -> String {
+    format!("{}.sha256", path.display())
+}
+
+/// Stores the sha256 of an environment next to it, so listing does not hash gigabytes every time.
+pub fn write_hash_file(path: &Path, hash: &str) -> Result<()> {
+    dry_run::write(get_hash_file(path), format!("{}\n", hash))?;
+    Ok(())
+}
+
+/// sha256 stored by `env create` or `env update`, None if there is none or the file changed since.
+pub fn read_hash_file(path: &Path) -> Result<Option<String>> {
+    let hash_file = get_hash_file(path);
+    let modified = fs::metadata(path)?.modified()?;
+    match fs::metadata(&hash_file) {
+        Ok(hash_metadata) if hash_metadata.modified()? >= modified => {
+            Ok(Some(fs::read_to_string(&hash_file)?.trim().to_string()))
+        }
+        _ => Ok(None),
+    }
+}
+
+pub fn format_size(size: u64) -> String {
+    let units = ["B", "KB", "MB", "GB", "TB"];
+    let mut size = size as f64;
+    let mut unit = 0;
+    while size >= 1024.0 && unit < units.len() - 1 {
+        size /= 1024.0;
+        unit += 1;
+    }
+    if unit == 0 {
+        format!("{} {}", size, units[unit])
+    } else {
+        format!("{:.1} {}", size, units[unit])
+    }
+}
+
+pub fn format_time(time: SystemTime) -> String {
+    let seconds = time.duration_since(UNIX_EPOCH).map(|duration| duration.as_secs() as i64).unwrap_or_default();
+    let (year, month, day, hour, minute, second) = epoch_to_civil(seconds);
+    format!(
+        "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+        year, month, day, hour, minute, second
+    )
+}
+
+pub fn format_env_table(envs: &[EnvInfo]) -> String {
+    let rows: Vec<Vec<&str>> = envs
+        .iter()
+        .map(|env| vec![
+            env.kind.as_str(),
+            env.suite.as_str(),
+            env.arch.as_str(),
+            env.size.as_str(),
+            env.created.as_str(),
+            env.hash.as_str(),
+            env.location.as_str(),
+        ])
+        .collect();
+    format_table(&["TYPE", "SUITE", "ARCH", "SIZE", "CREATED", "SHA256", "LOCATION"], &rows)
+}
+
+/// Left aligned columns, as wide as their widest cell.
+pub fn format_table(header: &[&str], rows: &[Vec<&str>]) -> String {
+    let mut widths: Vec<usize> = header.iter().map(|column| column.len()).collect();
+    for row in rows.iter() {
+        for (width, cell) in widths.iter_mut().zip(row.iter()) {
+            *width = (*width).max(cell.len());
+        }
+    }
+    let mut table = String::new();
+    for row in std::iter::once(header).chain(rows.iter().map(|row| row.as_slice())) {
+        let line: Vec<String> = row
+            .iter()
+            .zip(widths.iter())
+            .map(|(cell, width)| format!("{:<width$}", cell, width = width))
+            .collect();
+        writeln!(table, "{}", line.join("  ").trim_end()).unwrap();
+    }
+    table
+}
+
+/// Installed packages from the content of /var/lib/dpkg/status.
+pub fn parse_dpkg_status(status: &str) -> Vec<InstalledPackage> {
+    let mut packages = vec![];
+    for paragraph in status.split("\n\n") {
+        let field = |name: &str| {
+            paragraph
+                .lines()
+                .find_map(|line| line.strip_prefix(name).and_then(|value| value.strip_prefix(':')))
+                .map(|value| value.trim().to_string())
+        };
+        if field("Status").as_deref() != Some("install ok installed") {
+            continue;
+        }
+        if let (Some(name), Some(version)) = (field("Package"), field("Version")) {
+            packages.push(InstalledPackage {
+                name,
+                version,
+                arch: field("Architecture").unwrap_or_default(),
+            });
+        }
+    }
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    packages
+}
+
+/// Installed packages from the output of `dpkg-query -W -f '${Package}\t${Version}\t${Architecture}\n'`.
+pub fn parse_dpkg_query(output: &str) -> Vec<InstalledPackage> {
+    let mut packages: Vec<InstalledPackage> = output
+        .lines()
+        .filter_map(|line| {
+            let mut fields = line.split('\t');
+            Some(InstalledPackage {
+                name: fields.next()?.to_string(),
+                version: fields.next()?.to_string(),
+                arch: fields.next().unwrap_or_default().to_string(),
+            })
+        })
+        .collect();
+    packages.sort_by(|a, b| a.name.cmp(&b.name));
+    packages
+}
+
+pub fn format_packages(packages: &[InstalledPackage]) -> String {
+    let name_width = packages.iter().map(|package| package.name.len()).max().unwrap_or_default();
+    let version_width = packages.iter().map(|package| package.version.len()).max().unwrap_or_default();
+    let mut text = format!("{} packages installed:\n", packages.len());
+    for package in packages {
+        let line = format!(
+            "{:<name_width$}  {:<version_width$}  {}",
+            package.name,
+            package.version,
+            package.arch,
+            name_width = name_width,
+            version_width = version_width
+        );
+        writeln!(text, "{}", line.trim_end()).unwrap();
+    }
+    text
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::time::Duration;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_list_cache_dir() {
+        let temp_dir = tempdir().unwrap();
+        fs::write(temp_dir.path().join("bookworm-amd64.tar.gz"), "chroot").unwrap();
+        fs::write(temp_dir.path().join("autopkgtest-noble-arm64.img"), "image").unwrap();
+        fs::write(temp_dir.path().join("notes.txt"), "not an environment").unwrap();
+
+        write_hash_file(&temp_dir.path().join("bookworm-amd64.tar.gz"), "abc").unwrap();
+
+        let envs = list_cache_dir(temp_dir.path()).unwrap();
+        assert_eq!(envs.len(), 2);
+        assert_eq!(envs[0].kind, "autopkgtest image");
+        assert_eq!(envs[0].suite, "noble");
+        assert_eq!(envs[0].arch, "arm64");
+        assert_eq!(envs[1].kind, "chroot");
+        assert_eq!(envs[1].suite, "bookworm");
+        assert_eq!(envs[1].arch, "amd64");
+        assert_eq!(envs[1].size, "6 B");
+        // only hashes stored by env create and env update are shown, the hash files are not listed themselves
+        assert_eq!(envs[0].hash, "-");
+        assert_eq!(envs[1].hash, "abc");
+        assert!(!temp_dir.path().join("autopkgtest-noble-arm64.img.sha256").exists());
+    }
+
+    #[test]
+    fn test_format_time_and_size() {
+        assert_eq!(format_time(UNIX_EPOCH + Duration::from_secs(1709210096)), "2024-02-29 12:34:56 UTC");
+        assert_eq!(format_size(512), "512 B");
+        assert_eq!(format_size(157_286_400), "150.0 MB");
+    }
+
+    #[test]
+    fn test_parse_dpkg_status() {
+        let status = "Package: zlib1g\nStatus: install ok installed\nArchitecture: amd64\nVersion: 1:1.2.13.dfsg-1\n\n\
+                      Package: bash\nStatus: install ok installed\nArchitecture: amd64\nVersion: 5.2.15-2+b2\nDescription: GNU Bourne Again SHell\n multi line\n\n\
+                      Package: removed\nStatus: deinstall ok config-files\nVersion: 1.0\n";
+        let packages = parse_dpkg_status(status);
+        assert_eq!(packages, vec![
+            InstalledPackage { name: "bash".to_string(), version: "5.2.15-2+b2".to_string(), arch: "amd64".to_string() },
+            InstalledPackage { name: "zlib1g".to_string(), version: "1:1.2.13.dfsg-1".to_string(), arch: "amd64".to_string() },
+        ]);
+        assert_eq!(format_packages(&packages), "2 packages installed:\nbash    5.2.15-2+b2      amd64\nzlib1g  1:1.2.13.dfsg-1  amd64\n");
+    }
+
+    #[test]
+    fn test_format_env_table() {
+        let env = EnvInfo {
+            kind: "chroot".to_string(),
+            suite: "bookworm".to_string(),
+            arch: "amd64".to_string(),
+            size: "150.0 MB".to_string(),
+            created: "2024-06-01 00:00:00 UTC".to_string(),
+            hash: "abc".to_string(),
+            location: "/cache/bookworm-amd64.tar.gz".to_string(),
+        };
+        assert_eq!(format_env_table(&[env]),
+            "TYPE    SUITE     ARCH   SIZE      CREATED                  SHA256  LOCATION\n\
+             chroot  bookworm  amd64  150.0 MB  2024-06-01 00:00:00 UTC  abc     /cache/bookworm-amd64.tar.gz\n");
+    }
+}
@@ -0,0 +1,285 @@
+use crate::v1::build::dir_setup::calculate_sha256;
+use crate::v1::build::dry_run;
+use eyre::{eyre, Result};
+use log::info;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Output};
+
+/// Bare mirrors of git repositories keyed by url, clones and submodule updates are served from them.
+///
+/// Offline, the mirrors are used as they are and missing ones are an error, so the network is never used.
+pub struct GitCache {
+    dir: PathBuf,
+    offline: bool,
+}
+
+impl GitCache {
+    pub fn new(dir: &str, offline: bool) -> Self {
+        GitCache {
+            dir: PathBuf::from(shellexpand::tilde(dir).to_string()),
+            offline,
+        }
+    }
+
+    fn get_mirror_path(&self, url: &str) -> Result<PathBuf> {
+        let name = url
+            .trim_end_matches('/')
+            .trim_end_matches(".git")
+            .rsplit(['/', ':'])
+            .next()
+            .unwrap_or_default();
+        let hash = calculate_sha256(url.as_bytes())?;
+        Ok(self.dir.join(format!("{}-{}.git", name, &hash[..16])))
+    }
+
+    /// Creates or updates the mirror of `url`, offline the existing mirror is used as it is.
+    pub fn update_mirror(&self, url: &str) -> Result<PathBuf> {
+        let mirror = self.get_mirror_path(url)?;
+        let mirror_str = mirror.to_str().unwrap();
+        if mirror.exists() {
+            if self.offline {
+                info!("Using mirror {} of {} offline", mirror.display(), url);
+                return Ok(mirror);
+            }
+            info!("Updating mirror {} of {}", mirror.display(), url);
+            let output = dry_run::output(Command::new("git")
+                .args(["-C", mirror_str, "remote", "update", "--prune"]))?;
+            check_output(output, &format!("Failed to update mirror of {}", url))?;
+        } else {
+            if self.offline {
+                return Err(eyre!("No mirror of {} in {}, it is created by the first build that is not offline", url, self.dir.display()));
+            }
+            info!("Creating mirror {} of {}", mirror.display(), url);
+            dry_run::create_dir_all(&self.dir)?;
+            let output = dry_run::output(Command::new("git")
+                .args(["clone", "--mirror", url, mirror_str]))?;
+            check_output(output, &format!("Failed to create mirror of {}", url))?;
+        }
+        Ok(mirror)
+    }
+
+    /// Clones `tag` of `url` from its mirror into `path`, origin is `url` so relative submodule urls resolve.
+    pub fn clone_tag(&self, url: &str, tag: &str, path: &str) -> Result<()> {
+        let mirror = self.update_mirror(url)?;
+        // LFS objects are not in the mirror, they are checked out on request by checkout_lfs_objects
+        let output = dry_run::output(Command::new("git")
+            .env("GIT_LFS_SKIP_SMUDGE", "1")
+            .args(["clone", "--branch", tag, mirror.to_str().unwrap(), path]))?;
+        check_output(output, &format!("Failed to checkout tag {}{}", tag, self.offline_hint()))?;
+        let output = dry_run::output(Command::new("git")
+            .current_dir(path)
+            .args(["remote", "set-url", "origin", url]))?;
+        check_output(output, "Failed to set the url of origin")?;
+        Ok(())
+    }
+
+    /// Checks out the submodules of the repository at `path`, at any depth, from their mirrors.
+    pub fn update_submodules(&self, path: &str) -> Result<()> {
+        // resolves the urls of .gitmodules, relative ones against origin
+        let output = dry_run::output(Command::new("git")
+            .current_dir(path)
+            .args(["submodule", "init"]))?;
+        check_output(output, "Failed to initialize submodules")?;
+        let urls = get_submodule_config(path, &["config", "--get-regexp", r"^submodule\..*\.url$"], "url")?;
+        let paths = get_submodule_config(path, &["config", "--file", ".gitmodules", "--get-regexp", r"^submodule\..*\.path$"], "path")?;
+        if urls.is_empty() {
+            return Ok(());
+        }
+
+        for (name, url) in urls.iter() {
+            let mirror = self.update_mirror(url)?;
+            let output = dry_run::output(Command::new("git")
+                .current_dir(path)
+                .args(["config", &format!("submodule.{}.url", name), mirror.to_str().unwrap()]))?;
+            check_output(output, &format!("Failed to set the url of submodule {}", name))?;
+        }
+        // the mirrors are local paths, which git does not clone submodules from by default
+        let output = dry_run::output(Command::new("git")
+            .current_dir(path)
+            .env("GIT_LFS_SKIP_SMUDGE", "1")
+            .args(["-c", "protocol.file.allow=always", "submodule", "update"]))?;
+        check_output(output, &format!("Failed to update submodules{}", self.offline_hint()))?;
+
+        for (name, url) in urls.iter() {
+            let Some((_, submodule_path)) = paths.iter().find(|(path_name, _)| path_name == name) else {
+                continue;
+            };
+            let submodule_path = Path::new(path).join(submodule_path);
+            let submodule_path = submodule_path.to_str().unwrap();
+            let output = dry_run::output(Command::new("git")
+                .current_dir(submodule_path)
+                .args(["remote", "set-url", "origin", url]))?;
+            check_output(output, &format!("Failed to set the url of origin of submodule {}", name))?;
+            self.update_submodules(submodule_path)?;
+        }
+        Ok(())
+    }
+
+    /// Replaces the LFS pointer files of the checkout at `path` with their content.
+    ///
+    /// The objects are fetched from origin into a store shared by all repositories, offline only that store is used.
+    pub fn checkout_lfs_objects(&self, path: &str) -> Result<()> {
+        let lfs_storage = format!("lfs.storage={}", self.dir.join("lfs").display());
+        if !self.offline {
+            info!("Fetching LFS objects of {}", path);
+            let output = dry_run::output(Command::new("git")
+                .current_dir(path)
+                .args(["-c", &lfs_storage, "lfs", "fetch", "origin", "HEAD"]))?;
+            check_output(output, "Failed to fetch LFS objects")?;
+        }
+        let output = dry_run::output(Command::new("git")
+            .current_dir(path)
+            .args(["-c", &lfs_storage, "lfs", "checkout"]))?;
+        check_output(output, "Failed to check out LFS objects")?;
+
+        // "<oid> * <path>" for checked out files, "<oid> - <path>" for pointer files
+        let output = dry_run::output(Command::new("git")
+            .current_dir(path)
+            .args(["-c", &lfs_storage, "lfs", "ls-files"]))?;
+        let output = check_output(output, "Failed to list LFS files")?;
+        let missing: Vec<String> = String::from_utf8_lossy(&output.stdout)
+            .lines()
+            .filter_map(|line| line.split_once(" - ").map(|(_, file)| file.to_string()))
+            .collect();
+        if !missing.is_empty() {
+            return Err(eyre!("LFS objects are missing for {}{}", missing.join(", "), self.offline_hint()));
+        }
+        Ok(())
+    }
+
+    fn offline_hint(&self) -> &str {
+        if self.offline {
+            ", the git cache may not have it as it is offline"
+        } else {
+            ""
+        }
+    }
+}
+
+/// Submodule names with the value of `submodule.<name>.<key>`, empty if none is set.
+fn get_submodule_config(path: &str, args: &[&str], key: &str) -> Result<Vec<(String, String)>> {
+    let output = dry_run::output(Command::new("git").current_dir(path).args(args))?;
+    // git config exits with 1 if nothing matches
+    if output.status.code() == Some(1) {
+        return Ok(vec![]);
+    }
+    let output = check_output(output, "Failed to read the submodule config")?;
+    let mut values = vec![];
+    for line in String::from_utf8_lossy(&output.stdout).lines().filter(|line| !line.is_empty()) {
+        let (name, value) = line
+            .split_once(' ')
+            .and_then(|(config_key, value)| {
+                let name = config_key.strip_prefix("submodule.")?.strip_suffix(&format!(".{}", key))?;
+                Some((name.to_string(), value.to_string()))
+            })
+            .ok_or_else(|| eyre!("Could not parse submodule config: {}", line))?;
+        values.push((name, value));
+    }
+    Ok(values)
+}
+
+fn check_output(output: Output, message: &str) -> Result<Output> {
+    if !output.status.success() {
+        return Err(eyre!("{}: {}", message, String::from_utf8_lossy(&output.stderr)));
+    }
+    Ok(output)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::build::command_runner::{self, Invocation, ReplayCommandRunner};
+    use std::fs;
+    use std::rc::Rc;
+    use tempfile::tempdir;
+
+    fn git(cwd: Option<&str>, args: &[&str], stdout: &str) -> Invocation {
+        Invocation {
+            program: "git".to_string(),
+            args: args.iter().map(|arg| arg.to_string()).collect(),
+            cwd: cwd.map(|cwd| cwd.to_string()),
+            stdout: stdout.to_string(),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    fn test_mirror_path_is_keyed_by_url() {
+        let cache = GitCache::new("/cache/git", false);
+        let mirror = cache.get_mirror_path("https://github.com/status-im/nimbus-eth2.git").unwrap();
+        assert!(mirror.starts_with("/cache/git"));
+        assert!(mirror.file_name().unwrap().to_str().unwrap().starts_with("nimbus-eth2-"));
+        assert_ne!(mirror, cache.get_mirror_path("https://gitlab.com/status-im/nimbus-eth2.git").unwrap());
+    }
+
+    #[test]
+    fn test_offline_uses_existing_mirrors_only() {
+        let temp_dir = tempdir().unwrap();
+        let cache = GitCache::new(temp_dir.path().to_str().unwrap(), true);
+        let url = "https://github.com/status-im/nimbus-eth2.git";
+        let err = cache.update_mirror(url).unwrap_err();
+        assert!(err.to_string().starts_with("No mirror of https://github.com/status-im/nimbus-eth2.git"), "{}", err);
+
+        // no command is run for an existing mirror
+        let runner = Rc::new(ReplayCommandRunner::new(vec![]));
+        command_runner::set_runner(runner);
+        fs::create_dir_all(cache.get_mirror_path(url).unwrap()).unwrap();
+        assert_eq!(cache.update_mirror(url).unwrap(), cache.get_mirror_path(url).unwrap());
+    }
+
+    #[test]
+    fn test_clone_tag_and_submodules_from_mirrors() {
+        let temp_dir = tempdir().unwrap();
+        let cache = GitCache::new(temp_dir.path().join("git").to_str().unwrap(), false);
+        let url = "https://github.com/eth-pkg/hello.git";
+        let submodule_url = "https://github.com/eth-pkg/world.git";
+        let mirror = cache.get_mirror_path(url).unwrap();
+        let submodule_mirror = cache.get_mirror_path(submodule_url).unwrap();
+        fs::create_dir_all(&mirror).unwrap();
+        let path = temp_dir.path().join("hello");
+        let path = path.to_str().unwrap();
+        let submodule_path = format!("{}/vendor/world", path);
+
+        let runner = Rc::new(ReplayCommandRunner::new(vec![
+            git(None, &["-C", mirror.to_str().unwrap(), "remote", "update", "--prune"], ""),
+            git(None, &["clone", "--branch", "v1.0.0", mirror.to_str().unwrap(), path], ""),
+            git(Some(path), &["remote", "set-url", "origin", url], ""),
+            git(Some(path), &["submodule", "init"], ""),
+            git(Some(path), &["config", "--get-regexp", r"^submodule\..*\.url$"], &format!("submodule.world.url {}\n", submodule_url)),
+            git(Some(path), &["config", "--file", ".gitmodules", "--get-regexp", r"^submodule\..*\.path$"], "submodule.world.path vendor/world\n"),
+            git(None, &["clone", "--mirror", submodule_url, submodule_mirror.to_str().unwrap()], ""),
+            git(Some(path), &["config", "submodule.world.url", submodule_mirror.to_str().unwrap()], ""),
+            git(Some(path), &["-c", "protocol.file.allow=always", "submodule", "update"], ""),
+            git(Some(&submodule_path), &["remote", "set-url", "origin", submodule_url], ""),
+            git(Some(&submodule_path), &["submodule", "init"], ""),
+            Invocation { exit_code: 1, ..git(Some(&submodule_path), &["config", "--get-regexp", r"^submodule\..*\.url$"], "") },
+            Invocation { exit_code: 1, ..git(Some(&submodule_path), &["config", "--file", ".gitmodules", "--get-regexp", r"^submodule\..*\.path$"], "") },
+        ]));
+        command_runner::set_runner(runner.clone());
+
+        cache.clone_tag(url, "v1.0.0", path).unwrap();
+        cache.update_submodules(path).unwrap();
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_checkout_lfs_objects_fails_for_missing_objects() {
+        let temp_dir = tempdir().unwrap();
+        let cache_dir = temp_dir.path().join("git");
+        let lfs_storage = format!("lfs.storage={}", cache_dir.join("lfs").display());
+        let ls_files = |stdout: &str| git(Some("/tmp/hello"), &["-c", &lfs_storage, "lfs", "ls-files"], stdout);
+        let runner = Rc::new(ReplayCommandRunner::new(vec![
+            git(Some("/tmp/hello"), &["-c", &lfs_storage, "lfs", "fetch", "origin", "HEAD"], ""),
+            git(Some("/tmp/hello"), &["-c", &lfs_storage, "lfs", "checkout"], ""),
+            ls_files("4d7a214614 * assets/logo.png\n"),
+            git(Some("/tmp/hello"), &["-c", &lfs_storage, "lfs", "checkout"], ""),
+            ls_files("4d7a214614 * assets/logo.png\n8f434346648 - assets/intro.mp4\n"),
+        ]));
+        command_runner::set_runner(runner.clone());
+
+        GitCache::new(cache_dir.to_str().unwrap(), false).checkout_lfs_objects("/tmp/hello").unwrap();
+        let err = GitCache::new(cache_dir.to_str().unwrap(), true).checkout_lfs_objects("/tmp/hello").unwrap_err();
+        assert_eq!(err.to_string(), "LFS objects are missing for assets/intro.mp4, the git cache may not have it as it is offline");
+        assert!(runner.remaining().is_empty());
+    }
+}
@@ -0,0 +1,203 @@
+use crate::v1::build::dry_run;
+use crate::v1::build::env_info::{parse_dpkg_query, EnvInfo, InstalledPackage};
+use crate::v1::build::sbuild::{run_command, Sbuild};
+use crate::v1::packager::BackendBuildEnv;
+use crate::v1::pkg_config::PkgConfig;
+use crate::v1::pkg_config_verify::PkgVerifyConfig;
+use eyre::{eyre, Result};
+use log::{info, warn};
+use std::process::{Command, Stdio};
+
+/// Builds the package directly on the host with dpkg-buildpackage, without a chroot.
+///
+/// Meant for quick iteration on debian/rules, the result is not reproducible
+/// as it depends on what is installed on the host.
+pub struct Host {
+    config: PkgConfig,
+    build_files_dir: String,
+    // output layout, lintian, piuparts and autopkgtest are shared with sbuild
+    sbuild: Sbuild,
+}
+
+impl Host {
+    pub fn new(config: PkgConfig, build_files_dir: String) -> Host {
+        Host {
+            sbuild: Sbuild::new(config.clone(), build_files_dir.clone()),
+            config,
+            build_files_dir,
+        }
+    }
+
+    /// Fails with the list of missing build dependencies reported by dpkg-checkbuilddeps.
+    pub fn check_build_deps(&self) -> Result<()> {
+        info!("Checking build dependencies on the host..");
+        let output = dry_run::output(Command::new("dpkg-checkbuilddeps")
+            .current_dir(&self.build_files_dir))
+            .map_err(|err| eyre!("Failed to run dpkg-checkbuilddeps, is dpkg-dev installed? {}", err))?;
+        if output.status.success() {
+            return Ok(());
+        }
+        let stderr = String::from_utf8_lossy(&output.stderr);
+        match get_unmet_build_deps(&stderr) {
+            Some(unmet_deps) => Err(eyre!(
+                "Missing build dependencies on the host: {}. Install them with: sudo apt-get build-dep {}",
+                unmet_deps,
+                self.build_files_dir
+            )),
+            None => Err(eyre!("dpkg-checkbuilddeps failed: {}", stderr.trim())),
+        }
+    }
+
+    pub fn get_build_args(&self) -> Vec<String> {
+        // unsigned, signing is left to the release process as with sbuild
+        vec!["-us".to_string(), "-uc".to_string()]
+    }
+}
+
+fn get_unmet_build_deps(stderr: &str) -> Option<String> {
+    stderr
+        .lines()
+        .find_map(|line| line.split_once("Unmet build dependencies: "))
+        .map(|(_, unmet_deps)| unmet_deps.trim().to_string())
+}
+
+impl BackendBuildEnv for Host {
+    fn clean(&self) -> Result<()> {
+        info!("Host backend builds without a build environment, nothing to clean.");
+        Ok(())
+    }
+
+    fn create(&self) -> Result<()> {
+        info!("Host backend builds without a build environment, nothing to create.");
+        Ok(())
+    }
+
+    fn update(&self) -> Result<()> {
+        Err(eyre!("Host backend builds with the packages installed on the host, upgrade them with apt."))
+    }
+
+    fn list(&self) -> Result<Vec<EnvInfo>> {
+        info!("Host backend builds without a build environment, nothing to list.");
+        Ok(vec![])
+    }
+
+    fn info(&self) -> Result<Vec<InstalledPackage>> {
+        let output = dry_run::output(Command::new("dpkg-query")
+            .args(["-W", "-f=${Package}\t${Version}\t${Architecture}\n"]))?;
+        if !output.status.success() {
+            return Err(eyre!("dpkg-query failed: {}", String::from_utf8_lossy(&output.stderr)));
+        }
+        Ok(parse_dpkg_query(&String::from_utf8_lossy(&output.stdout)))
+    }
+
+    fn package(&self) -> Result<()> {
+        if self.config.build_env.is_cross_build() {
+            return Err(eyre!("Cross builds are only supported by the sbuild backend, build_arch differs from arch {}.", self.config.build_env.arch));
+        }
+        if !self.sbuild.get_build_deps_not_in_debian().is_empty() {
+            warn!("Language toolchain is not installed by the host backend, it has to be available on the host already.");
+        }
+        self.check_build_deps()?;
+
+        let cmd_args = self.get_build_args();
+        info!(
+            "Building package by invoking: dpkg-buildpackage {}",
+            cmd_args.join(" ")
+        );
+        let mut cmd = Command::new("dpkg-buildpackage");
+        cmd.current_dir(&self.build_files_dir)
+            .args(&cmd_args)
+            .stdout(Stdio::inherit())
+            .stderr(Stdio::inherit());
+        run_command(&mut cmd)?;
+
+        if let Some(true) = self.config.build_env.run_lintian {
+            self.run_lintian()?;
+        };
+        if let Some(true) = self.config.build_env.run_piuparts {
+            self.run_piuparts()?;
+        };
+        if let Some(true) = self.config.build_env.run_autopkgtest {
+            self.run_autopkgtests()?;
+        }
+        Ok(())
+    }
+
+    fn verify(&self, verify_config: PkgVerifyConfig) -> Result<()> {
+        self.sbuild.verify(verify_config)
+    }
+
+    fn run_lintian(&self) -> Result<()> {
+        self.sbuild.run_lintian()
+    }
+
+    fn run_piuparts(&self) -> Result<()> {
+        self.sbuild.run_piuparts()
+    }
+
+    fn run_autopkgtests(&self) -> Result<()> {
+        self.sbuild.run_autopkgtests()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::build::command_runner::{self, Invocation, ReplayCommandRunner};
+    use std::rc::Rc;
+
+    fn host() -> Host {
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.package_fields.package_name = "hello-world".to_string();
+        pkg_config.package_fields.version_number = "1.0.0".to_string();
+        pkg_config.package_fields.revision_number = "1".to_string();
+        pkg_config.build_env.codename = "bookworm".to_string();
+        pkg_config.build_env.arch = "amd64".to_string();
+        Host::new(pkg_config, "/tmp/packages/hello-world-1.0.0-1/hello-world-1.0.0".to_string())
+    }
+
+    #[test]
+    fn test_package_checks_build_deps_and_builds() {
+        let runner = Rc::new(ReplayCommandRunner::new(vec![
+            Invocation {
+                program: "dpkg-checkbuilddeps".to_string(),
+                cwd: Some("/tmp/packages/hello-world-1.0.0-1/hello-world-1.0.0".to_string()),
+                ..Default::default()
+            },
+            Invocation {
+                program: "dpkg-buildpackage".to_string(),
+                args: vec!["-us".to_string(), "-uc".to_string()],
+                cwd: Some("/tmp/packages/hello-world-1.0.0-1/hello-world-1.0.0".to_string()),
+                ..Default::default()
+            },
+        ]));
+        command_runner::set_runner(runner.clone());
+        let result = host().package();
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_missing_build_deps_are_reported() {
+        let runner = Rc::new(ReplayCommandRunner::new(vec![Invocation {
+            program: "dpkg-checkbuilddeps".to_string(),
+            exit_code: 1,
+            stderr: "dpkg-checkbuilddeps: error: Unmet build dependencies: debhelper-compat (= 13) cargo\n".to_string(),
+            ..Default::default()
+        }]));
+        command_runner::set_runner(runner.clone());
+        let err = host().package().unwrap_err().to_string();
+        assert!(err.starts_with("Missing build dependencies on the host: debhelper-compat (= 13) cargo."));
+        // the build is not started
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_output_layout_is_shared_with_sbuild() {
+        let host = host();
+        assert_eq!(
+            host.sbuild.get_changes_file().to_str().unwrap(),
+            "/tmp/packages/hello-world-1.0.0-1/hello-world_1.0.0-1_amd64.changes"
+        );
+    }
+}
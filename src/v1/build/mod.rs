@@ -1,5 +1,19 @@
+pub mod archive;
+pub mod autopkgtest_image;
+pub mod autopkgtest_virt;
+pub mod backend_packager;
 pub mod sbuild;
 pub mod sbuild_packager;
+pub mod container;
+pub mod host;
+pub mod chroot;
+pub mod date;
 pub mod dir_setup;
+pub mod download;
+pub mod env_info;
+pub mod git_cache;
 pub mod debcrafter_helper;
-
+pub mod dry_run;
+pub mod command_runner;
+pub mod piuparts;
+pub mod privilege;
@@ -0,0 +1,136 @@
+use crate::v1::build::dry_run::{self, TempDir};
+use crate::v1::pkg_config::PiupartsConfig;
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+/// The previous version of the package, which piuparts installs and then upgrades to the new .deb.
+///
+/// piuparts runs its upgrade test whenever the package is available from an apt source,
+/// so the previous version is added as an extra repository.
+pub struct UpgradeBaseline {
+    args: Vec<String>,
+    // local .deb files are served from a temporary repository for the duration of the run
+    _repo_dir: Option<TempDir>,
+}
+
+impl UpgradeBaseline {
+    pub fn new(config: &PiupartsConfig, package_name: &str) -> Result<Option<UpgradeBaseline>> {
+        if let Some(repo) = &config.upgrade_from_repo {
+            info!("Testing upgrade from the previous version in {}", repo);
+            return Ok(Some(UpgradeBaseline {
+                args: vec![format!("--extra-repo={}", repo)],
+                _repo_dir: None,
+            }));
+        }
+        let Some(upgrade_from_dir) = &config.upgrade_from_dir else {
+            return Ok(None);
+        };
+        let upgrade_from_dir = PathBuf::from(shellexpand::tilde(upgrade_from_dir).to_string());
+        let debs = find_debs(&upgrade_from_dir, package_name)?;
+        info!("Testing upgrade from the previous version in {}", upgrade_from_dir.display());
+
+        let repo_dir = dry_run::tempdir()?;
+        for deb in debs.iter() {
+            dry_run::copy(deb, repo_dir.path().join(deb.file_name().unwrap()))?;
+        }
+        let output = dry_run::output(Command::new("dpkg-scanpackages")
+            .args(["--multiversion", "."])
+            .current_dir(repo_dir.path()))
+            .map_err(|err| eyre!("Failed to run dpkg-scanpackages, is dpkg-dev installed? {}", err))?;
+        if !output.status.success() {
+            return Err(eyre!("dpkg-scanpackages failed: {}", String::from_utf8_lossy(&output.stderr)));
+        }
+        dry_run::write(repo_dir.path().join("Packages"), output.stdout)?;
+
+        let repo_path = repo_dir.path().display().to_string();
+        Ok(Some(UpgradeBaseline {
+            args: vec![
+                format!("--bindmount={}", repo_path),
+                format!("--extra-repo=deb [trusted=yes] file://{} ./", repo_path),
+            ],
+            _repo_dir: Some(repo_dir),
+        }))
+    }
+
+    pub fn get_args(&self) -> &[String] {
+        &self.args
+    }
+}
+
+/// The .deb files in `dir`, fails if none of them is a version of `package_name`,
+/// as piuparts would silently skip the upgrade test.
+fn find_debs(dir: &Path, package_name: &str) -> Result<Vec<PathBuf>> {
+    let entries = fs::read_dir(dir)
+        .map_err(|err| eyre!("Could not read upgrade_from_dir {}: {}", dir.display(), err))?;
+    let mut debs = vec![];
+    for entry in entries {
+        let path = entry?.path();
+        if path.extension().is_some_and(|extension| extension == "deb") {
+            debs.push(path);
+        }
+    }
+    debs.sort();
+    let prefix = format!("{}_", package_name);
+    let has_package = debs
+        .iter()
+        .any(|deb| deb.file_name().unwrap().to_string_lossy().starts_with(&prefix));
+    if !has_package {
+        return Err(eyre!(
+            "upgrade_from_dir {} does not contain a .deb of {}, nothing to upgrade from",
+            dir.display(),
+            package_name
+        ));
+    }
+    Ok(debs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use tempfile::tempdir;
+    use crate::v1::build::command_runner::{self, Invocation, ReplayCommandRunner};
+    use std::rc::Rc;
+
+    #[test]
+    fn test_upgrade_from_repo() {
+        let config = PiupartsConfig {
+            upgrade_from_repo: Some("deb [trusted=yes] https://repo.example.com/debian bookworm main".to_string()),
+            ..Default::default()
+        };
+        let baseline = UpgradeBaseline::new(&config, "hello-world").unwrap().unwrap();
+        assert_eq!(baseline.get_args(), ["--extra-repo=deb [trusted=yes] https://repo.example.com/debian bookworm main"]);
+        assert!(UpgradeBaseline::new(&PiupartsConfig::default(), "hello-world").unwrap().is_none());
+    }
+
+    #[test]
+    fn test_upgrade_from_dir() {
+        let upgrade_from_dir = tempdir().unwrap();
+        fs::write(upgrade_from_dir.path().join("hello-world_0.9.0-1_amd64.deb"), "deb").unwrap();
+        fs::write(upgrade_from_dir.path().join("hello-world_0.9.0-1_amd64.changes"), "changes").unwrap();
+        let config = PiupartsConfig {
+            upgrade_from_dir: Some(upgrade_from_dir.path().to_str().unwrap().to_string()),
+            ..Default::default()
+        };
+        let runner = Rc::new(ReplayCommandRunner::new(vec![Invocation {
+            program: "dpkg-scanpackages".to_string(),
+            args: vec!["--multiversion".to_string(), ".".to_string()],
+            stdout: "Package: hello-world\nVersion: 0.9.0-1\nFilename: ./hello-world_0.9.0-1_amd64.deb\n".to_string(),
+            ..Default::default()
+        }]));
+        command_runner::set_runner(runner.clone());
+
+        let baseline = UpgradeBaseline::new(&config, "hello-world").unwrap().unwrap();
+        let repo_dir = baseline._repo_dir.as_ref().unwrap().path();
+        assert!(repo_dir.join("hello-world_0.9.0-1_amd64.deb").exists());
+        assert!(!repo_dir.join("hello-world_0.9.0-1_amd64.changes").exists());
+        assert!(fs::read_to_string(repo_dir.join("Packages")).unwrap().starts_with("Package: hello-world\n"));
+        assert_eq!(baseline.get_args()[1], format!("--extra-repo=deb [trusted=yes] file://{} ./", repo_dir.display()));
+        assert!(runner.remaining().is_empty());
+
+        let err = UpgradeBaseline::new(&config, "other-package").err().unwrap().to_string();
+        assert!(err.contains("does not contain a .deb of other-package"));
+    }
+}
@@ -0,0 +1,91 @@
+use crate::v1::build::dry_run;
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+use std::io::IsTerminal;
+use std::os::unix::fs::MetadataExt;
+use std::process::Command;
+
+/// Arguments of `unshare` running a command as root in a user namespace, like sbuild's unshare mode.
+pub const UNSHARE_ARGS: [&str; 5] = ["--map-root-user", "--map-auto", "--mount", "--pid", "--fork"];
+
+pub fn is_root() -> bool {
+    fs::metadata("/proc/self").map(|metadata| metadata.uid() == 0).unwrap_or(false)
+}
+
+/// Whether unprivileged user namespaces are available, checked by entering one.
+pub fn user_namespaces_available() -> bool {
+    let mut cmd = Command::new("unshare");
+    cmd.args(["--map-root-user", "--map-auto", "true"]);
+    dry_run::output(&mut cmd)
+        .map(|output| output.status.success())
+        .unwrap_or(false)
+}
+
+/// Command running `program` as root, `reason` explains why root is needed.
+///
+/// Goes through sudo unless pkg-builder already runs as root. Fails up front if sudo asks
+/// for a password without a terminal to enter it, instead of waiting for it.
+pub fn root_command(program: &str, reason: &str) -> Result<Command> {
+    if is_root() {
+        return Ok(Command::new(program));
+    }
+    get_sudo_command(program, reason, std::io::stdin().is_terminal())
+}
+
+fn get_sudo_command(program: &str, reason: &str, interactive: bool) -> Result<Command> {
+    let passwordless = dry_run::output(Command::new("sudo").args(["-n", "true"]))
+        .map(|output| output.status.success())
+        .unwrap_or(false);
+    let mut cmd = Command::new("sudo");
+    if passwordless {
+        cmd.arg("-n");
+    } else if interactive {
+        info!("{} requires root, please provide your password through sudo.", reason);
+    } else {
+        return Err(eyre!(
+            "{} requires root, but sudo asks for a password and there is no terminal to enter it. \
+             Run pkg-builder as root or allow {} through sudo without a password.",
+            reason,
+            program
+        ));
+    }
+    cmd.arg(program);
+    Ok(cmd)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::build::command_runner::{self, Invocation, ReplayCommandRunner};
+    use std::rc::Rc;
+
+    fn sudo_check(exit_code: i32) -> Rc<ReplayCommandRunner> {
+        Rc::new(ReplayCommandRunner::new(vec![Invocation {
+            program: "sudo".to_string(),
+            args: vec!["-n".to_string(), "true".to_string()],
+            exit_code,
+            ..Default::default()
+        }]))
+    }
+
+    #[test]
+    fn test_passwordless_sudo() {
+        command_runner::set_runner(sudo_check(0));
+        let cmd = get_sudo_command("piuparts", "Piuparts", false).unwrap();
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, vec!["-n", "piuparts"]);
+    }
+
+    #[test]
+    fn test_sudo_password_without_terminal_fails() {
+        command_runner::set_runner(sudo_check(1));
+        let err = get_sudo_command("piuparts", "Piuparts", false).unwrap_err().to_string();
+        assert!(err.starts_with("Piuparts requires root, but sudo asks for a password and there is no terminal to enter it."));
+
+        command_runner::set_runner(sudo_check(1));
+        let cmd = get_sudo_command("piuparts", "Piuparts", true).unwrap();
+        let args: Vec<_> = cmd.get_args().collect();
+        assert_eq!(args, vec!["piuparts"]);
+    }
+}
@@ -1,15 +1,25 @@
 use crate::v1::packager::BackendBuildEnv;
-use crate::v1::pkg_config::{LanguageEnv, PackageType, PkgConfig};
+use crate::v1::pkg_config::{AutopkgtestBackend, ChrootConfig, ChrootCreator, LanguageEnv, PackageType, PkgConfig};
 use eyre::{eyre, Report, Result};
 use log::{info, warn};
 use rand::random;
-use std::io::{BufRead, BufReader, Read, Write};
+use std::io::Read;
 use std::path::{Path, PathBuf};
-use std::process::{Child, Command, Stdio};
+use std::process::{Command, Stdio};
+use crate::v1::build::chroot::{get_chroot_mirror, get_mmdebstrap_args, get_update_command, snapshot_to_epoch, verify_tarball_hash};
+use crate::v1::build::env_info::{list_cache_dir, parse_dpkg_status, write_hash_file, EnvInfo, InstalledPackage};
+use crate::v1::build::autopkgtest_image::AutopkgtestImage;
+use crate::v1::build::autopkgtest_virt::AutopkgtestVirt;
+use crate::v1::build::command_runner::{self, Invocation};
+use crate::v1::build::piuparts::UpgradeBaseline;
+use crate::v1::build::privilege::{root_command, user_namespaces_available, UNSHARE_ARGS};
+use crate::v1::build::dry_run::{self, PlanStep};
 use std::{env, fs, io};
-use std::fs::{create_dir_all};
 use cargo_metadata::semver::Version;
 use crate::v1::pkg_config_verify::PkgVerifyConfig;
+use crate::v1::build::dir_setup::{calculate_sha256, get_changelog_epoch};
+use crate::v1::deb822::{Buildinfo, Changes, ChangesFile, Checksum, Dsc};
+use crate::v1::distribution::{resolve_distribution, Distribution, Vendor};
 use sha1::{Digest, Sha1}; // Import from the sha1 crate
 
 pub struct Sbuild {
@@ -32,6 +42,8 @@ impl Sbuild {
     }
 
     fn get_build_deps_based_on_langenv(&self, lang_env: &LanguageEnv) -> Vec<String> {
+        // the toolchain runs in the chroot, which is of the build architecture
+        let build_arch = self.config.build_env.get_build_arch();
         match lang_env {
             LanguageEnv::C => {
                 let lang_deps = vec![];
@@ -40,9 +52,10 @@ impl Sbuild {
             LanguageEnv::Rust(config) => {
                 // TODO
                 // let rust_version = &config.rust_version;
-                let rust_binary_url = &config.rust_binary_url;
-                let rust_binary_gpg_asc = &config.rust_binary_gpg_asc;
-                let lang_deps = vec![
+                let binary = config.get_binary(&build_arch);
+                let rust_binary_url = &binary.url;
+                let rust_binary_gpg_asc = &binary.checksum;
+                let mut lang_deps = vec![
                     "apt install -y curl gpg gpg-agent".to_string(),
                     format!("cd /tmp && curl -o rust.tar.xz -L {}", rust_binary_url),
                     format!("cd /tmp && echo \"{}\" >> rust.tar.xz.asc && cat rust.tar.xz.asc ", rust_binary_gpg_asc),
@@ -50,15 +63,29 @@ impl Sbuild {
                     "cd /tmp && gpg --verify rust.tar.xz.asc rust.tar.xz".to_string(),
                     "cd /tmp && tar xvJf rust.tar.xz -C . --strip-components=1 --exclude=rust-docs".to_string(),
                     "cd /tmp && /bin/bash install.sh --without=rust-docs".to_string(),
-                    "apt remove -y curl gpg gpg-agent".to_string(),
                 ];
+                if self.config.build_env.is_cross_build() {
+                    // the toolchain only has the standard library of the build architecture
+                    match config.get_std_url(&self.config.build_env.arch) {
+                        Some(rust_std_url) => lang_deps.extend([
+                            format!("cd /tmp && curl -o rust-std.tar.xz -L {}", rust_std_url),
+                            format!("cd /tmp && curl -o rust-std.tar.xz.asc -L {}.asc", rust_std_url),
+                            "cd /tmp && gpg --verify rust-std.tar.xz.asc rust-std.tar.xz".to_string(),
+                            "mkdir -p /tmp/rust-std && cd /tmp && tar xJf rust-std.tar.xz -C rust-std --strip-components=1".to_string(),
+                            "cd /tmp/rust-std && /bin/bash install.sh".to_string(),
+                        ]),
+                        None => warn!("No rust target is known for {}, the rust standard library is not installed for it.", self.config.build_env.arch),
+                    }
+                }
+                lang_deps.push("apt remove -y curl gpg gpg-agent".to_string());
                 lang_deps
             }
             LanguageEnv::Go(config) => {
                 // TODO
                 //let go_version = &config.go_version;
-                let go_binary_url = &config.go_binary_url;
-                let go_binary_checksum = &config.go_binary_checksum;
+                let binary = config.get_binary(&build_arch);
+                let go_binary_url = &binary.url;
+                let go_binary_checksum = &binary.checksum;
                 let install = vec![
                     "apt install -y curl".to_string(),
                     format!("cd /tmp && curl -o go.tar.gz -L {}", go_binary_url),
@@ -75,8 +102,9 @@ impl Sbuild {
             }
             LanguageEnv::JavaScript(config) | LanguageEnv::TypeScript(config) => {
                 // let node_version = &config.go_version;
-                let node_binary_url = &config.node_binary_url;
-                let node_binary_checksum = &config.node_binary_checksum;
+                let binary = config.get_binary(&build_arch);
+                let node_binary_url = &binary.url;
+                let node_binary_checksum = &binary.checksum;
                 let mut install = vec![
                     "apt install -y curl".to_string(),
                     format!("cd /tmp && curl -o node.tar.gz -L {}", node_binary_url),
@@ -103,8 +131,9 @@ impl Sbuild {
                 let is_oracle = config.is_oracle;
                 if is_oracle {
                     let jdk_version = &config.jdk_version;
-                    let jdk_binary_url = &config.jdk_binary_url;
-                    let jdk_binary_checksum = &config.jdk_binary_checksum;
+                    let binary = config.get_binary(&build_arch);
+                    let jdk_binary_url = &binary.url;
+                    let jdk_binary_checksum = &binary.checksum;
                     let mut install = vec![
                         "apt install -y wget".to_string(),
                         format!("mkdir -p /opt/lib/jvm/jdk-{version}-oracle && mkdir -p /usr/lib/jvm", version = jdk_version),
@@ -139,41 +168,31 @@ impl Sbuild {
             LanguageEnv::Dotnet(config) => {
                 let dotnet_version = &config.dotnet_version;
                 let dotnet_full_version = &config.dotnet_full_version;
-                if self.config.build_env.codename == "bookworm" ||
-                    self.config.build_env.codename == "jammy jellyfish" {
-                    // TODO do not use MS repository as they upgrade between major versions
-                    // this breaks backward compatibility
-                    // reproducible builds should use pinned versions
-                    let install = vec![
-                        "apt install -y wget".to_string(),
-                        "cd /tmp && wget https://packages.microsoft.com/config/debian/12/packages-microsoft-prod.deb -O packages-microsoft-prod.deb".to_string(),
-                        "cd /tmp && dpkg -i packages-microsoft-prod.deb ".to_string(),
-                        "apt-get update -y".to_string(),
-                        format!("apt-get install -y dotnet-sdk-{}={}", dotnet_version, dotnet_full_version),
-                        "dotnet --version".to_string(),
-                        "apt remove -y wget".to_string(),
-                    ];
-                    install
-                } else if self.config.build_env.codename == "noble numbat" {
-                    let install = vec![
-                        "apt install -y wget".to_string(),
-                        "cd /tmp && wget https://packages.microsoft.com/config/ubuntu/24.04/packages-microsoft-prod.deb -O packages-microsoft-prod.deb".to_string(),
-                        "cd /tmp && dpkg -i packages-microsoft-prod.deb ".to_string(),
-                        "apt-get update -y".to_string(),
-                        format!("apt-cache madison dotnet-sdk-{}", dotnet_version),
-                        format!("apt-get install -y dotnet-sdk-{}={}", dotnet_version, dotnet_full_version),
-                        "dotnet --version".to_string(),
-                        "apt remove -y wget".to_string(),
-                    ];
-                    install
-                } else {
+                let Some(microsoft_repo) = self.get_microsoft_repo() else {
                     return vec![];
-                }
+                };
+                // TODO do not use MS repository as they upgrade between major versions
+                // this breaks backward compatibility
+                // reproducible builds should use pinned versions
+                let install = vec![
+                    "apt install -y wget".to_string(),
+                    format!("cd /tmp && wget https://packages.microsoft.com/config/{}/packages-microsoft-prod.deb -O packages-microsoft-prod.deb", microsoft_repo),
+                    "cd /tmp && dpkg -i packages-microsoft-prod.deb ".to_string(),
+                    "apt-get update -y".to_string(),
+                    format!("apt-cache madison dotnet-sdk-{}", dotnet_version),
+                    format!("apt-get install -y dotnet-sdk-{}={}", dotnet_version, dotnet_full_version),
+                    "dotnet --version".to_string(),
+                    "apt remove -y wget".to_string(),
+                ];
+                install
             }
             LanguageEnv::Nim(config) => {
                 let nim_version = &config.nim_version;
-                let nim_binary_url = &config.nim_binary_url;
-                let nim_version_checksum = &config.nim_version_checksum;
+                let binary = config.get_binary(&build_arch);
+                let nim_binary_url = &binary.url;
+                let nim_version_checksum = &binary.checksum;
+                // named like the checksum line expects, e.g. nim-2.0.2-linux_x64.tar.xz
+                let nim_archive = nim_binary_url.rsplit('/').next().unwrap_or_default();
                 let install = vec![
                     "apt install -y wget".to_string(),
                     format!("rm -rf /tmp/nim-{version} && rm -rf /usr/lib/nim/nim-{version}&& rm -rf /opt/lib/nim/nim-{version} && mkdir /tmp/nim-{version}", version = nim_version),
@@ -181,7 +200,7 @@ impl Sbuild {
                     format!("cd /tmp && wget -q {}", nim_binary_url),
                     format!("cd /tmp && echo {} >> hash_file.txt && cat hash_file.txt", nim_version_checksum),
                     "cd /tmp && sha256sum -c hash_file.txt".to_string(),
-                    format!("cd /tmp && tar xJf nim-{version}-linux_x64.tar.xz -C nim-{version} --strip-components=1", version = nim_version),
+                    format!("cd /tmp && tar xJf {archive} -C nim-{version} --strip-components=1", archive = nim_archive, version = nim_version),
                     format!("cd /tmp  && mv nim-{version} /opt/lib/nim", version = nim_version),
                     format!("ln -s /opt/lib/nim/nim-{version}/bin/nim  /usr/bin/nim", version = nim_version),
                     // equality check not working
@@ -193,7 +212,7 @@ impl Sbuild {
             }
         }
     }
-    fn get_build_deps_not_in_debian(&self) -> Vec<String> {
+    pub(crate) fn get_build_deps_not_in_debian(&self) -> Vec<String> {
         let package_type = &self.config.package_type;
         let lang_env = match package_type {
             PackageType::Default(config) => Some(&config.language_env),
@@ -239,29 +258,17 @@ impl Sbuild {
             }
             LanguageEnv::Dotnet(_) => {
                 // add ms repo, but do not install dotnet, let test_bed add it as intall dependency
-                if self.config.build_env.codename == "bookworm" ||
-                    self.config.build_env.codename == "jammy jellyfish"
-                {
-                    let install = vec![
-                        "apt install -y wget".to_string(),
-                        "cd /tmp && wget https://packages.microsoft.com/config/debian/12/packages-microsoft-prod.deb -O packages-microsoft-prod.deb".to_string(),
-                        "cd /tmp && dpkg -i packages-microsoft-prod.deb ".to_string(),
-                        "apt-get update -y".to_string(),
-                        "apt remove -y wget".to_string(),
-                    ];
-                    install
-                } else if self.config.build_env.codename == "noble numbat" {
-                    let install = vec![
-                        "apt install -y wget".to_string(),
-                        "cd /tmp && wget https://packages.microsoft.com/config/ubuntu/24.04/packages-microsoft-prod.deb -O packages-microsoft-prod.deb".to_string(),
-                        "cd /tmp && dpkg -i packages-microsoft-prod.deb ".to_string(),
-                        "apt-get update -y".to_string(),
-                        "apt remove -y wget".to_string(),
-                    ];
-                    install
-                } else {
+                let Some(microsoft_repo) = self.get_microsoft_repo() else {
                     return vec![];
-                }
+                };
+                let install = vec![
+                    "apt install -y wget".to_string(),
+                    format!("cd /tmp && wget https://packages.microsoft.com/config/{}/packages-microsoft-prod.deb -O packages-microsoft-prod.deb", microsoft_repo),
+                    "cd /tmp && dpkg -i packages-microsoft-prod.deb ".to_string(),
+                    "apt-get update -y".to_string(),
+                    "apt remove -y wget".to_string(),
+                ];
+                install
             }
             LanguageEnv::Nim(_) => {
                 // nim compiles to binary, no need to install under test_bed
@@ -270,7 +277,7 @@ impl Sbuild {
             }
         }
     }
-    fn get_test_deps_not_in_debian(&self) -> Vec<String> {
+    pub(crate) fn get_test_deps_not_in_debian(&self) -> Vec<String> {
         let package_type = &self.config.package_type;
         let lang_env = match package_type {
             PackageType::Default(config) => Some(&config.language_env),
@@ -287,27 +294,71 @@ impl Sbuild {
         }
     }
 
-    pub fn get_cache_file(&self) -> String {
+    pub fn get_distribution(&self) -> Result<Distribution> {
+        resolve_distribution(&self.config)
+    }
+
+    pub fn get_chroot_config(&self) -> ChrootConfig {
+        self.config.build_env.chroot.clone().unwrap_or_default()
+    }
+
+    pub fn get_autopkgtest_image(&self) -> Result<AutopkgtestImage> {
+        let distribution = self.get_distribution()?;
+        Ok(AutopkgtestImage::new(
+            &self.get_cache_dir(),
+            &distribution,
+            &self.config.build_env.arch,
+            &get_chroot_mirror(&self.get_chroot_config(), &distribution),
+            self.config.build_env.autopkgtest_image.clone().unwrap_or_default(),
+        ))
+    }
+
+    /// The packages.microsoft.com release dotnet is installed from, warns if there is none.
+    fn get_microsoft_repo(&self) -> Option<String> {
+        let distribution = self.get_distribution().ok()?;
+        let microsoft_repo = distribution.get_microsoft_repo();
+        if microsoft_repo.is_none() {
+            warn!("Microsoft publishes no dotnet packages for {}, dotnet is not installed.", distribution.suite);
+        }
+        microsoft_repo
+    }
+
+    pub fn get_autopkgtest_virt(&self) -> Result<AutopkgtestVirt> {
+        let distribution = self.get_distribution()?;
+        let mirror = get_chroot_mirror(&self.get_chroot_config(), &distribution);
+        Ok(AutopkgtestVirt::new(
+            &self.config.build_env,
+            distribution,
+            &mirror,
+            self.get_autopkgtest_image()?,
+            &self.get_cache_file(),
+        ))
+    }
+
+    pub fn get_cache_dir(&self) -> String {
         let dir = &self.cache_dir;
-        let expanded_path = if dir.starts_with('~') {
-            let expanded_path = shellexpand::tilde(dir).to_string();
-            expanded_path
+        if dir.starts_with('~') {
+            shellexpand::tilde(dir).to_string()
         } else if dir.starts_with('/') {
             self.cache_dir.clone()
         } else {
             let parent_dir = env::current_dir().unwrap();
             let dir = parent_dir.join(dir);
             let path = fs::canonicalize(dir.clone()).unwrap();
-            let path = path.to_str().unwrap().to_string();
-            path
-        };
+            path.to_str().unwrap().to_string()
+        }
+    }
 
-        let codename = normalize_codename(&self.config.build_env.codename).unwrap();
+    pub fn get_cache_file(&self) -> String {
+        let expanded_path = self.get_cache_dir();
+        let suite = self.get_distribution()
+            .map(|distribution| distribution.suite)
+            .unwrap_or_else(|_| self.config.build_env.codename.clone());
+        // the chroot is of the build architecture, which differs from arch in cross builds
         let cache_file_name = format!(
             "{}-{}.tar.gz",
-            codename, self.config.build_env.arch
-        )
-            .to_string();
+            suite, self.config.build_env.get_build_arch()
+        );
         let path = Path::new(&expanded_path);
         let cache_file = path.join(cache_file_name);
         cache_file.to_str().unwrap().to_string()
@@ -317,27 +368,131 @@ impl Sbuild {
         let deb_dir = Path::new(&self.build_files_dir).parent().unwrap();
         deb_dir
     }
+    /// Version of the built packages, including the version suffix of the distribution.
+    pub fn get_version(&self) -> String {
+        let suite = self.get_distribution()
+            .map(|distribution| distribution.suite)
+            .unwrap_or_else(|_| self.config.build_env.codename.clone());
+        format!("{}-{}",
+                self.config.package_fields.version_number,
+                self.config.package_fields.get_revision(&suite))
+    }
+
+    /// The package named like the source package, only a guess of what the build produces.
+    /// Only stands in for the built files in a dry run, otherwise they are read from the .changes file.
     pub fn get_deb_name(&self) -> PathBuf {
         let deb_dir = self.get_deb_dir();
-        let deb_file_name = format!("{}_{}-{}_{}.deb",
+        let deb_file_name = format!("{}_{}_{}.deb",
                                     self.config.package_fields.package_name,
-                                    self.config.package_fields.version_number,
-                                    self.config.package_fields.revision_number,
+                                    self.get_version(),
                                     self.config.build_env.arch);
-        let deb_name = deb_dir.join(deb_file_name);
-        deb_name
+        deb_dir.join(deb_file_name)
+    }
+
+    fn read_changes(&self) -> Result<Changes> {
+        let changes_file = self.get_changes_file();
+        let content = fs::read_to_string(&changes_file)
+            .map_err(|err| eyre!("Could not read {}, was the package built? {}", changes_file.display(), err))?;
+        content.parse::<Changes>()
+            .map_err(|err| eyre!("Could not parse {}: {}", changes_file.display(), err))
+    }
+
+    /// SOURCE_DATE_EPOCH of the build, from the config or the latest changelog entry.
+    pub fn get_source_date_epoch(&self) -> Result<Option<u64>> {
+        if let Some(epoch) = self.config.package_fields.get_source_date_epoch()? {
+            return Ok(Some(epoch));
+        }
+        if dry_run::is_enabled() && !Path::new(&self.build_files_dir).join("debian/changelog").exists() {
+            // the source is not prepared in a dry run
+            return Ok(None);
+        }
+        get_changelog_epoch(&self.build_files_dir).map(Some)
+    }
+
+    /// Files the build produced according to the .changes file, in the order listed there.
+    pub fn get_built_files(&self) -> Result<Vec<ChangesFile>> {
+        if dry_run::is_enabled() && !self.get_changes_file().exists() {
+            // nothing is built in a dry run, the package named like the source package stands in
+            let name = self.get_deb_name().file_name().unwrap().to_string_lossy().to_string();
+            return Ok(vec![ChangesFile {
+                md5: String::new(),
+                size: 0,
+                section: String::new(),
+                priority: String::new(),
+                name,
+            }]);
+        }
+        self.read_changes()?.files()
+    }
+
+    /// Binary packages of the build without the dbgsym packages.
+    pub fn get_built_debs(&self) -> Result<Vec<PathBuf>> {
+        let deb_dir = self.get_deb_dir();
+        let debs: Vec<PathBuf> = self.get_built_files()?
+            .iter()
+            .filter(|file| file.is_binary_package() && !file.is_dbgsym_package())
+            .map(|file| deb_dir.join(&file.name))
+            .collect();
+        if debs.is_empty() {
+            return Err(eyre!("{} lists no binary packages", self.get_changes_file().display()));
+        }
+        Ok(debs)
     }
 
-    //hello-world_1.0.0-1_amd64.changes
+    /// The .changes file of the binary build, found by its Source and Architecture fields.
     pub fn get_changes_file(&self) -> PathBuf {
         let deb_dir = self.get_deb_dir();
-        let deb_file_name = format!("{}_{}-{}_{}.changes",
-                                    self.config.package_fields.package_name,
-                                    self.config.package_fields.version_number,
-                                    self.config.package_fields.revision_number,
-                                    self.config.build_env.arch);
-        let deb_name = deb_dir.join(deb_file_name);
-        deb_name
+        let package_name = &self.config.package_fields.package_name;
+        let arch = &self.config.build_env.arch;
+        let mut candidates: Vec<PathBuf> = fs::read_dir(deb_dir)
+            .map(|entries| {
+                entries
+                    .filter_map(|entry| entry.ok().map(|entry| entry.path()))
+                    .filter(|path| path.extension().is_some_and(|extension| extension == "changes"))
+                    .collect()
+            })
+            .unwrap_or_default();
+        candidates.sort();
+        for candidate in candidates {
+            let Ok(changes) = fs::read_to_string(&candidate).map_err(Report::from).and_then(|content| content.parse::<Changes>()) else {
+                continue;
+            };
+            let architecture = changes.architecture();
+            if &changes.source() == package_name
+                && architecture.iter().any(|changes_arch| changes_arch == arch || changes_arch == "all") {
+                return candidate;
+            }
+        }
+        // not built yet, named like dpkg-genchanges names it
+        let changes_file_name = format!("{}_{}_{}.changes",
+                                        package_name,
+                                        self.get_version(),
+                                        arch);
+        deb_dir.join(changes_file_name)
+    }
+
+    pub(crate) fn get_lintian_args(&self, changes_file: &str) -> Result<Vec<String>> {
+        let mut cmd_args = vec![
+            "--suppress-tags".to_string(),
+            "bad-distribution-in-changes-file".to_string(),
+            "-i".to_string(),
+            "--I".to_string(),
+            changes_file.to_string(),
+            "--tag-display-limit=0".to_string(),
+            "--fail-on=warning".to_string(), // fail on warning
+            "--fail-on=error".to_string(), // fail on error
+            "--suppress-tags".to_string(), // overrides fails for this message
+            "debug-file-with-no-debug-symbols".to_string(),
+        ];
+        let distribution = self.get_distribution()?;
+
+        if distribution.vendor == Vendor::Ubuntu {
+            // changed a format of .deb packages on ubuntu, it's not a bug
+            // but some lintian will report as such
+            cmd_args.push("--suppress-tags".to_string());
+            cmd_args.push("malformed-deb-archive".to_string());
+        }
+        Ok(cmd_args)
     }
 }
 
@@ -347,44 +502,121 @@ impl BackendBuildEnv for Sbuild {
         info!("Cleaning cached build: {}", cache_file);
         let path = Path::new(&cache_file);
         if path.exists() {
-            remove_file_or_directory(&cache_file, false)
+            dry_run::remove_file(&cache_file)
                 .map_err(|_| eyre!("Could not remove previous cache file!"))?;
         }
+        let hash_file = format!("{}.sha256", cache_file);
+        if Path::new(&hash_file).exists() {
+            dry_run::remove_file(&hash_file)?;
+        }
+        self.get_autopkgtest_virt()?.clean()
+    }
+
+    fn update(&self) -> Result<()> {
+        let cache_file = self.get_cache_file();
+        if !Path::new(&cache_file).exists() && !dry_run::is_enabled() {
+            return Err(eyre!("Chroot tarball {} does not exist, please create it with: pkg-builder env create", cache_file));
+        }
+        info!("Upgrading packages of chroot tarball {}", cache_file);
+        run_command(&mut get_update_command(&cache_file)?)
+            .map_err(|err| eyre!("Failed to update chroot: {}", err))?;
+        if dry_run::is_enabled() {
+            return Ok(());
+        }
+        let hash = calculate_sha256(fs::File::open(&cache_file)?)?;
+        write_hash_file(Path::new(&cache_file), &hash)?;
+        if self.get_chroot_config().tarball_hash.is_some_and(|tarball_hash| tarball_hash != hash) {
+            warn!("The chroot changed, update tarball_hash in [build_env.chroot] to {}", hash);
+        } else {
+            info!("Updated chroot tarball has sha256 {}", hash);
+        }
         Ok(())
     }
 
+    fn list(&self) -> Result<Vec<EnvInfo>> {
+        list_cache_dir(Path::new(&self.get_cache_dir()))
+    }
+
+    fn info(&self) -> Result<Vec<InstalledPackage>> {
+        let cache_file = self.get_cache_file();
+        if !Path::new(&cache_file).exists() && !dry_run::is_enabled() {
+            return Err(eyre!("Chroot tarball {} does not exist, please create it with: pkg-builder env create", cache_file));
+        }
+        let output = dry_run::output(Command::new("tar")
+            .args(["--extract", "--to-stdout", "--file", &cache_file])
+            .args(["--wildcards", "--no-anchored", "var/lib/dpkg/status"]))?;
+        if !output.status.success() {
+            return Err(eyre!("Could not read the package list of {}: {}", cache_file, String::from_utf8_lossy(&output.stderr)));
+        }
+        Ok(parse_dpkg_status(&String::from_utf8_lossy(&output.stdout)))
+    }
+
     fn create(&self) -> Result<()> {
         let mut temp_dir = env::temp_dir();
         let dir_name = format!("temp_{}", random::<u32>());
         temp_dir.push(dir_name);
-        fs::create_dir(&temp_dir)?;
+        dry_run::create_dir_all(&temp_dir)?;
 
         let cache_file = self.get_cache_file();
         let cache_dir = Path::new(&cache_file).parent().unwrap();
-        create_dir_all(cache_dir).map_err(|_| eyre!("Failed to create cache_dir"))?;
-        let codename = normalize_codename(&self.config.build_env.codename)?;
-
-        let repo_url = get_repo_url(&self.config.build_env.codename.as_str())?;
-        let create_result = Command::new("sbuild-createchroot")
-            .arg("--chroot-mode=unshare")
-            .arg("--make-sbuild-tarball")
-            .arg(cache_file)
-            .arg(codename)
-            .arg(temp_dir)
-            .arg(repo_url)
-            .status();
-
-        if let Err(err) = create_result {
-            return Err(eyre!(format!("Failed to create new chroot: {}", err)));
+        dry_run::create_dir_all(cache_dir).map_err(|_| eyre!("Failed to create cache_dir"))?;
+        let distribution = self.get_distribution()?;
+
+        let chroot = self.get_chroot_config();
+        match chroot.creator {
+            ChrootCreator::SbuildCreatechroot => {
+                run_command(Command::new("sbuild-createchroot")
+                    .arg("--chroot-mode=unshare")
+                    .arg("--make-sbuild-tarball")
+                    .arg(&cache_file)
+                    .arg(format!("--arch={}", self.config.build_env.get_build_arch()))
+                    .arg(format!("--components={}", distribution.components.join(",")))
+                    .arg(format!("--keyring={}", distribution.keyring))
+                    .arg(&distribution.suite)
+                    .arg(temp_dir)
+                    .arg(get_chroot_mirror(&chroot, &distribution)))
+                    .map_err(|err| eyre!("Failed to create new chroot: {}", err))?;
+            }
+            ChrootCreator::Mmdebstrap => {
+                let mut architectures = vec![self.config.build_env.get_build_arch()];
+                if self.config.build_env.is_cross_build() {
+                    // the host architecture is added as a foreign one for the build dependencies
+                    architectures.push(self.config.build_env.arch.clone());
+                }
+                let cmd_args = get_mmdebstrap_args(&chroot, &distribution, &architectures.join(","), &cache_file);
+                info!("Creating chroot by invoking: mmdebstrap {}", cmd_args.join(" "));
+                let mut cmd = Command::new("mmdebstrap");
+                cmd.args(&cmd_args)
+                    .stdout(Stdio::inherit())
+                    .stderr(Stdio::inherit());
+                if let Some(snapshot) = &chroot.snapshot {
+                    // mmdebstrap creates a reproducible tarball when SOURCE_DATE_EPOCH is set
+                    cmd.env("SOURCE_DATE_EPOCH", snapshot_to_epoch(snapshot)?.to_string());
+                }
+                run_command(&mut cmd)
+                    .map_err(|err| eyre!("Failed to create new chroot: {}", err))?;
+            }
+        }
+        if !dry_run::is_enabled() {
+            let hash = verify_tarball_hash(&cache_file, &chroot)?;
+            write_hash_file(Path::new(&cache_file), &hash)?;
+        }
+        if let Some(true) = self.config.build_env.run_autopkgtest {
+            self.get_autopkgtest_virt()?.create()?;
         }
         Ok(())
     }
+
     fn package(&self) -> Result<()> {
-        let codename = normalize_codename(&self.config.build_env.codename)?;
+        let distribution = self.get_distribution()?;
+        let chroot = self.get_chroot_config();
+        if chroot.tarball_hash.is_some() && !dry_run::is_enabled() {
+            verify_tarball_hash(&self.get_cache_file(), &chroot)?;
+        }
 
         let mut cmd_args = vec![
             "-d".to_string(),
-            codename.to_string(),
+            distribution.suite,
             "-A".to_string(),                    // build_arch_all
             "-s".to_string(),                    // build source
             "--source-only-changes".to_string(), // source_only_changes
@@ -393,7 +625,11 @@ impl BackendBuildEnv for Sbuild {
             "-v".to_string(),                    // verbose
             "--chroot-mode=unshare".to_string(),
         ];
-
+        if self.config.build_env.is_cross_build() {
+            // sbuild adds the host architecture to the chroot and resolves the build dependencies multiarch
+            cmd_args.push(format!("--host={}", self.config.build_env.arch));
+            cmd_args.push(format!("--build={}", self.config.build_env.get_build_arch()));
+        }
 
         let lang_deps = self.get_build_deps_not_in_debian();
 
@@ -412,13 +648,12 @@ impl BackendBuildEnv for Sbuild {
             cmd_args.join(" ")
         );
 
-        let mut cmd = Command::new("sbuild")
-            .current_dir(self.build_files_dir.clone())
+        let mut cmd = Command::new("sbuild");
+        cmd.current_dir(self.build_files_dir.clone())
             .args(&cmd_args)
             .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()?;
-        run_process(&mut cmd)?;
+            .stderr(Stdio::inherit());
+        run_command(&mut cmd)?;
 
         if let Some(true) = self.config.build_env.run_lintian {
             self.run_lintian()?;
@@ -437,8 +672,20 @@ impl BackendBuildEnv for Sbuild {
         let package_hash = verify_config.verify.package_hash;
         let mut errors: Vec<Report> = vec![];
         for output in package_hash.iter() {
+            if dry_run::record(PlanStep::Check {
+                description: format!("{} has sha1 {}", output_dir.join(&output.name).display(), output.hash),
+            }) {
+                continue;
+            }
             let file = output_dir.join(output.name.clone());
             if !file.exists() {
+                let version = self.get_version();
+                let unsuffixed_version = format!("{}-{}",
+                                                 self.config.package_fields.version_number,
+                                                 self.config.package_fields.revision_number);
+                if output.name.contains(&unsuffixed_version) && !output.name.contains(&version) {
+                    return Err(eyre!("File to be verified does not exist {}, the packages are built with version {}", output.name, version));
+                }
                 return Err(eyre!(format!("File to be verified does not exist {}", output.name)));
             }
             let mut file = fs::File::open(file).map_err(|_| eyre!("Could not open file."))?;
@@ -449,6 +696,29 @@ impl BackendBuildEnv for Sbuild {
                 errors.push(eyre!(format!("file {} actual sha1 is {}", output.name,  &actual_sha1)));
             }
         }
+        if dry_run::is_enabled() {
+            return Ok(());
+        }
+        if self.get_changes_file().exists() {
+            let changes = self.read_changes()?;
+            for file in changes.files()? {
+                if file.is_binary_package() && !package_hash.iter().any(|output| output.name == file.name) {
+                    warn!("{} was built, but has no hash in the verify config.", file.name);
+                }
+            }
+            // the build outputs have to match the checksums recorded for them during the build
+            errors.append(&mut verify_checksums(output_dir, &changes.checksums_sha256()?));
+            for file in changes.files()? {
+                let path = output_dir.join(&file.name);
+                if file.name.ends_with(".dsc") {
+                    let dsc: Dsc = fs::read_to_string(&path)?.parse()?;
+                    errors.append(&mut verify_checksums(output_dir, &dsc.checksums_sha256()?));
+                } else if file.name.ends_with(".buildinfo") {
+                    let buildinfo: Buildinfo = fs::read_to_string(&path)?.parse()?;
+                    errors.append(&mut verify_checksums(output_dir, &buildinfo.checksums_sha256()?));
+                }
+            }
+        }
         let result = if errors.is_empty() {
             println!("Verify is successful!");
             Ok(())
@@ -470,63 +740,44 @@ impl BackendBuildEnv for Sbuild {
         check_lintian_version(self.config.build_env.lintian_version.clone())?;
         // let deb_dir = self.get_deb_dir();
         let changes_file = self.get_changes_file();
-        let changes_file = changes_file.to_str().unwrap();
-        let mut cmd_args = vec![
-            "--suppress-tags".to_string(),
-            "bad-distribution-in-changes-file".to_string(),
-            "-i".to_string(),
-            "--I".to_string(),
-            changes_file.to_string(),
-            "--tag-display-limit=0".to_string(),
-            "--fail-on=warning".to_string(), // fail on warning
-            "--fail-on=error".to_string(), // fail on error
-            "--suppress-tags".to_string(), // overrides fails for this message
-            "debug-file-with-no-debug-symbols".to_string(),
-        ];
-        let codename = normalize_codename(&self.config.build_env.codename)?;
-
-        if codename == "jammy".to_string() || codename == "noble".to_string() {
-            // changed a format of .deb packages on ubuntu, it's not a bug
-            // but some lintian will report as such
-            cmd_args.push("--suppress-tags".to_string());
-            cmd_args.push("malformed-deb-archive".to_string());
-        }
+        let cmd_args = self.get_lintian_args(changes_file.to_str().unwrap())?;
 
         info!(
             "Testing package by invoking: lintian {}",
             cmd_args.join(" ")
         );
 
-        let mut cmd = Command::new("lintian")
-            // for CI
-            .args(&cmd_args)
+        let mut cmd = Command::new("lintian");
+        // for CI
+        cmd.args(&cmd_args)
             .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()?;
-        run_process(&mut cmd)
+            .stderr(Stdio::inherit());
+        run_command(&mut cmd)
     }
 
 
     fn run_piuparts(&self) -> Result<()> {
+        if self.config.build_env.is_cross_build() {
+            warn!(
+                "Skipping piuparts, {} packages cannot be installed on {}.",
+                self.config.build_env.arch,
+                self.config.build_env.get_build_arch()
+            );
+            return Ok(());
+        }
         info!(
-            "Running piuparts command with elevated privileges..",
-        );
-        info!(
-            "Piuparts must run as root user through sudo, please provide your password, if prompted."
+            "Running piuparts command..",
         );
         check_piuparts_version(self.config.build_env.piuparts_version.clone())?;
 
-        let repo_url = get_repo_url(&self.config.build_env.codename.as_str())?;
-        let keyring = get_keyring(&self.config.build_env.codename)?;
-        let codename = normalize_codename(&self.config.build_env.codename)?;
+        let distribution = self.get_distribution()?;
 
         let mut cmd_args = vec![
             "-d".to_string(),
-            codename.to_string(),
+            distribution.suite.clone(),
             "-m".to_string(),
-            repo_url.to_string(),
-            "--bindmount=/dev".to_string(),
-            format!("--keyring={}", keyring),
+            format!("{} {}", distribution.mirror, distribution.components.join(" ")),
+            format!("--keyring={}", distribution.keyring),
         ];
         let package_type = &self.config.package_type;
 
@@ -535,61 +786,81 @@ impl BackendBuildEnv for Sbuild {
             PackageType::Git(config) => Some(&config.language_env),
             PackageType::Virtual => None,
         };
-        if let Some(env) = lang_env {
-            match env {
-                LanguageEnv::Dotnet(_) => {
-                    if self.config.build_env.codename == "bookworm" ||
-                        self.config.build_env.codename == "jammy jellyfish" {
-                        let ms_repo = format!("deb https://packages.microsoft.com/debian/12/prod {} main", self.config.build_env.codename);
-                        cmd_args.push(format!("--extra-repo={}", ms_repo));
-                        cmd_args.push("--do-not-verify-signatures".to_string());
-                    } else if self.config.build_env.codename == "noble numbat" {
-                        let ms_repo = format!("deb https://packages.microsoft.com/ubuntu/24.04/prod {} main", self.config.build_env.codename);
-                        cmd_args.push(format!("--extra-repo={}", ms_repo));
-                        cmd_args.push("--do-not-verify-signatures".to_string());
-                    }
-                }
-                _ => {
-                    // no other package repositories supported
-                    // might supply my own, but not for now
-                }
+        // no other package repositories supported
+        // might supply my own, but not for now
+        if let Some(LanguageEnv::Dotnet(_)) = lang_env {
+            if let Some(microsoft_repo) = distribution.get_microsoft_repo() {
+                let ms_repo = format!("deb https://packages.microsoft.com/{}/prod {} main", microsoft_repo, distribution.suite);
+                cmd_args.push(format!("--extra-repo={}", ms_repo));
+                cmd_args.push("--do-not-verify-signatures".to_string());
             }
         }
+        let piuparts_config = self.config.piuparts.clone().unwrap_or_default();
+        // kept until piuparts is done, a local baseline is served from a temporary directory
+        let upgrade_baseline = UpgradeBaseline::new(&piuparts_config, &self.config.package_fields.package_name)?;
+        if let Some(upgrade_baseline) = &upgrade_baseline {
+            cmd_args.extend(upgrade_baseline.get_args().iter().cloned());
+        }
+        let cache_file = self.get_cache_file();
+        let mut cmd = if Path::new(&cache_file).exists() {
+            // the chroot tarball of the build is the base system, which piuparts
+            // can then set up as root of a user namespace
+            cmd_args.push(format!("--basetgz={}", cache_file));
+            if user_namespaces_available() {
+                // /dev cannot be bind mounted as root of a user namespace
+                let mut cmd = Command::new("unshare");
+                cmd.args(UNSHARE_ARGS).arg("piuparts");
+                cmd
+            } else {
+                cmd_args.push("--bindmount=/dev".to_string());
+                root_command("piuparts", "Piuparts without unprivileged user namespaces")?
+            }
+        } else {
+            warn!("Chroot tarball {} does not exist, piuparts creates its own base system.", cache_file);
+            cmd_args.push("--bindmount=/dev".to_string());
+            root_command("piuparts", "Piuparts without the chroot tarball")?
+        };
         let deb_dir = self.get_deb_dir();
-        let deb_name = self.get_deb_name();
+        // all binary packages of the build are installed together, they usually depend on each other
+        let debs: Vec<String> = self.get_built_debs()?
+            .iter()
+            .map(|deb| deb.to_str().unwrap().to_string())
+            .collect();
         info!(
-            "Testing package by invoking: sudo -S piuparts {} {}",
+            "Testing package by invoking: {} {} {}",
+            Invocation::from_command(&cmd).command_line(),
             cmd_args.join(" "),
-            deb_name.to_str().unwrap()
+            debs.join(" ")
         );
         info!("Note this command run inside of directory: {}", deb_dir.display());
 
-        let mut cmd = Command::new("sudo")
-            .current_dir(deb_dir)
-            // for CI
-            .arg("-S")
-            .arg("piuparts")
+        cmd.current_dir(deb_dir)
             .args(&cmd_args)
-            .arg(deb_name)
+            .args(&debs)
             .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()?;
-        run_process(&mut cmd)
+            .stderr(Stdio::inherit());
+        run_command(&mut cmd)
     }
 
     fn run_autopkgtests(&self) -> Result<()> {
         info!(
             "Running autopkgtests command",
         );
+        let backend = self.config.build_env.autopkgtest_backend.clone().unwrap_or_default();
+        if self.config.build_env.is_cross_build() && backend != AutopkgtestBackend::Qemu {
+            // only qemu emulates the host architecture
+            warn!(
+                "Skipping autopkgtest, {} packages cannot be tested with the {} backend on {}.",
+                self.config.build_env.arch,
+                backend.name(),
+                self.config.build_env.get_build_arch()
+            );
+            return Ok(());
+        }
         check_autopkgtest_version(self.config.build_env.autopkgtest_version.clone())?;
 
-        let image_name = format!("autopkgtest-{}.img", self.config.build_env.codename.to_string());
-        let mut cache_dir = self.cache_dir.clone();
-        if cache_dir.starts_with('~') {
-            cache_dir = shellexpand::tilde(&cache_dir).to_string()
-        }
-        let image_path = Path::new(&cache_dir).join(image_name.clone());
-        create_autopkgtest_image(image_path.clone(), self.config.build_env.codename.to_string())?;
+        let virt = self.get_autopkgtest_virt()?;
+        virt.check()?;
 
         let deb_dir = self.get_deb_dir();
         //  let deb_name = self.get_deb_name();
@@ -607,27 +878,43 @@ impl BackendBuildEnv for Sbuild {
             cmd_args.push(format!("--setup-commands={}", action))
         }
         cmd_args.push("--".to_string());
-        cmd_args.push("qemu".to_string());
-        cmd_args.push(image_path.to_str().unwrap().to_string());
+        cmd_args.extend(virt.get_virt_args());
         info!(
             "Testing package by invoking: autopkgtest {}",
             cmd_args.join(" ")
         );
         info!("Note this command run inside of directory: {}", deb_dir.display());
-        let mut cmd = Command::new("autopkgtest")
-            .current_dir(deb_dir)
+        let mut cmd = Command::new("autopkgtest");
+        cmd.current_dir(deb_dir)
             .args(&cmd_args)
             .stdout(Stdio::inherit())
-            .stderr(Stdio::inherit())
-            .spawn()?;
-        run_process(&mut cmd)
+            .stderr(Stdio::inherit());
+        run_command(&mut cmd)
+    }
+}
+
+fn verify_checksums(dir: &Path, checksums: &[Checksum]) -> Vec<Report> {
+    let mut errors = vec![];
+    for checksum in checksums {
+        let path = dir.join(&checksum.name);
+        match fs::File::open(&path).map_err(Report::from).and_then(calculate_sha256) {
+            Ok(actual_hash) if actual_hash != checksum.hash => {
+                errors.push(eyre!("file {} actual sha256 is {}, but {} was recorded", checksum.name, actual_hash, checksum.hash));
+            }
+            Ok(_) => {}
+            Err(err) => errors.push(eyre!("Could not read {}: {}", path.display(), err)),
+        }
     }
+    errors
 }
 
 fn check_lintian_version(expected_version: String) -> Result<()> {
-    let output = Command::new("lintian")
-        .arg("--version")
-        .output()?;
+    let mut cmd = Command::new("lintian");
+    cmd.arg("--version");
+    if dry_run::record_command(&cmd) {
+        return Ok(());
+    }
+    let output = command_runner::output(&mut cmd)?;
 
     if output.status.success() {
         let mut output_str = String::from_utf8_lossy(&output.stdout).to_string()
@@ -647,9 +934,12 @@ fn check_lintian_version(expected_version: String) -> Result<()> {
 }
 
 fn check_piuparts_version(expected_version: String) -> Result<()> {
-    let output = Command::new("piuparts")
-        .arg("--version")
-        .output()?;
+    let mut cmd = Command::new("piuparts");
+    cmd.arg("--version");
+    if dry_run::record_command(&cmd) {
+        return Ok(());
+    }
+    let output = command_runner::output(&mut cmd)?;
 
     if output.status.success() {
         let output_str = String::from_utf8_lossy(&output.stdout)
@@ -666,11 +956,14 @@ fn check_piuparts_version(expected_version: String) -> Result<()> {
 }
 
 fn check_autopkgtest_version(expected_version: String) -> Result<()> {
-    let output = Command::new("apt")
-        .arg("list")
+    let mut cmd = Command::new("apt");
+    cmd.arg("list")
         .arg("--installed")
-        .arg("autopkgtest")
-        .output()?;
+        .arg("autopkgtest");
+    if dry_run::record_command(&cmd) {
+        return Ok(());
+    }
+    let output = command_runner::output(&mut cmd)?;
 
     //autopkgtest/jammy-updates,now 5.32ubuntu3~22.04.1 all [installed]
     if output.status.success() {
@@ -717,51 +1010,6 @@ pub fn warn_compare_versions(expected_version: String, actual_version: &str, pro
     }
 }
 
-pub fn normalize_codename(codename: &str) -> Result<&str> {
-    match codename {
-        "bookworm" => {
-            Ok("bookworm")
-        }
-        "noble numbat" => {
-            Ok("noble")
-        }
-        "jammy jellyfish" => {
-            Ok("jammy")
-        }
-        _ => {
-            Err(eyre!("Not supported distribution"))
-        }
-    }
-}
-
-pub fn get_keyring(codename: &str) -> Result<&str> {
-    match codename {
-        "bookworm" => {
-            Ok("/usr/share/keyrings/debian-archive-keyring.gpg")
-        }
-        "noble numbat" | "jammy jellyfish" => {
-            Ok("/usr/share/keyrings/ubuntu-archive-keyring.gpg")
-        }
-        _ => {
-            Err(eyre!("Not supported distribution"))
-        }
-    }
-}
-
-pub fn get_repo_url(codename: &str) -> Result<&str> {
-    match codename {
-        "bookworm" => {
-            Ok("http://deb.debian.org/debian")
-        }
-        "noble numbat" | "jammy jellyfish" => {
-            Ok("http://archive.ubuntu.com/ubuntu")
-        }
-        _ => {
-            Err(eyre!("Not supported distribution"))
-        }
-    }
-}
-
 pub fn calculate_sha1<R: Read>(mut reader: R) -> Result<String, io::Error> {
     let mut hasher = Sha1::new();
     io::copy(&mut reader, &mut hasher)?;
@@ -771,70 +1019,24 @@ pub fn calculate_sha1<R: Read>(mut reader: R) -> Result<String, io::Error> {
     Ok(hex_digest)
 }
 
-fn create_autopkgtest_image(image_path: PathBuf, codename: String) -> Result<()> {
-
-    // do not recreate image if exists
-    if image_path.exists() {
-        return Ok(());
-    }
-    info!(
-        "autopkgtests environment does not exist. Creating it."
-    );
-    info!(
-        "please provide your password through sudo to as autopkgtest env creation requires it."
-    );
-    create_dir_all(image_path.parent().unwrap())?;
-    let repo_url = get_repo_url(&codename)?;
-
-    let codename = normalize_codename(&codename)?;
-    let cmd_args = vec![
-        codename.to_string(),
-        image_path.to_str().unwrap().to_string(),
-        format!("--mirror={}", repo_url),
-    ];
-    let mut cmd = Command::new("sudo")
-        // for CI
-        .arg("-S")
-        .arg("autopkgtest-build-qemu")
-        .args(&cmd_args)
-        .stdout(Stdio::inherit())
-        .stderr(Stdio::inherit())
-        .spawn()?;
-    run_process(&mut cmd)
-}
-
-fn run_process(child: &mut Child) -> Result<()> {
-    if let Some(stdout) = child.stdout.take() {
-        let reader = BufReader::new(stdout);
-
-        for line in reader.lines() {
-            let line = line?;
-            info!("{}", line);
-        }
-    }
-    io::stdout().flush()?;
-
-    let status = child.wait().map_err(|err| eyre!(err.to_string()))?;
+pub(crate) fn run_command(cmd: &mut Command) -> Result<()> {
+    let status = dry_run::status(cmd)?;
     if status.success() {
         Ok(())
     } else {
-        Err(eyre!("Sbuild exited with non-zero status code. Please see build output for potential causes."))
+        Err(eyre!("{} exited with non-zero status code. Please see build output for potential causes.", cmd.get_program().to_string_lossy()))
     }
 }
 
-fn remove_file_or_directory(path: &str, is_directory: bool) -> io::Result<()> {
-    if is_directory {
-        fs::remove_dir_all(path)?;
-    } else {
-        fs::remove_file(path)?;
-    }
-    Ok(())
-}
-
 #[cfg(test)]
 mod tests {
     use super::*;
+    use crate::v1::build::command_runner::{Invocation, ReplayCommandRunner};
+    use crate::v1::build::privilege::is_root;
+    use crate::v1::pkg_config::{DefaultPackageTypeConfig, DotnetConfig, GoConfig, RustConfig, ToolchainBinary};
+    use crate::v1::pkg_config_verify::{PackageHash, VerifyConfig};
     use env_logger::Env;
+    use std::rc::Rc;
     use std::fs::File;
     use std::sync::Once;
     use tempfile::tempdir;
@@ -874,7 +1076,7 @@ mod tests {
         pkg_config.build_env.arch = "amd64".to_string();
         let sbuild_cache = tempdir().unwrap();
         // create dir manually, as it doesn't exist
-        create_dir_all(sbuild_cache.path()).expect("Could not create temporary directory for testing.");
+        fs::create_dir_all(sbuild_cache.path()).expect("Could not create temporary directory for testing.");
         let sbuild_cache_dir = sbuild_cache.path().to_str().unwrap().to_string();
         pkg_config.build_env.sbuild_cache_dir = Some(sbuild_cache_dir.clone());
         let build_env = Sbuild::new(pkg_config, build_files_dir);
@@ -904,7 +1106,7 @@ mod tests {
         pkg_config.build_env.codename = "bookworm".to_string();
         pkg_config.build_env.arch = "amd64".to_string();
         let sbuild_cache_dir = tempdir().unwrap().path().to_str().unwrap().to_string();
-        pkg_config.build_env.sbuild_cache_dir = Some(sbuild_cache_dir);
+        pkg_config.build_env.sbuild_cache_dir = Some(sbuild_cache_dir.clone());
 
         let build_files_dir = tempdir().unwrap().path().to_str().unwrap().to_string();
         let build_env = Sbuild::new(pkg_config, build_files_dir);
@@ -912,8 +1114,382 @@ mod tests {
         let cache_file = build_env.get_cache_file();
         let cache_file_path = Path::new(&cache_file);
         assert!(!cache_file_path.exists());
+
+        let runner = Rc::new(ReplayCommandRunner::from_file(
+            "tests/fixtures/sbuild_create.json",
+            &[("CACHE_DIR", &sbuild_cache_dir)],
+        ).unwrap());
+        command_runner::set_runner(runner.clone());
+        // the replayed sbuild-createchroot does not write the tarball
+        fs::create_dir_all(&sbuild_cache_dir).unwrap();
+        fs::write(cache_file_path, "chroot").unwrap();
         let result = build_env.create();
-        assert!(result.is_ok());
-        assert!(cache_file_path.exists())
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_create_fails_when_createchroot_fails() {
+        setup();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = "bookworm".to_string();
+        pkg_config.build_env.arch = "amd64".to_string();
+        let sbuild_cache = tempdir().unwrap();
+        pkg_config.build_env.sbuild_cache_dir = Some(sbuild_cache.path().to_str().unwrap().to_string());
+        let build_files_dir = tempdir().unwrap().path().to_str().unwrap().to_string();
+        let build_env = Sbuild::new(pkg_config, build_files_dir);
+
+        let runner = Rc::new(ReplayCommandRunner::new(vec![Invocation {
+            program: "sbuild-createchroot".to_string(),
+            args: vec!["*"; 9].into_iter().map(String::from).collect(),
+            exit_code: 1,
+            ..Default::default()
+        }]));
+        command_runner::set_runner(runner.clone());
+        let result = build_env.create();
+        assert!(result.unwrap_err().to_string().starts_with("Failed to create new chroot"));
+        assert!(runner.remaining().is_empty());
+        assert!(!Path::new(&build_env.get_cache_file()).exists());
+    }
+
+    #[test]
+    fn test_package_runs_build_and_tests() {
+        setup();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.package_fields.package_name = "hello-world".to_string();
+        pkg_config.package_fields.version_number = "1.0.0".to_string();
+        pkg_config.package_fields.revision_number = "1".to_string();
+        pkg_config.build_env.codename = "bookworm".to_string();
+        pkg_config.build_env.arch = "amd64".to_string();
+        pkg_config.build_env.run_lintian = Some(true);
+        pkg_config.build_env.run_piuparts = Some(true);
+        pkg_config.build_env.run_autopkgtest = Some(true);
+        pkg_config.build_env.lintian_version = "2.116.3".to_string();
+        pkg_config.build_env.piuparts_version = "1.1.7".to_string();
+        pkg_config.build_env.autopkgtest_version = "5.28".to_string();
+        let sbuild_cache = tempdir().unwrap();
+        let sbuild_cache_dir = sbuild_cache.path().to_str().unwrap().to_string();
+        pkg_config.build_env.sbuild_cache_dir = Some(sbuild_cache_dir.clone());
+        let deb_dir = tempdir().unwrap();
+        let deb_dir = deb_dir.path().to_str().unwrap().to_string();
+        let build_files_dir = format!("{}/hello-world-1.0.0", deb_dir);
+        fs::write(sbuild_cache.path().join("autopkgtest-bookworm-amd64.img"), "image").unwrap();
+        fs::write(sbuild_cache.path().join("bookworm-amd64.tar.gz"), "chroot").unwrap();
+        fs::create_dir_all(format!("{}/debian", build_files_dir)).unwrap();
+        fs::write(format!("{}/debian/changelog", build_files_dir),
+                  "hello-world (1.0.0-1) bookworm; urgency=medium\n\n  * Initial release.\n\n \
+                   -- Jane Doe <jane@example.com>  Mon, 01 Jan 2024 01:00:00 +0100\n").unwrap();
+        fs::write(format!("{}/hello-world_1.0.0-1_amd64.changes", deb_dir),
+                  "Source: hello-world\nArchitecture: amd64\nFiles:\n 1d 1024 misc optional hello-world_1.0.0-1_amd64.deb\n \
+                   2d 2048 debug optional hello-world-dbgsym_1.0.0-1_amd64.deb\n").unwrap();
+
+        let runner = Rc::new(ReplayCommandRunner::from_file(
+            "tests/fixtures/sbuild_package.json",
+            &[("CACHE_DIR", &sbuild_cache_dir), ("DEB_DIR", &deb_dir)],
+        ).unwrap());
+        command_runner::set_runner(runner.clone());
+        let build_env = Sbuild::new(pkg_config, build_files_dir);
+        let result = build_env.package();
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(runner.remaining().is_empty());
+    }
+
+    #[test]
+    fn test_cross_build_uses_build_arch_chroot_and_toolchain() {
+        setup();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = "bookworm".to_string();
+        pkg_config.build_env.arch = "arm64".to_string();
+        pkg_config.build_env.build_arch = Some("amd64".to_string());
+        pkg_config.build_env.sbuild_cache_dir = Some("/cache".to_string());
+        pkg_config.build_env.run_piuparts = Some(true);
+        let mut go = GoConfig {
+            go_binary_url: "https://go.dev/dl/go1.22.0.linux-amd64.tar.gz".to_string(),
+            go_binary_checksum: "amd64sum".to_string(),
+            ..Default::default()
+        };
+        go.binaries.insert("arm64".to_string(), ToolchainBinary {
+            url: "https://go.dev/dl/go1.22.0.linux-arm64.tar.gz".to_string(),
+            checksum: "arm64sum".to_string(),
+        });
+        let build_env = Sbuild::new(pkg_config, "/tmp/hello-world-1.0.0".to_string());
+
+        assert_eq!(build_env.get_cache_file(), "/cache/bookworm-amd64.tar.gz");
+        let deps = build_env.get_build_deps_based_on_langenv(&LanguageEnv::Go(go));
+        assert!(deps.contains(&"cd /tmp && curl -o go.tar.gz -L https://go.dev/dl/go1.22.0.linux-amd64.tar.gz".to_string()));
+
+        // arm64 packages cannot be installed in the amd64 chroot, so nothing is run
+        command_runner::set_runner(Rc::new(ReplayCommandRunner::new(vec![])));
+        assert!(build_env.run_piuparts().is_ok());
+    }
+
+    #[test]
+    fn test_cross_build_passes_host_and_build_arch_to_sbuild() {
+        setup();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = "bookworm".to_string();
+        pkg_config.build_env.arch = "arm64".to_string();
+        pkg_config.build_env.build_arch = Some("amd64".to_string());
+        pkg_config.build_env.sbuild_cache_dir = Some("/cache".to_string());
+        pkg_config.package_fields.source_date_epoch = Some(1704067200);
+        pkg_config.package_type = PackageType::Default(DefaultPackageTypeConfig {
+            language_env: LanguageEnv::Rust(RustConfig {
+                rust_version: "1.77.2".to_string(),
+                rust_binary_url: "https://static.rust-lang.org/dist/rust-1.77.2-x86_64-unknown-linux-gnu.tar.xz".to_string(),
+                rust_binary_gpg_asc: "asc".to_string(),
+                ..Default::default()
+            }),
+            ..Default::default()
+        });
+        let build_env = Sbuild::new(pkg_config, "/tmp/packages/hello-world-1.0.0".to_string());
+        let deps = build_env.get_build_deps_not_in_debian();
+        let mut args: Vec<String> = vec![
+            "-d", "bookworm", "-A", "-s", "--source-only-changes", "-c", "/cache/bookworm-amd64.tar.gz", "-v",
+            "--chroot-mode=unshare", "--host=arm64", "--build=amd64",
+        ].into_iter().map(|arg| arg.to_string()).collect();
+        args.extend(deps.iter().map(|dep| format!("--chroot-setup-commands={}", dep)));
+        args.extend(["--no-run-lintian", "--no-run-piuparts", "--no-run-autopkgtest", "--no-apt-upgrade", "--no-apt-distupgrade"]
+            .into_iter().map(|arg| arg.to_string()));
+        let runner = Rc::new(ReplayCommandRunner::new(vec![Invocation {
+            program: "sbuild".to_string(),
+            args,
+            ..Default::default()
+        }]));
+        command_runner::set_runner(runner.clone());
+        let result = build_env.package();
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(runner.remaining().is_empty());
+        // the standard library of the host architecture is installed next to the toolchain
+        assert!(deps.contains(&"cd /tmp && curl -o rust-std.tar.xz -L \
+            https://static.rust-lang.org/dist/rust-std-1.77.2-aarch64-unknown-linux-gnu.tar.xz".to_string()));
+    }
+
+    #[test]
+    fn test_dotnet_repo_of_distribution() {
+        setup();
+        let dotnet = LanguageEnv::Dotnet(DotnetConfig {
+            dotnet_version: "8.0".to_string(),
+            dotnet_full_version: "8.0.100-1".to_string(),
+        });
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = "jammy".to_string();
+        let build_env = Sbuild::new(pkg_config.clone(), "/tmp/hello-world-1.0.0".to_string());
+        assert!(build_env.get_build_deps_based_on_langenv(&dotnet)
+            .contains(&"cd /tmp && wget https://packages.microsoft.com/config/ubuntu/22.04/packages-microsoft-prod.deb -O packages-microsoft-prod.deb".to_string()));
+
+        pkg_config.build_env.codename = "sid".to_string();
+        pkg_config.distributions = vec![Distribution { codename: "sid".to_string(), suite: "sid".to_string(), ..Default::default() }];
+        let build_env = Sbuild::new(pkg_config, "/tmp/hello-world-1.0.0".to_string());
+        assert!(build_env.get_build_deps_based_on_langenv(&dotnet).is_empty());
+    }
+
+    #[test]
+    fn test_version_suffix_in_file_names() {
+        setup();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.package_fields.package_name = "hello-world".to_string();
+        pkg_config.package_fields.version_number = "1.0.0".to_string();
+        pkg_config.package_fields.revision_number = "1".to_string();
+        pkg_config.package_fields.version_suffix = Some(true);
+        pkg_config.build_env.codename = "jammy jellyfish".to_string();
+        pkg_config.build_env.arch = "amd64".to_string();
+        let deb_dir = tempdir().unwrap();
+        let build_files_dir = deb_dir.path().join("hello-world-1.0.0").to_str().unwrap().to_string();
+        let build_env = Sbuild::new(pkg_config, build_files_dir);
+
+        assert_eq!(build_env.get_version(), "1.0.0-1~jammy1");
+        assert_eq!(build_env.get_deb_name(), deb_dir.path().join("hello-world_1.0.0-1~jammy1_amd64.deb"));
+        assert_eq!(build_env.get_changes_file(), deb_dir.path().join("hello-world_1.0.0-1~jammy1_amd64.changes"));
+
+        let verify_config = PkgVerifyConfig {
+            verify: VerifyConfig {
+                package_hash: vec![PackageHash { name: "hello-world_1.0.0-1_amd64.deb".to_string(), hash: "abc".to_string() }],
+            },
+        };
+        let err = build_env.verify(verify_config).unwrap_err().to_string();
+        assert_eq!(err, "File to be verified does not exist hello-world_1.0.0-1_amd64.deb, the packages are built with version 1.0.0-1~jammy1");
+    }
+
+    #[test]
+    fn test_autopkgtest_fails_without_image() {
+        setup();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = "bookworm".to_string();
+        pkg_config.build_env.arch = "amd64".to_string();
+        pkg_config.build_env.autopkgtest_version = "5.28".to_string();
+        let sbuild_cache = tempdir().unwrap();
+        pkg_config.build_env.sbuild_cache_dir = Some(sbuild_cache.path().to_str().unwrap().to_string());
+        let runner = Rc::new(ReplayCommandRunner::new(vec![Invocation {
+            program: "apt".to_string(),
+            args: vec!["list".to_string(), "--installed".to_string(), "autopkgtest".to_string()],
+            stdout: "Listing...\nautopkgtest/stable,now 5.28 all [installed]\n".to_string(),
+            ..Default::default()
+        }]));
+        command_runner::set_runner(runner.clone());
+        let build_env = Sbuild::new(pkg_config, "/tmp/hello-world-1.0.0".to_string());
+        let err = build_env.run_autopkgtests().unwrap_err().to_string();
+        assert!(err.contains("autopkgtest-bookworm-amd64.img does not exist"), "{}", err);
+    }
+
+    #[test]
+    fn test_verify_checks_changes_checksums() {
+        setup();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.package_fields.package_name = "hello-world".to_string();
+        pkg_config.build_env.arch = "amd64".to_string();
+        let deb_dir = tempdir().unwrap();
+        let deb = "hello-world_1.0.0-1_amd64.deb";
+        fs::write(deb_dir.path().join(deb), "deb").unwrap();
+        let deb_sha1 = calculate_sha1("deb".as_bytes()).unwrap();
+        let deb_sha256 = calculate_sha256("deb".as_bytes()).unwrap();
+        let changes_file = deb_dir.path().join("hello-world_1.0.0-1_amd64.changes");
+        let changes = format!("Source: hello-world\nArchitecture: amd64\nChecksums-Sha256:\n {} 3 {}\nFiles:\n 1d 3 misc optional {}\n",
+                              deb_sha256, deb, deb);
+        fs::write(&changes_file, &changes).unwrap();
+        let build_files_dir = deb_dir.path().join("hello-world-1.0.0").to_str().unwrap().to_string();
+        let build_env = Sbuild::new(pkg_config, build_files_dir);
+        assert_eq!(build_env.get_changes_file(), changes_file);
+        let verify_config = PkgVerifyConfig {
+            verify: VerifyConfig {
+                package_hash: vec![PackageHash { name: deb.to_string(), hash: deb_sha1 }],
+            },
+        };
+        assert!(build_env.verify(verify_config.clone()).is_ok());
+
+        fs::write(&changes_file, changes.replace(&deb_sha256, "0000")).unwrap();
+        let err = build_env.verify(verify_config).unwrap_err().to_string();
+        assert!(err.contains(&format!("file {} actual sha256 is {}, but 0000 was recorded", deb, deb_sha256)), "{}", err);
+    }
+
+    #[test]
+    fn test_package_fails_when_sbuild_fails() {
+        setup();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = "bookworm".to_string();
+        pkg_config.build_env.arch = "amd64".to_string();
+        pkg_config.build_env.sbuild_cache_dir = Some("/tmp/sbuild-cache".to_string());
+        let runner = Rc::new(ReplayCommandRunner::new(vec![Invocation {
+            program: "sbuild".to_string(),
+            args: vec!["*".to_string(); 14],
+            exit_code: 2,
+            ..Default::default()
+        }]));
+        command_runner::set_runner(runner.clone());
+        let build_env = Sbuild::new(pkg_config, "/tmp/packages/hello-world-1.0.0".to_string());
+        let err = build_env.package().unwrap_err();
+        assert!(err.to_string().contains("sbuild exited with non-zero status code"));
+    }
+
+    #[test]
+    fn test_package_fails_when_chroot_does_not_match_tarball_hash() {
+        setup();
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = "bookworm".to_string();
+        pkg_config.build_env.arch = "amd64".to_string();
+        let sbuild_cache = tempdir().unwrap();
+        pkg_config.build_env.sbuild_cache_dir = Some(sbuild_cache.path().to_str().unwrap().to_string());
+        pkg_config.build_env.chroot = Some(ChrootConfig {
+            creator: ChrootCreator::Mmdebstrap,
+            tarball_hash: Some("abc".to_string()),
+            ..Default::default()
+        });
+        let build_env = Sbuild::new(pkg_config, "/tmp/packages/hello-world-1.0.0".to_string());
+        fs::write(build_env.get_cache_file(), "stale chroot").unwrap();
+        let runner = Rc::new(ReplayCommandRunner::new(vec![]));
+        command_runner::set_runner(runner);
+        let err = build_env.package().unwrap_err();
+        assert!(err.to_string().contains("but tarball_hash is abc"));
+    }
+
+    fn build_env_with_cache_file() -> (Sbuild, tempfile::TempDir) {
+        let mut pkg_config = PkgConfig::default();
+        pkg_config.build_env.codename = "bookworm".to_string();
+        pkg_config.build_env.arch = "amd64".to_string();
+        let sbuild_cache = tempdir().unwrap();
+        pkg_config.build_env.sbuild_cache_dir = Some(sbuild_cache.path().to_str().unwrap().to_string());
+        let build_env = Sbuild::new(pkg_config, "/tmp/packages/hello-world-1.0.0".to_string());
+        fs::write(build_env.get_cache_file(), "chroot").unwrap();
+        (build_env, sbuild_cache)
+    }
+
+    #[test]
+    fn test_info_lists_installed_packages() {
+        setup();
+        let (build_env, _sbuild_cache) = build_env_with_cache_file();
+        let runner = Rc::new(ReplayCommandRunner::new(vec![Invocation {
+            program: "tar".to_string(),
+            args: vec!["--extract", "--to-stdout", "--file", &build_env.get_cache_file(), "--wildcards", "--no-anchored", "var/lib/dpkg/status"]
+                .into_iter().map(|arg| arg.to_string()).collect(),
+            stdout: "Package: bash\nStatus: install ok installed\nArchitecture: amd64\nVersion: 5.2.15-2+b2\n".to_string(),
+            ..Default::default()
+        }]));
+        command_runner::set_runner(runner);
+        let packages = build_env.info().unwrap();
+        assert_eq!(packages, vec![InstalledPackage {
+            name: "bash".to_string(),
+            version: "5.2.15-2+b2".to_string(),
+            arch: "amd64".to_string(),
+        }]);
+    }
+
+    #[test]
+    fn test_update_upgrades_chroot_in_user_namespace() {
+        setup();
+        let (build_env, _sbuild_cache) = build_env_with_cache_file();
+        let runner = Rc::new(ReplayCommandRunner::new(vec![
+            Invocation {
+                program: "unshare".to_string(),
+                args: vec!["--map-root-user".to_string(), "--map-auto".to_string(), "true".to_string()],
+                ..Default::default()
+            },
+            Invocation {
+                program: "unshare".to_string(),
+                args: vec!["--map-root-user", "--map-auto", "--mount", "--pid", "--fork", "sh", "-c", "*", "sh", &build_env.get_cache_file()]
+                    .into_iter().map(|arg| arg.to_string()).collect(),
+                ..Default::default()
+            },
+        ]));
+        command_runner::set_runner(runner.clone());
+        let result = build_env.update();
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(runner.remaining().is_empty());
+        assert!(Path::new(&format!("{}.sha256", build_env.get_cache_file())).exists());
+
+        build_env.clean().unwrap();
+        assert!(!Path::new(&format!("{}.sha256", build_env.get_cache_file())).exists());
+    }
+
+    #[test]
+    fn test_update_falls_back_to_root_without_user_namespaces() {
+        setup();
+        let (build_env, _sbuild_cache) = build_env_with_cache_file();
+        let update_args: Vec<String> = vec!["--mount", "--pid", "--fork", "sh", "-c", "*", "sh", &build_env.get_cache_file()]
+            .into_iter().map(|arg| arg.to_string()).collect();
+        let mut invocations = vec![Invocation {
+            program: "unshare".to_string(),
+            args: vec!["--map-root-user".to_string(), "--map-auto".to_string(), "true".to_string()],
+            // util-linux before 2.38 does not know --map-auto
+            exit_code: 1,
+            ..Default::default()
+        }];
+        if is_root() {
+            invocations.push(Invocation { program: "unshare".to_string(), args: update_args, ..Default::default() });
+        } else {
+            invocations.push(Invocation {
+                program: "sudo".to_string(),
+                args: vec!["-n".to_string(), "true".to_string()],
+                ..Default::default()
+            });
+            invocations.push(Invocation {
+                program: "sudo".to_string(),
+                args: ["-n".to_string(), "unshare".to_string()].into_iter().chain(update_args).collect(),
+                ..Default::default()
+            });
+        }
+        let runner = Rc::new(ReplayCommandRunner::new(invocations));
+        command_runner::set_runner(runner.clone());
+        let result = build_env.update();
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(runner.remaining().is_empty());
     }
 }
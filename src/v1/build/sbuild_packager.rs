@@ -1,11 +1,15 @@
+use crate::v1::build::archive::{repack_as_orig, ArchiveFormat, Compression};
+use crate::v1::build::dry_run;
+use crate::v1::build::git_cache::GitCache;
 use crate::v1::build::sbuild::Sbuild;
+use crate::v1::distribution::resolve_distribution;
 use crate::v1::packager::{BackendBuildEnv, Packager};
 
-use eyre::{Result};
+use eyre::{eyre, Result};
 
 use crate::v1::pkg_config::{PackageType, PkgConfig};
 use log::info;
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 use crate::v1::build::dir_setup::{*};
 
 pub struct SbuildPackager {
@@ -13,60 +17,34 @@ pub struct SbuildPackager {
     source_to_patch_from_path: String,
     debian_artifacts_dir: String,
     debian_orig_tarball_path: String,
+    upstream_tarball_path: String,
     build_files_dir: String,
     config_root: String,
 }
 
-impl Packager for SbuildPackager {
-    type BuildEnv = Sbuild;
-
-    fn new(config: PkgConfig, config_root: String) -> Self {
-        let package_fields = config.package_fields.clone();
-        let config_root_path = PathBuf::from(&config_root);
-        let source_to_patch_from_path = config_root_path.join("src").to_str().unwrap().to_string();
-        let workdir = config
-            .build_env
-            .workdir
-            .clone()
-            .unwrap_or(format!("~/.pkg-builder/packages/{}", config.build_env.codename));
-        let workdir = expand_path(&workdir, None);
-        let debian_artifacts_dir = get_build_artifacts_dir(&package_fields.package_name, &workdir, &package_fields.version_number, &package_fields.revision_number);
-        let debian_orig_tarball_path = get_tarball_path(
-            &package_fields.package_name,
-            &package_fields.version_number,
-            &debian_artifacts_dir,
-        );
-        let build_files_dir = get_build_files_dir(
-            &package_fields.package_name,
-            &package_fields.version_number,
-            &debian_artifacts_dir,
-        );
-        let mut updated_config = SbuildPackager {
-            config,
-            source_to_patch_from_path,
-            build_files_dir,
-            debian_artifacts_dir,
-            debian_orig_tarball_path,
-            config_root,
-        };
-        updated_config.config.build_env.workdir = Some(workdir);
-        let spec_file = package_fields.spec_file;
-        let spec_file_canonical = config_root_path.join(spec_file);
-        updated_config.config.package_fields.spec_file =
-            spec_file_canonical.to_str().unwrap().to_string();
-        updated_config
-    }
-
-    fn package(&self) -> Result<()> {
-        let pre_build: Result<()> = match &self.config.package_type {
+impl SbuildPackager {
+    /// Downloads, extracts and patches the source, shared by every build backend.
+    pub fn prepare_source(&self) -> Result<()> {
+        let suite = resolve_distribution(&self.config)?.suite;
+        let version_suffix = self.config.package_fields.get_version_suffix(&suite);
+        match &self.config.package_type {
             PackageType::Default(config) => {
                 create_package_dir(&self.debian_artifacts_dir.clone())?;
                 download_source(
-                    &self.debian_orig_tarball_path,
+                    &self.upstream_tarball_path,
                     &config.tarball_url,
                     &self.config_root,
+                    config.tarball_hash.as_deref(),
+                    &self.config.build_env.get_download_cache_dir(),
                 )?;
-                verify_hash(&self.debian_orig_tarball_path, config.tarball_hash.clone())?;
+                verify_hash(&self.upstream_tarball_path, config.tarball_hash.clone())?;
+                if !dry_run::is_enabled() {
+                    check_archive_format(&self.upstream_tarball_path, &config.tarball_url, get_source_format(&self.config.package_type))?;
+                }
+                if self.upstream_tarball_path != self.debian_orig_tarball_path {
+                    info!("Repacking {} as {}", self.upstream_tarball_path, self.debian_orig_tarball_path);
+                    repack_as_orig(Path::new(&self.upstream_tarball_path), Path::new(&self.debian_orig_tarball_path))?;
+                }
                 extract_source(&self.debian_orig_tarball_path, &self.build_files_dir)?;
                 create_debian_dir(
                     &self.build_files_dir.clone(),
@@ -77,8 +55,8 @@ impl Packager for SbuildPackager {
                     &self.build_files_dir.clone(),
                     &self.config.package_fields.homepage,
                     &self.source_to_patch_from_path,
+                    version_suffix.as_deref(),
                 )?;
-                setup_sbuild()?;
                 Ok(())
             }
             PackageType::Git(config) => {
@@ -87,9 +65,10 @@ impl Packager for SbuildPackager {
                     &self.debian_artifacts_dir,
                     &self.debian_orig_tarball_path,
                     &self.config.package_fields.package_name,
-                    &config.git_url,
-                    &config.git_tag,
-                    &config.submodules,
+                    config,
+                    &self.config_root,
+                    &GitCache::new(&self.config.build_env.get_git_cache_dir(), self.config.build_env.git_offline.unwrap_or_default()),
+                    self.config.package_fields.get_source_date_epoch()?,
                 )?;
                 extract_source(&self.debian_orig_tarball_path, &self.build_files_dir)?;
                 create_debian_dir(
@@ -101,14 +80,14 @@ impl Packager for SbuildPackager {
                     &self.build_files_dir.clone(),
                     &self.config.package_fields.homepage,
                     &self.source_to_patch_from_path,
+                    version_suffix.as_deref(),
                 )?;
-                setup_sbuild()?;
                 Ok(())
             }
             PackageType::Virtual => {
                 info!("creating virtual package");
                 create_package_dir(&self.debian_artifacts_dir.clone())?;
-                create_empty_tar(&self.debian_artifacts_dir, &self.debian_orig_tarball_path)?;
+                create_empty_tar(&self.debian_orig_tarball_path)?;
                 extract_source(&self.debian_orig_tarball_path, &self.build_files_dir)?;
                 create_debian_dir(
                     &self.build_files_dir.clone(),
@@ -119,20 +98,146 @@ impl Packager for SbuildPackager {
                     &self.build_files_dir.clone(),
                     &self.config.package_fields.homepage,
                     &self.source_to_patch_from_path,
+                    version_suffix.as_deref(),
                 )?;
-                setup_sbuild()?;
                 Ok(())
             }
+        }
+    }
+
+    pub fn get_config(&self) -> &PkgConfig {
+        &self.config
+    }
+
+    pub fn get_build_files_dir(&self) -> &str {
+        &self.build_files_dir
+    }
+
+}
+
+impl SbuildPackager {
+    pub fn new(config: PkgConfig, config_root: String) -> Self {
+        let package_fields = config.package_fields.clone();
+        let config_root_path = PathBuf::from(&config_root);
+        let source_to_patch_from_path = config_root_path.join("src").to_str().unwrap().to_string();
+        let workdir = config
+            .build_env
+            .workdir
+            .clone()
+            .unwrap_or(format!("~/.pkg-builder/packages/{}", config.build_env.codename));
+        let workdir = expand_path(&workdir, None);
+        let debian_artifacts_dir = get_build_artifacts_dir(&package_fields.package_name, &workdir, &package_fields.version_number, &package_fields.revision_number);
+        let source_format = get_source_format(&config.package_type);
+        let debian_orig_tarball_path = get_tarball_path(
+            &package_fields.package_name,
+            &package_fields.version_number,
+            &debian_artifacts_dir,
+            source_format.get_orig_compression(),
+        );
+        let upstream_tarball_path = if source_format.needs_repack() {
+            get_upstream_tarball_path(
+                &package_fields.package_name,
+                &package_fields.version_number,
+                &debian_artifacts_dir,
+                source_format,
+            )
+        } else {
+            debian_orig_tarball_path.clone()
+        };
+        let build_files_dir = get_build_files_dir(
+            &package_fields.package_name,
+            &package_fields.version_number,
+            &debian_artifacts_dir,
+        );
+        let mut updated_config = SbuildPackager {
+            config,
+            source_to_patch_from_path,
+            build_files_dir,
+            debian_artifacts_dir,
+            debian_orig_tarball_path,
+            upstream_tarball_path,
+            config_root,
         };
-        pre_build?;
-        let build_env = self.get_build_env().unwrap();
+        updated_config.config.build_env.workdir = Some(workdir);
+        let spec_file = package_fields.spec_file;
+        let spec_file_canonical = config_root_path.join(spec_file);
+        updated_config.config.package_fields.spec_file =
+            spec_file_canonical.to_str().unwrap().to_string();
+        updated_config
+    }
+}
+
+/// Format of the upstream source, by the extension of the tarball url, gzip if it has none.
+fn get_source_format(package_type: &PackageType) -> ArchiveFormat {
+    match package_type {
+        PackageType::Default(config) => {
+            ArchiveFormat::from_file_name(&config.tarball_url).unwrap_or(ArchiveFormat::Tar(Compression::Gzip))
+        }
+        PackageType::Git(_) | PackageType::Virtual => ArchiveFormat::Tar(Compression::Gzip),
+    }
+}
+
+/// The orig tarball is named by the extension of the url, so the content has to match it.
+fn check_archive_format(tarball_path: &str, tarball_url: &str, expected: ArchiveFormat) -> Result<()> {
+    let actual = ArchiveFormat::detect(Path::new(tarball_path))?;
+    if actual != expected {
+        return Err(eyre!(
+            "{} is a .{} archive, but tarball_url is named like a .{}, please fix the extension of tarball_url",
+            tarball_url,
+            actual.name(),
+            expected.name()
+        ));
+    }
+    Ok(())
+}
+
+impl Packager for SbuildPackager {
+    fn package(&self) -> Result<()> {
+        self.prepare_source()?;
+        let build_env = Sbuild::new(self.config.clone(), self.build_files_dir.clone());
+        setup_sbuild(build_env.get_source_date_epoch()?)?;
         build_env.package()?;
         Ok(())
     }
 
-    fn get_build_env(&self) -> Result<Self::BuildEnv> {
+    fn get_build_env(&self) -> Result<Box<dyn BackendBuildEnv>> {
         let backend_build_env = Sbuild::new(self.config.clone(), self.build_files_dir.clone());
-        Ok(backend_build_env)
+        Ok(Box::new(backend_build_env))
     }
 }
 
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::build::dry_run::{self, PlanStep};
+    use crate::v1::pkg_config::get_config;
+    use tempfile::tempdir;
+
+    #[test]
+    fn test_dry_run_package_does_not_touch_workdir() {
+        let config_root = "examples/bookworm/virtual/hello-world";
+        let mut config: PkgConfig = get_config(format!("{}/pkg-builder.toml", config_root)).unwrap();
+        let temp_dir = tempdir().unwrap();
+        let workdir = temp_dir.path().join("packages");
+        config.build_env.workdir = Some(workdir.to_str().unwrap().to_string());
+        let packager = SbuildPackager::new(config, config_root.to_string());
+
+        dry_run::enable();
+        let result = packager.package();
+        let plan = dry_run::take_plan().unwrap();
+
+        assert!(result.is_ok(), "{:?}", result);
+        assert!(!workdir.exists());
+        assert_eq!(plan.steps[0], PlanStep::CreateDir {
+            path: workdir.join("test-virtual-package-1.0.0-1").to_str().unwrap().to_string(),
+        });
+        let programs: Vec<&str> = plan.steps.iter().filter_map(|step| match step {
+            PlanStep::RunCommand { program, .. } => Some(program.as_str()),
+            _ => None,
+        }).collect();
+        assert!(programs.contains(&"debcrafter"));
+        assert!(programs.contains(&"sbuild"));
+        assert!(programs.contains(&"lintian"));
+    }
+}
@@ -1,11 +1,19 @@
-use super::args::{ActionType, BuildEnvSubCommand, PkgBuilderArgs};
+use super::args::{ActionType, BuildEnvSubCommand, GitSubCommand, PackageCommand, PkgBuilderArgs, PlanFormat};
+use super::build::command_runner::{self, RecordingCommandRunner, ReplayCommandRunner};
+use super::build::dry_run;
+use super::build::dir_setup::expand_path;
+use super::build::env_info::{format_env_table, format_packages};
+use super::git_lock::lock_submodules;
+use super::matrix::{format_matrix_summary, get_entry_verify_config, get_matrix_entries, MatrixResult};
 use super::packager::DistributionPackager;
+use crate::v1::distribution::resolve_distribution;
 use crate::v1::pkg_config::{get_config, PkgConfig};
 use clap::Parser;
 use env_logger::Env;
 use eyre::{eyre, Result};
 use std::{env, fs, path::Path};
 use std::process::Command;
+use std::rc::Rc;
 use cargo_metadata::semver;
 use log::{error, info, warn};
 use crate::v1::pkg_config_verify::PkgVerifyConfig;
@@ -19,20 +27,51 @@ const VERIFY_CONFIG_FILE_NAME: &str = "pkg-builder-verify.toml";
 pub fn run_cli() -> Result<()> {
     let args = PkgBuilderArgs::parse();
     env_logger::Builder::from_env(Env::default().default_filter_or("info")).init();
+    let replay_runner = set_command_runner_from_env()?;
+    let dry_run_args = args.action.get_dry_run_args().cloned();
+    if let Some(true) = dry_run_args.as_ref().map(|dry_run_args| dry_run_args.dry_run) {
+        dry_run::enable();
+    }
+    let result = run_action(args.action);
+    if let (Some(replay_runner), true) = (replay_runner, result.is_ok()) {
+        for invocation in replay_runner.remaining() {
+            warn!("Command of the replay fixture was not called: {}", invocation.command_line());
+        }
+    }
+    // the plan up to a failing step is printed as well, it shows where the run stopped
+    if let (Some(plan), Some(dry_run_args)) = (dry_run::take_plan(), dry_run_args) {
+        match dry_run_args.plan_format {
+            PlanFormat::Text => print!("{}", plan.to_text()),
+            PlanFormat::Json => println!("{}", plan.to_json()),
+        }
+    }
+    result
+}
+
+fn run_action(action: ActionType) -> Result<()> {
     let program_name: &str = env!("CARGO_PKG_NAME");
     let program_version: &str = env!("CARGO_PKG_VERSION");
-    match args.action {
+    match action {
         ActionType::Verify(command) => {
             let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
             let config = get_config::<PkgConfig>(config_file.clone())?;
 
             fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
 
-            let distribution = get_distribution(config, config_file)?;
-            let verify_config_file = get_config_file(command.verify_config, VERIFY_CONFIG_FILE_NAME)?;
-            let verify_config_file = get_config::<PkgVerifyConfig>(verify_config_file.clone())?;
             let no_package = command.no_package.unwrap_or_default();
-            distribution.verify(verify_config_file, !no_package)?;
+            let is_matrix = config.matrix.is_some();
+            run_entries(&config, |config| {
+                let verify_config_file = if is_matrix {
+                    let suite = resolve_distribution(&config)?.suite;
+                    let entry_verify_config = get_entry_verify_config(command.verify_config.as_deref(), &suite, &config.build_env.arch);
+                    get_config_file(Some(entry_verify_config), VERIFY_CONFIG_FILE_NAME)?
+                } else {
+                    get_config_file(command.verify_config.clone(), VERIFY_CONFIG_FILE_NAME)?
+                };
+                let distribution = get_distribution(config, config_file.clone())?;
+                let verify_config_file = get_config::<PkgVerifyConfig>(verify_config_file)?;
+                distribution.verify(verify_config_file, !no_package)
+            })?;
         }
         ActionType::Lintian(command) => {
             let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
@@ -40,59 +79,113 @@ pub fn run_cli() -> Result<()> {
 
             fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
 
-            let distribution = get_distribution(config, config_file)?;
-            distribution.run_lintian()?;
+            run_entries(&config, |config| get_distribution(config, config_file.clone())?.run_lintian())?;
         }
         ActionType::Piuparts(command) => {
             let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
             let config = get_config::<PkgConfig>(config_file.clone())?;
             fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
 
-            let distribution = get_distribution(config, config_file)?;
-            distribution.run_piuparts()?;
+            run_entries(&config, |config| get_distribution(config, config_file.clone())?.run_piuparts())?;
         }
         ActionType::Autopkgtest(command) => {
             let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
             let config = get_config::<PkgConfig>(config_file.clone())?;
             fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
 
-            let distribution = get_distribution(config, config_file)?;
-            distribution.run_autopkgtests()?;
+            run_entries(&config, |config| get_distribution(config, config_file.clone())?.run_autopkgtests())?;
         }
         ActionType::Package(command) => {
-            let config_file = get_config_file(command.config, CONFIG_FILE_NAME)?;
-            let mut config = get_config::<PkgConfig>(config_file.clone())?;
+            let config_file = get_config_file(command.config.clone(), CONFIG_FILE_NAME)?;
+            let config = get_config::<PkgConfig>(config_file.clone())?;
             fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
 
-            check_sbuild_version(config.build_env.sbuild_version.clone())?;
-            if let Some(run_piuparts) = command.run_piuparts {
-                config.build_env.run_piuparts = Some(run_piuparts);
-            }
-            if let Some(run_autopkgttests) = command.run_autopkgtest {
-                config.build_env.run_autopkgtest = Some(run_autopkgttests);
-            }
-            if let Some(run_lintian) = command.run_lintian {
-                config.build_env.run_lintian = Some(run_lintian);
-            }
-            let distribution = get_distribution(config, config_file)?;
-            distribution.package()?;
+            run_entries(&config, |config| package(config, config_file.clone(), &command))?;
         }
         ActionType::Env(build_env_action) => {
+            // every entry of the matrix builds in its own build env
             match build_env_action.build_env_sub_command {
                 BuildEnvSubCommand::Create(sub_command) => {
                     let config_file = get_config_file(sub_command.config, CONFIG_FILE_NAME)?;
                     let config = get_config::<PkgConfig>(config_file.clone())?;
                     fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
 
-                    let distribution = get_distribution(config, config_file)?;
-                    distribution.create_build_env()?;
+                    for entry in get_matrix_entries(&config)? {
+                        let mut config = entry.config;
+                        if sub_command.with_autopkgtest {
+                            config.build_env.run_autopkgtest = Some(true);
+                        }
+                        let distribution = get_distribution(config, config_file.clone())?;
+                        distribution.create_build_env()?;
+                    }
                 }
                 BuildEnvSubCommand::Clean(sub_command) => {
                     let config_file = get_config_file(sub_command.config, CONFIG_FILE_NAME)?;
                     let config = get_config::<PkgConfig>(config_file.clone())?;
                     fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
-                    let distribution = get_distribution(config, config_file)?;
-                    distribution.clean_build_env()?;
+                    for entry in get_matrix_entries(&config)? {
+                        let distribution = get_distribution(entry.config, config_file.clone())?;
+                        distribution.clean_build_env()?;
+                    }
+                }
+                BuildEnvSubCommand::List(sub_command) => {
+                    let config_file = get_config_file(sub_command.config, CONFIG_FILE_NAME)?;
+                    let config = get_config::<PkgConfig>(config_file.clone())?;
+                    fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+                    // the build envs of all entries are listed from the same cache dir or container runtime
+                    let entry = get_matrix_entries(&config)?.remove(0);
+                    let distribution = get_distribution(entry.config, config_file)?;
+                    let envs = distribution.list_build_envs()?;
+                    print!("{}", format_env_table(&envs));
+                }
+                BuildEnvSubCommand::Update(sub_command) => {
+                    let config_file = get_config_file(sub_command.config, CONFIG_FILE_NAME)?;
+                    let config = get_config::<PkgConfig>(config_file.clone())?;
+                    fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+                    for entry in get_matrix_entries(&config)? {
+                        let distribution = get_distribution(entry.config, config_file.clone())?;
+                        distribution.update_build_env()?;
+                    }
+                }
+                BuildEnvSubCommand::Info(sub_command) => {
+                    let config_file = get_config_file(sub_command.config, CONFIG_FILE_NAME)?;
+                    let config = get_config::<PkgConfig>(config_file.clone())?;
+                    fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+                    for entry in get_matrix_entries(&config)? {
+                        let distribution = get_distribution(entry.config, config_file.clone())?;
+                        let packages = distribution.build_env_info()?;
+                        if !dry_run::is_enabled() {
+                            if config.matrix.is_some() {
+                                println!("{} {}:", entry.codename, entry.arch);
+                            }
+                            print!("{}", format_packages(&packages));
+                        }
+                    }
+                }
+                BuildEnvSubCommand::Rebuild(sub_command) => {
+                    let config_file = get_config_file(sub_command.config, CONFIG_FILE_NAME)?;
+                    let config = get_config::<PkgConfig>(config_file.clone())?;
+                    fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+                    for entry in get_matrix_entries(&config)? {
+                        let mut config = entry.config;
+                        if sub_command.with_autopkgtest {
+                            config.build_env.run_autopkgtest = Some(true);
+                        }
+                        let distribution = get_distribution(config, config_file.clone())?;
+                        distribution.clean_build_env()?;
+                        distribution.create_build_env()?;
+                    }
+                }
+            };
+        }
+        ActionType::Git(git_command) => {
+            match git_command.git_sub_command {
+                GitSubCommand::Lock(sub_command) => {
+                    let config_file = get_config_file(sub_command.config, CONFIG_FILE_NAME)?;
+                    let config = get_config::<PkgConfig>(config_file.clone())?;
+                    fail_compare_versions(config.build_env.pkg_builder_version.clone(), program_version, program_name)?;
+                    let submodules = lock_submodules(&config, &config_file, &get_config_root(&config_file)?)?;
+                    info!("Pinned {} submodules in {}", submodules.len(), config_file);
                 }
             };
         }
@@ -103,17 +196,91 @@ pub fn run_cli() -> Result<()> {
     Ok(())
 }
 
+/// Runs `action` for the config, or for every entry of its matrix, even if one of them fails,
+/// followed by a summary of the entries.
+fn run_entries(config: &PkgConfig, mut action: impl FnMut(PkgConfig) -> Result<()>) -> Result<()> {
+    if config.matrix.is_none() {
+        return action(config.clone());
+    }
+    let mut results = vec![];
+    for entry in get_matrix_entries(config)? {
+        info!("Running matrix entry {} {}", entry.codename, entry.arch);
+        let workdir = entry.config.build_env.workdir.clone().unwrap_or_default();
+        let error = action(entry.config).err().map(|err| {
+            error!("Matrix entry {} {} failed: {}", entry.codename, entry.arch, err);
+            err.to_string()
+        });
+        results.push(MatrixResult {
+            codename: entry.codename,
+            arch: entry.arch,
+            workdir: expand_path(&workdir, None),
+            error,
+        });
+    }
+    print!("{}", format_matrix_summary(&results));
+    let failed = results.iter().filter(|result| result.error.is_some()).count();
+    if failed > 0 {
+        return Err(eyre!("{} of {} matrix entries failed", failed, results.len()));
+    }
+    Ok(())
+}
+
+fn package(mut config: PkgConfig, config_file: String, command: &PackageCommand) -> Result<()> {
+    if config.build_env.get_backend() == "sbuild" {
+        check_sbuild_version(config.build_env.sbuild_version.clone())?;
+    }
+    if let Some(run_piuparts) = command.run_piuparts {
+        config.build_env.run_piuparts = Some(run_piuparts);
+    }
+    if let Some(run_autopkgttests) = command.run_autopkgtest {
+        config.build_env.run_autopkgtest = Some(run_autopkgttests);
+    }
+    if let Some(run_lintian) = command.run_lintian {
+        config.build_env.run_lintian = Some(run_lintian);
+    }
+    if let Some(git_offline) = command.git_offline {
+        config.build_env.git_offline = Some(git_offline);
+    }
+    let distribution = get_distribution(config, config_file)?;
+    distribution.package()
+}
+
+/// Records the executed commands to, or replays them from, the fixture file given by
+/// PKG_BUILDER_RECORD_COMMANDS or PKG_BUILDER_REPLAY_COMMANDS.
+/// The home directory is stored as ${HOME}, so fixtures can be shared between machines.
+fn set_command_runner_from_env() -> Result<Option<Rc<ReplayCommandRunner>>> {
+    let home = env::var("HOME").unwrap_or_default();
+    if let Ok(fixture_file) = env::var("PKG_BUILDER_RECORD_COMMANDS") {
+        info!("Recording commands to {}", fixture_file);
+        let mut recording_runner = RecordingCommandRunner::new(fixture_file);
+        if !home.is_empty() {
+            recording_runner = recording_runner.with_variable("HOME", &home);
+        }
+        command_runner::set_runner(Rc::new(recording_runner));
+    } else if let Ok(fixture_file) = env::var("PKG_BUILDER_REPLAY_COMMANDS") {
+        info!("Replaying commands from {}", fixture_file);
+        let replay_runner = Rc::new(ReplayCommandRunner::from_file(&fixture_file, &[("HOME", &home)])
+            .map_err(|err| eyre!("Could not read fixture {}: {}", fixture_file, err))?);
+        command_runner::set_runner(replay_runner.clone());
+        return Ok(Some(replay_runner));
+    }
+    Ok(None)
+}
+
 pub fn check_sbuild_version(expected_version: String) -> Result<()> {
-    let output = Command::new("sbuild")
-        .arg("--version")
-        .output()?;
+    let mut cmd = Command::new("sbuild");
+    cmd.arg("--version");
+    if dry_run::record_command(&cmd) {
+        return Ok(());
+    }
+    let output = command_runner::output(&mut cmd)?;
 
     if output.status.success() {
         let actual_version = String::from_utf8_lossy(&output.stdout).to_string();
         let actual_version = get_first_line(&actual_version);
         let actual_version = extract_version(actual_version).unwrap();
         info!("sbuild version {}", actual_version);
-        fail_compare_versions(expected_version, &actual_version, "sbuild")?;
+        fail_compare_versions(expected_version, actual_version, "sbuild")?;
         Ok(())
     } else {
         Err(eyre!("Failed to execute sbuild --version"))
@@ -155,8 +322,9 @@ pub fn fail_compare_versions(expected_version: String, actual_version: &str, pro
     }
 }
 
-pub fn get_distribution(config: PkgConfig, config_file_path: String) -> Result<DistributionPackager> {
-    let path = Path::new(&config_file_path);
+/// Directory of the config file, relative paths in the config are relative to it.
+fn get_config_root(config_file_path: &str) -> Result<String> {
+    let path = Path::new(config_file_path);
     let config_file_path = fs::canonicalize(path)?;
     let config_root = config_file_path
         .parent()
@@ -164,12 +332,17 @@ pub fn get_distribution(config: PkgConfig, config_file_path: String) -> Result<D
         .to_str()
         .unwrap()
         .to_string();
-    Ok(DistributionPackager::new(config, config_root))
+    Ok(config_root)
+}
+
+pub fn get_distribution(config: PkgConfig, config_file_path: String) -> Result<DistributionPackager> {
+    let config_root = get_config_root(&config_file_path)?;
+    DistributionPackager::new(config, config_root)
 }
 
 
 pub fn get_config_file(config: Option<String>, config_file_name: &str) -> Result<String> {
-    return if let Some(location) = config {
+    if let Some(location) = config {
         let path = Path::new(&location);
         if !path.exists() {
             return Err(eyre!("Directory or file does not exist {}", location));
@@ -189,5 +362,5 @@ pub fn get_config_file(config: Option<String>, config_file_name: &str) -> Result
             return Ok(config_file.to_str().unwrap().to_string());
         }
         Err(eyre!("Could not find {} in current directory.", config_file_name))
-    };
+    }
 }
\ No newline at end of file
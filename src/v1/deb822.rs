@@ -0,0 +1,476 @@
+//! deb822 files, see deb822(5): .changes, .dsc, .buildinfo and debian/control.
+//!
+//! Files are kept as written, so writing back a parsed file only changes the fields that were set.
+
+use eyre::{eyre, Report, Result};
+use std::fmt;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Entry {
+    // raw is everything after the colon, continuation lines and line breaks included
+    Field { name: String, raw: String },
+    Comment(String),
+}
+
+/// Fields of one paragraph, looked up case-insensitively.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Paragraph {
+    entries: Vec<Entry>,
+}
+
+impl Paragraph {
+    fn find(&self, name: &str) -> Option<usize> {
+        self.entries.iter().position(|entry| match entry {
+            Entry::Field { name: field_name, .. } => field_name.eq_ignore_ascii_case(name),
+            Entry::Comment(_) => false,
+        })
+    }
+
+    /// Value of the field, continuation lines are joined with line breaks and without indentation.
+    pub fn get(&self, name: &str) -> Option<String> {
+        match &self.entries[self.find(name)?] {
+            Entry::Field { raw, .. } => Some(
+                raw.lines()
+                    .map(|line| match line.trim() {
+                        "." => "",
+                        line => line,
+                    })
+                    .collect::<Vec<_>>()
+                    .join("\n")
+                    .trim()
+                    .to_string(),
+            ),
+            Entry::Comment(_) => None,
+        }
+    }
+
+    /// Adds the field after the field `after`, or at the end of the paragraph if there is no such field.
+    pub fn insert_after(&mut self, after: &str, name: &str, value: &str) {
+        let index = self.find(after).map(|index| index + 1).unwrap_or(self.entries.len());
+        self.insert(index, name, format_value(value));
+    }
+
+    fn insert(&mut self, index: usize, name: &str, raw: String) {
+        // the last field of a file might not end with a line break
+        if let Some(Entry::Field { raw: previous, .. } | Entry::Comment(previous)) = index.checked_sub(1).map(|i| &mut self.entries[i]) {
+            if !previous.ends_with('\n') {
+                previous.push('\n');
+            }
+        }
+        self.entries.insert(index, Entry::Field { name: name.to_string(), raw });
+    }
+
+    fn write(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for entry in self.entries.iter() {
+            match entry {
+                Entry::Field { name, raw } => write!(f, "{}:{}", name, raw)?,
+                Entry::Comment(raw) => write!(f, "{}", raw)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+fn format_value(value: &str) -> String {
+    let mut lines = value.lines();
+    let mut raw = match lines.next() {
+        // multiline fields like Files start on the next line
+        Some("") | None => "\n".to_string(),
+        Some(first) => format!(" {}\n", first),
+    };
+    for line in lines {
+        match line {
+            "" => raw.push_str(" .\n"),
+            line => raw.push_str(&format!(" {}\n", line)),
+        }
+    }
+    raw
+}
+
+#[derive(Debug, Clone, PartialEq)]
+enum Block {
+    Paragraph(Paragraph),
+    Separator(String),
+}
+
+/// A deb822 file, its paragraphs with the blank lines between them.
+#[derive(Debug, Clone, PartialEq, Default)]
+pub struct Deb822 {
+    blocks: Vec<Block>,
+}
+
+impl Deb822 {
+    pub fn paragraphs(&self) -> impl Iterator<Item = &Paragraph> {
+        self.blocks.iter().filter_map(|block| match block {
+            Block::Paragraph(paragraph) => Some(paragraph),
+            Block::Separator(_) => None,
+        })
+    }
+
+    pub fn paragraphs_mut(&mut self) -> impl Iterator<Item = &mut Paragraph> {
+        self.blocks.iter_mut().filter_map(|block| match block {
+            Block::Paragraph(paragraph) => Some(paragraph),
+            Block::Separator(_) => None,
+        })
+    }
+
+    /// The first paragraph, which has to have the field `required`.
+    fn first_paragraph(&self, kind: &str, required: &str) -> Result<&Paragraph> {
+        match self.paragraphs().next() {
+            Some(paragraph) if paragraph.get(required).is_some() => Ok(paragraph),
+            _ => Err(eyre!("Not a {}, the {} field is missing", kind, required)),
+        }
+    }
+}
+
+impl FromStr for Deb822 {
+    type Err = Report;
+
+    fn from_str(content: &str) -> Result<Self> {
+        if content.starts_with("-----BEGIN PGP SIGNED MESSAGE-----") {
+            return Err(eyre!("Signed deb822 files are not supported"));
+        }
+        let mut blocks = vec![];
+        let mut paragraph = Paragraph::default();
+        let mut separator = String::new();
+        for (number, line) in content.split_inclusive('\n').enumerate() {
+            if line.trim().is_empty() {
+                if !paragraph.entries.is_empty() {
+                    blocks.push(Block::Paragraph(std::mem::take(&mut paragraph)));
+                }
+                separator.push_str(line);
+                continue;
+            }
+            if !separator.is_empty() {
+                blocks.push(Block::Separator(std::mem::take(&mut separator)));
+            }
+            if line.starts_with('#') {
+                paragraph.entries.push(Entry::Comment(line.to_string()));
+            } else if line.starts_with(' ') || line.starts_with('\t') {
+                match paragraph.entries.last_mut() {
+                    Some(Entry::Field { raw, .. }) => raw.push_str(line),
+                    _ => return Err(eyre!("Line {} continues a field, but there is no field before it", number + 1)),
+                }
+            } else {
+                let (name, raw) = line
+                    .split_once(':')
+                    .ok_or_else(|| eyre!("Line {} is not a field: {}", number + 1, line.trim_end()))?;
+                paragraph.entries.push(Entry::Field { name: name.to_string(), raw: raw.to_string() });
+            }
+        }
+        if !paragraph.entries.is_empty() {
+            blocks.push(Block::Paragraph(paragraph));
+        }
+        if !separator.is_empty() {
+            blocks.push(Block::Separator(separator));
+        }
+        Ok(Deb822 { blocks })
+    }
+}
+
+impl fmt::Display for Deb822 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        for block in self.blocks.iter() {
+            match block {
+                Block::Paragraph(paragraph) => paragraph.write(f)?,
+                Block::Separator(raw) => write!(f, "{}", raw)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// An entry of a Checksums-Sha256 field.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Checksum {
+    pub hash: String,
+    pub size: u64,
+    pub name: String,
+}
+
+fn parse_checksums(paragraph: &Paragraph, field: &str) -> Result<Vec<Checksum>> {
+    let value = paragraph.get(field).unwrap_or_default();
+    value
+        .lines()
+        .filter(|line| !line.is_empty())
+        .map(|line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+            [hash, size, name] => Ok(Checksum {
+                hash: hash.to_string(),
+                size: size.parse().map_err(|_| eyre!("Invalid size in {}: {}", field, line))?,
+                name: name.to_string(),
+            }),
+            _ => Err(eyre!("Invalid {} entry: {}", field, line)),
+        })
+        .collect()
+}
+
+/// An entry of the Files field of a .changes file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ChangesFile {
+    pub md5: String,
+    pub size: u64,
+    pub section: String,
+    pub priority: String,
+    pub name: String,
+}
+
+impl ChangesFile {
+    /// Whether the file is a binary package, .udeb and Ubuntu's .ddeb included.
+    pub fn is_binary_package(&self) -> bool {
+        self.name.ends_with(".deb") || self.name.ends_with(".udeb") || self.name.ends_with(".ddeb")
+    }
+
+    /// Whether the file is a package with the detached debug symbols of another one.
+    pub fn is_dbgsym_package(&self) -> bool {
+        self.is_binary_package() && self.package_name().ends_with("-dbgsym")
+    }
+
+    /// Package name of a binary package, the part of the file name before the version.
+    pub fn package_name(&self) -> &str {
+        self.name.split_once('_').map(|(package_name, _)| package_name).unwrap_or(&self.name)
+    }
+}
+
+/// A .changes file as written by dpkg-genchanges, unsigned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Changes {
+    pub document: Deb822,
+}
+
+impl Changes {
+    fn paragraph(&self) -> &Paragraph {
+        // checked when parsing
+        self.document.paragraphs().next().unwrap()
+    }
+
+    pub fn source(&self) -> String {
+        self.paragraph().get("Source").unwrap_or_default()
+    }
+
+    pub fn architecture(&self) -> Vec<String> {
+        let architecture = self.paragraph().get("Architecture").unwrap_or_default();
+        architecture.split_whitespace().map(|arch| arch.to_string()).collect()
+    }
+
+    pub fn files(&self) -> Result<Vec<ChangesFile>> {
+        let value = self.paragraph().get("Files").unwrap_or_default();
+        value
+            .lines()
+            .filter(|line| !line.is_empty())
+            .map(|line| match line.split_whitespace().collect::<Vec<_>>()[..] {
+                [md5, size, section, priority, name] => Ok(ChangesFile {
+                    md5: md5.to_string(),
+                    size: size.parse().map_err(|_| eyre!("Invalid size in Files: {}", line))?,
+                    section: section.to_string(),
+                    priority: priority.to_string(),
+                    name: name.to_string(),
+                }),
+                _ => Err(eyre!("Invalid Files entry: {}", line)),
+            })
+            .collect()
+    }
+
+    pub fn checksums_sha256(&self) -> Result<Vec<Checksum>> {
+        parse_checksums(self.paragraph(), "Checksums-Sha256")
+    }
+}
+
+impl FromStr for Changes {
+    type Err = Report;
+
+    fn from_str(content: &str) -> Result<Self> {
+        let document: Deb822 = content.parse()?;
+        document.first_paragraph(".changes file", "Source")?;
+        Ok(Changes { document })
+    }
+}
+
+impl fmt::Display for Changes {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.document.fmt(f)
+    }
+}
+
+/// A .dsc file of a source package, unsigned.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Dsc {
+    pub document: Deb822,
+}
+
+impl Dsc {
+    pub fn checksums_sha256(&self) -> Result<Vec<Checksum>> {
+        parse_checksums(self.document.paragraphs().next().unwrap(), "Checksums-Sha256")
+    }
+}
+
+impl FromStr for Dsc {
+    type Err = Report;
+
+    fn from_str(content: &str) -> Result<Self> {
+        let document: Deb822 = content.parse()?;
+        document.first_paragraph(".dsc file", "Format")?;
+        Ok(Dsc { document })
+    }
+}
+
+impl fmt::Display for Dsc {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.document.fmt(f)
+    }
+}
+
+/// A .buildinfo file, the environment a package was built in and the checksums of what it produced.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Buildinfo {
+    pub document: Deb822,
+}
+
+impl Buildinfo {
+    pub fn checksums_sha256(&self) -> Result<Vec<Checksum>> {
+        parse_checksums(self.document.paragraphs().next().unwrap(), "Checksums-Sha256")
+    }
+}
+
+impl FromStr for Buildinfo {
+    type Err = Report;
+
+    fn from_str(content: &str) -> Result<Self> {
+        let document: Deb822 = content.parse()?;
+        document.first_paragraph(".buildinfo file", "Source")?;
+        Ok(Buildinfo { document })
+    }
+}
+
+impl fmt::Display for Buildinfo {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.document.fmt(f)
+    }
+}
+
+/// debian/control, the source paragraph followed by a paragraph per binary package.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Control {
+    pub document: Deb822,
+}
+
+impl Control {
+    pub fn source_mut(&mut self) -> &mut Paragraph {
+        // checked when parsing
+        self.document.paragraphs_mut().next().unwrap()
+    }
+}
+
+impl FromStr for Control {
+    type Err = Report;
+
+    fn from_str(content: &str) -> Result<Self> {
+        let document: Deb822 = content.parse()?;
+        document.first_paragraph("debian/control file", "Source")?;
+        Ok(Control { document })
+    }
+}
+
+impl fmt::Display for Control {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.document.fmt(f)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const CHANGES: &str = "Format: 1.8
+Date: Sat, 01 Jun 2024 00:00:00 +0000
+Source: hello-world
+Binary: hello-world hello-world-data hello-world-dbgsym
+Architecture: amd64 all
+Version: 1.0.0-1
+Description:
+ hello-world - prints hello world
+ hello-world-data - data files
+Changes:
+ hello-world (1.0.0-1) bookworm; urgency=medium
+ .
+   * Initial release.
+Checksums-Sha256:
+ ab12 1024 hello-world_1.0.0-1_amd64.deb
+ cd34 2048 hello-world-data_1.0.0-1_all.deb
+Files:
+ 1d 1024 misc optional hello-world_1.0.0-1_amd64.deb
+ 2d 2048 misc optional hello-world-data_1.0.0-1_all.deb
+ 3d 4096 debug optional hello-world-dbgsym_1.0.0-1_amd64.deb
+";
+
+    const CONTROL: &str = "# generated by debcrafter
+Source: hello-world
+Section: misc
+Priority: optional
+Maintainer: John Doe <john@example.com>
+Build-Depends: debhelper (>= 12),
+\tcargo
+
+Package: hello-world
+Architecture: any
+Description: prints hello world
+ It prints hello world.
+ .
+ That is all.";
+
+    #[test]
+    fn test_parse_changes() {
+        let changes: Changes = CHANGES.parse().unwrap();
+        assert_eq!(changes.source(), "hello-world");
+        assert_eq!(changes.architecture(), vec!["amd64", "all"]);
+        assert_eq!(changes.document.paragraphs().next().unwrap().get("changes").unwrap(),
+                   "hello-world (1.0.0-1) bookworm; urgency=medium\n\n* Initial release.");
+        let files = changes.files().unwrap();
+        assert_eq!(files.len(), 3);
+        assert_eq!(files[1], ChangesFile {
+            md5: "2d".to_string(),
+            size: 2048,
+            section: "misc".to_string(),
+            priority: "optional".to_string(),
+            name: "hello-world-data_1.0.0-1_all.deb".to_string(),
+        });
+        let binaries: Vec<_> = files
+            .iter()
+            .filter(|file| file.is_binary_package() && !file.is_dbgsym_package())
+            .map(|file| file.package_name())
+            .collect();
+        assert_eq!(binaries, vec!["hello-world", "hello-world-data"]);
+        assert_eq!(changes.checksums_sha256().unwrap()[0], Checksum {
+            hash: "ab12".to_string(),
+            size: 1024,
+            name: "hello-world_1.0.0-1_amd64.deb".to_string(),
+        });
+        assert_eq!(changes.to_string(), CHANGES);
+    }
+
+    #[test]
+    fn test_control_is_written_back_losslessly() {
+        let mut control: Control = CONTROL.parse().unwrap();
+        assert_eq!(control.to_string(), CONTROL);
+        assert_eq!(control.document.paragraphs().count(), 2);
+        assert_eq!(control.source_mut().get("Build-Depends").unwrap(), "debhelper (>= 12),\ncargo");
+
+        control.source_mut().insert_after("Priority", "Standards-Version", "4.5.1");
+        control.document.paragraphs_mut().nth(1).unwrap().insert_after("Version", "Multi-Arch", "foreign");
+        let binary = control.document.paragraphs().nth(1).unwrap();
+        assert_eq!(binary.get("Description").unwrap(), "prints hello world\nIt prints hello world.\n\nThat is all.");
+        assert_eq!(control.to_string(), CONTROL
+            .replace("Priority: optional\n", "Priority: optional\nStandards-Version: 4.5.1\n")
+            + "\nMulti-Arch: foreign\n");
+    }
+
+    #[test]
+    fn test_invalid_files() {
+        assert!("Source: hello-world\n".parse::<Dsc>().is_err());
+        assert!(" continuation\n".parse::<Deb822>().is_err());
+        assert!("Source hello-world\n".parse::<Deb822>().is_err());
+        assert!("-----BEGIN PGP SIGNED MESSAGE-----\n".parse::<Changes>().is_err());
+        let buildinfo: Buildinfo = "Source: hello-world\nChecksums-Sha256:\n ab12 not-a-size hello.deb\n".parse().unwrap();
+        assert!(buildinfo.checksums_sha256().is_err());
+    }
+}
@@ -0,0 +1,259 @@
+use eyre::{eyre, Report, Result};
+use serde::Deserialize;
+use crate::v1::pkg_config::{validate_not_empty, PkgConfig, Validation};
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum Vendor {
+    #[default]
+    Debian,
+    Ubuntu,
+}
+
+/// A distribution pkg-builder can build for.
+///
+/// `codename` is the value used in `build_env.codename`, `suite` is the name
+/// the archive and the build tools know the distribution by.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+pub struct Distribution {
+    pub codename: String,
+    pub suite: String,
+    pub mirror: String,
+    pub keyring: String,
+    pub vendor: Vendor,
+    #[serde(default = "default_components")]
+    pub components: Vec<String>,
+}
+
+impl Distribution {
+    /// The release path of the distribution on packages.microsoft.com, e.g. `debian/12`,
+    /// none if Microsoft does not publish packages for it.
+    pub fn get_microsoft_repo(&self) -> Option<String> {
+        let version = match (&self.vendor, self.suite.as_str()) {
+            (Vendor::Debian, "bullseye") => "11",
+            (Vendor::Debian, "bookworm") => "12",
+            (Vendor::Debian, "trixie") => "13",
+            (Vendor::Ubuntu, "focal") => "20.04",
+            (Vendor::Ubuntu, "jammy") => "22.04",
+            (Vendor::Ubuntu, "noble") => "24.04",
+            _ => return None,
+        };
+        let vendor = match self.vendor {
+            Vendor::Debian => "debian",
+            Vendor::Ubuntu => "ubuntu",
+        };
+        Some(format!("{}/{}", vendor, version))
+    }
+}
+
+fn default_components() -> Vec<String> {
+    vec!["main".to_string()]
+}
+
+impl Validation for Distribution {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Err(err) = validate_not_empty("codename", &self.codename) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("suite", &self.suite) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("mirror", &self.mirror) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("keyring", &self.keyring) {
+            errors.push(err);
+        }
+        if self.components.is_empty() {
+            errors.push(eyre!("field: components cannot be empty"));
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+pub fn builtin_distributions() -> Vec<Distribution> {
+    vec![
+        Distribution {
+            codename: "bookworm".to_string(),
+            suite: "bookworm".to_string(),
+            mirror: "http://deb.debian.org/debian".to_string(),
+            keyring: "/usr/share/keyrings/debian-archive-keyring.gpg".to_string(),
+            vendor: Vendor::Debian,
+            components: default_components(),
+        },
+        Distribution {
+            codename: "jammy jellyfish".to_string(),
+            suite: "jammy".to_string(),
+            mirror: "http://archive.ubuntu.com/ubuntu".to_string(),
+            keyring: "/usr/share/keyrings/ubuntu-archive-keyring.gpg".to_string(),
+            vendor: Vendor::Ubuntu,
+            components: default_components(),
+        },
+        Distribution {
+            codename: "noble numbat".to_string(),
+            suite: "noble".to_string(),
+            mirror: "http://archive.ubuntu.com/ubuntu".to_string(),
+            keyring: "/usr/share/keyrings/ubuntu-archive-keyring.gpg".to_string(),
+            vendor: Vendor::Ubuntu,
+            components: default_components(),
+        },
+    ]
+}
+
+pub struct DistributionRegistry {
+    distributions: Vec<Distribution>,
+}
+
+impl DistributionRegistry {
+    /// Built-in distributions extended with user defined ones,
+    /// a user defined entry replaces the built-in entry with the same codename.
+    pub fn new(user_distributions: &[Distribution]) -> Self {
+        let mut distributions = builtin_distributions();
+        for distribution in user_distributions {
+            match distributions
+                .iter_mut()
+                .find(|existing| existing.codename == distribution.codename) {
+                Some(existing) => *existing = distribution.clone(),
+                None => distributions.push(distribution.clone()),
+            }
+        }
+        DistributionRegistry { distributions }
+    }
+
+    pub fn from_config(config: &PkgConfig) -> Self {
+        DistributionRegistry::new(&config.distributions)
+    }
+
+    /// Looks up a distribution either by its codename or by its suite.
+    pub fn get(&self, codename: &str) -> Result<&Distribution> {
+        self.distributions
+            .iter()
+            .find(|distribution| distribution.codename == codename)
+            .or_else(|| self.distributions.iter().find(|distribution| distribution.suite == codename))
+            .ok_or_else(|| eyre!(
+                "Unsupported codename '{}'. Supported codenames are: {}. Other distributions can be added with a [[distributions]] entry in pkg-builder.toml.",
+                codename,
+                self.supported_codenames().join(", ")
+            ))
+    }
+
+    /// Whether both name the same distribution, by codename or suite, unknown names only match themselves.
+    pub fn is_same(&self, codename: &str, other: &str) -> bool {
+        match (self.get(codename), self.get(other)) {
+            (Ok(distribution), Ok(other_distribution)) => distribution.codename == other_distribution.codename,
+            _ => codename == other,
+        }
+    }
+
+    pub fn supported_codenames(&self) -> Vec<&str> {
+        self.distributions
+            .iter()
+            .map(|distribution| distribution.codename.as_str())
+            .collect()
+    }
+}
+
+pub fn resolve_distribution(config: &PkgConfig) -> Result<Distribution> {
+    let registry = DistributionRegistry::from_config(config);
+    let distribution = registry.get(&config.build_env.codename)?;
+    Ok(distribution.clone())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn trixie() -> Distribution {
+        Distribution {
+            codename: "trixie".to_string(),
+            suite: "trixie".to_string(),
+            mirror: "http://deb.debian.org/debian".to_string(),
+            keyring: "/usr/share/keyrings/debian-archive-keyring.gpg".to_string(),
+            vendor: Vendor::Debian,
+            components: vec!["main".to_string(), "contrib".to_string()],
+        }
+    }
+
+    #[test]
+    fn test_get_builtin_distribution() {
+        let registry = DistributionRegistry::new(&[]);
+        let distribution = registry.get("noble numbat").unwrap();
+        assert_eq!(distribution.suite, "noble");
+        assert_eq!(distribution.vendor, Vendor::Ubuntu);
+        assert_eq!(distribution.mirror, "http://archive.ubuntu.com/ubuntu");
+    }
+
+    #[test]
+    fn test_get_distribution_by_suite() {
+        let registry = DistributionRegistry::new(&[]);
+        let distribution = registry.get("jammy").unwrap();
+        assert_eq!(distribution.codename, "jammy jellyfish");
+    }
+
+    #[test]
+    fn test_is_same_distribution() {
+        let registry = DistributionRegistry::new(&[]);
+        assert!(registry.is_same("noble", "noble numbat"));
+        assert!(registry.is_same("bookworm", "bookworm"));
+        assert!(!registry.is_same("noble", "jammy"));
+        assert!(registry.is_same("unknown", "unknown"));
+        assert!(!registry.is_same("unknown", "bookworm"));
+    }
+
+    #[test]
+    fn test_user_defined_distribution_is_added() {
+        let registry = DistributionRegistry::new(&[trixie()]);
+        let distribution = registry.get("trixie").unwrap();
+        assert_eq!(distribution, &trixie());
+        assert!(registry.get("bookworm").is_ok());
+    }
+
+    #[test]
+    fn test_user_defined_distribution_overrides_builtin() {
+        let mut bookworm = builtin_distributions()[0].clone();
+        bookworm.mirror = "http://localhost:3142/debian".to_string();
+        let registry = DistributionRegistry::new(&[bookworm]);
+        let distribution = registry.get("bookworm").unwrap();
+        assert_eq!(distribution.mirror, "http://localhost:3142/debian");
+        assert_eq!(registry.supported_codenames().len(), builtin_distributions().len());
+    }
+
+    #[test]
+    fn test_unknown_codename_lists_supported() {
+        let registry = DistributionRegistry::new(&[]);
+        let err = registry.get("sid").unwrap_err().to_string();
+        assert!(err.contains("Unsupported codename 'sid'"));
+        assert!(err.contains("bookworm, jammy jellyfish, noble numbat"));
+    }
+
+    #[test]
+    fn test_microsoft_repo() {
+        let repos: Vec<_> = builtin_distributions().iter().map(|distribution| distribution.get_microsoft_repo()).collect();
+        assert_eq!(repos, vec![Some("debian/12".to_string()), Some("ubuntu/22.04".to_string()), Some("ubuntu/24.04".to_string())]);
+        assert_eq!(trixie().get_microsoft_repo(), Some("debian/13".to_string()));
+        let sid = Distribution { suite: "sid".to_string(), ..trixie() };
+        assert_eq!(sid.get_microsoft_repo(), None);
+    }
+
+    #[test]
+    fn test_parse_user_defined_distribution() {
+        let config_str = r#"
+codename = "trixie"
+suite = "trixie"
+mirror = "http://deb.debian.org/debian"
+keyring = "/usr/share/keyrings/debian-archive-keyring.gpg"
+vendor = "debian"
+components = ["main", "contrib"]
+"#;
+        let distribution: Distribution = toml::from_str(config_str).unwrap();
+        assert_eq!(distribution, trixie());
+        assert!(distribution.validate().is_ok());
+    }
+}
@@ -0,0 +1,132 @@
+use crate::v1::build::dir_setup::{clone_and_checkout_tag, list_submodules};
+use crate::v1::build::dry_run::{self, PlanStep};
+use crate::v1::build::git_cache::GitCache;
+use crate::v1::pkg_config::{GitPackageTypeConfig, PackageType, PkgConfig, SubModule};
+use eyre::{eyre, Result};
+use log::info;
+use std::fs;
+
+/// Clones the tag of the git package and writes all of its submodules, at any depth,
+/// with the commits the tag points them to, as the submodules array of the config file.
+///
+/// The submodules in the config are not applied, the lock only depends on the tag.
+pub fn lock_submodules(config: &PkgConfig, config_file: &str, config_root: &str) -> Result<Vec<SubModule>> {
+    let PackageType::Git(git_config) = &config.package_type else {
+        return Err(eyre!("pkg-builder git lock only works for package_type = \"git\""));
+    };
+    let unpinned = GitPackageTypeConfig {
+        submodules: vec![],
+        strict_submodules: None,
+        ..git_config.clone()
+    };
+    let checkout_dir = dry_run::tempdir()?;
+    let checkout_path = checkout_dir.path().to_str().unwrap();
+    let git_cache = GitCache::new(&config.build_env.get_git_cache_dir(), config.build_env.git_offline.unwrap_or_default());
+    clone_and_checkout_tag(&unpinned, checkout_path, config_root, &git_cache)?;
+    let submodules = list_submodules(checkout_path)?;
+
+    info!("Writing {} submodules of {} to {}", submodules.len(), git_config.git_tag, config_file);
+    if dry_run::record(PlanStep::WriteFile { path: config_file.to_string() }) {
+        return Ok(submodules);
+    }
+    let content = fs::read_to_string(config_file)?;
+    fs::write(config_file, replace_submodules(&content, &submodules)?)?;
+    Ok(submodules)
+}
+
+/// The submodules array in the style of the examples, one inline table per line.
+fn format_submodules(submodules: &[SubModule]) -> String {
+    let mut array = "submodules = [\n".to_string();
+    for submodule in submodules {
+        array.push_str(&format!("    {{commit = \"{}\", path = \"{}\"}},\n", submodule.commit, submodule.path));
+    }
+    array.push(']');
+    array
+}
+
+/// Replaces the submodules array in the config file content, keeping the rest of the file as it is.
+fn replace_submodules(content: &str, submodules: &[SubModule]) -> Result<String> {
+    let not_found = || eyre!("Could not find submodules = [...] in the config file");
+    let start = content
+        .match_indices("submodules")
+        .map(|(index, _)| index)
+        .find(|index| {
+            let line_start = content[..*index].rfind('\n').map_or(0, |newline| newline + 1);
+            content[line_start..*index].trim().is_empty()
+                && content[index + "submodules".len()..].trim_start().starts_with('=')
+        })
+        .ok_or_else(not_found)?;
+    let array_start = start + content[start..].find('[').ok_or_else(not_found)?;
+
+    // finds the closing bracket, skipping strings and comments
+    let mut depth = 0;
+    let mut in_string = false;
+    let mut in_comment = false;
+    let mut end = None;
+    for (index, c) in content[array_start..].char_indices() {
+        match c {
+            '\n' => in_comment = false,
+            _ if in_comment => {}
+            '"' => in_string = !in_string,
+            _ if in_string => {}
+            '#' => in_comment = true,
+            '[' => depth += 1,
+            ']' => {
+                depth -= 1;
+                if depth == 0 {
+                    end = Some(array_start + index + 1);
+                    break;
+                }
+            }
+            _ => {}
+        }
+    }
+    let end = end.ok_or_else(|| eyre!("The submodules array in the config file is not closed"))?;
+    Ok(format!("{}{}{}", &content[..start], format_submodules(submodules), &content[end..]))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_replace_submodules() {
+        let content = r#"[package_type]
+package_type="git"
+git_url="https://github.com/status-im/nimbus-eth2.git"
+git_tag="v24.3.0"
+submodules = [
+    # vendor/EIPs commit is different as it was forced pushed :(
+    {commit = "72523ee3f865e09f8a6117c1b5e74cbb2df4f60e", path = "vendor/EIPs"},
+    {commit = "ab3ff9fad45fa7e20d749d0a03a7567225f5dd4a", path = "vendor/NimYAML"}, # [sic]
+]
+
+[package_type.language_env]
+language_env = "c"
+"#;
+        let submodules = vec![
+            SubModule { commit: "ab581251bcda11e3cc120cc9e9ad1ad679340949".to_string(), path: "vendor/nim-eth".to_string() },
+            SubModule { commit: "14d8439235fa757dd39b9fb1c10a06a99a720989".to_string(), path: "vendor/nim-eth/vendor/nim-web3".to_string() },
+        ];
+        let expected = r#"[package_type]
+package_type="git"
+git_url="https://github.com/status-im/nimbus-eth2.git"
+git_tag="v24.3.0"
+submodules = [
+    {commit = "ab581251bcda11e3cc120cc9e9ad1ad679340949", path = "vendor/nim-eth"},
+    {commit = "14d8439235fa757dd39b9fb1c10a06a99a720989", path = "vendor/nim-eth/vendor/nim-web3"},
+]
+
+[package_type.language_env]
+language_env = "c"
+"#;
+        let replaced = replace_submodules(content, &submodules).unwrap();
+        assert_eq!(replaced, expected);
+        // the written array is read back as the same submodules
+        let config: toml::Value = toml::from_str(&replaced).unwrap();
+        assert_eq!(config["package_type"]["submodules"].as_array().unwrap().len(), 2);
+
+        assert!(replace_submodules("[package_type]\npackage_type = \"git\"\n", &submodules).is_err());
+        assert_eq!(replace_submodules("submodules = []\n", &[]).unwrap(), "submodules = [\n]\n");
+    }
+}
@@ -0,0 +1,217 @@
+use crate::v1::build::env_info::format_table;
+use crate::v1::distribution::{resolve_distribution, DistributionRegistry};
+use crate::v1::pkg_config::{PkgConfig, Validation};
+use eyre::{eyre, Result};
+use std::path::{Path, PathBuf};
+
+/// One codename and arch of the build matrix, with the overrides applied to its config.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatrixEntry {
+    pub codename: String,
+    pub arch: String,
+    pub config: PkgConfig,
+}
+
+/// Outcome of building one entry, `error` is None if it passed.
+#[derive(Debug, PartialEq, Clone)]
+pub struct MatrixResult {
+    pub codename: String,
+    pub arch: String,
+    pub workdir: String,
+    pub error: Option<String>,
+}
+
+/// The entries to build, only the config itself if it has no matrix.
+///
+/// Every entry gets its own workdir below build_env.workdir, unless an override sets one.
+pub fn get_matrix_entries(config: &PkgConfig) -> Result<Vec<MatrixEntry>> {
+    let Some(matrix) = &config.matrix else {
+        return Ok(vec![MatrixEntry {
+            codename: config.build_env.codename.clone(),
+            arch: config.build_env.arch.clone(),
+            config: config.clone(),
+        }]);
+    };
+    let base_workdir = config
+        .build_env
+        .workdir
+        .clone()
+        .unwrap_or("~/.pkg-builder/packages".to_string());
+    let registry = DistributionRegistry::from_config(config);
+    let mut entries = vec![];
+    for codename in matrix.codenames.iter() {
+        for arch in matrix.arches.iter() {
+            let mut entry_config = config.clone();
+            entry_config.matrix = None;
+            entry_config.build_env.codename = codename.clone();
+            entry_config.build_env.arch = arch.clone();
+            entry_config.build_env.workdir = None;
+            for matrix_override in matrix.overrides.iter().filter(|matrix_override| matrix_override.matches(&registry, codename, arch)) {
+                matrix_override.apply(&mut entry_config.build_env);
+            }
+            if entry_config.build_env.workdir.is_none() {
+                let suite = resolve_distribution(&entry_config)?.suite;
+                entry_config.build_env.workdir = Some(format!("{}/{}-{}", base_workdir, suite, arch));
+            }
+            entry_config
+                .validate()
+                .map_err(|errors| eyre!("Validation of matrix entry {} {} failed: {:?}", codename, arch, errors))?;
+            entries.push(MatrixEntry {
+                codename: codename.clone(),
+                arch: arch.clone(),
+                config: entry_config,
+            });
+        }
+    }
+    Ok(entries)
+}
+
+/// The verify config of a matrix entry, `pkg-builder-verify-<suite>-<arch>.toml` next to the given verify config,
+/// or in the current directory, as the built files and their hashes differ between the entries.
+pub fn get_entry_verify_config(verify_config: Option<&str>, suite: &str, arch: &str) -> String {
+    let dir = match verify_config {
+        Some(location) if Path::new(location).is_dir() => PathBuf::from(location),
+        Some(file) => Path::new(file).parent().map(Path::to_path_buf).unwrap_or_default(),
+        None => PathBuf::new(),
+    };
+    dir.join(format!("pkg-builder-verify-{}-{}.toml", suite, arch)).to_str().unwrap().to_string()
+}
+
+pub fn format_matrix_summary(results: &[MatrixResult]) -> String {
+    let rows: Vec<Vec<&str>> = results
+        .iter()
+        .map(|result| vec![
+            result.codename.as_str(),
+            result.arch.as_str(),
+            if result.error.is_none() { "passed" } else { "failed" },
+            result.workdir.as_str(),
+            result.error.as_deref().unwrap_or_default(),
+        ])
+        .collect();
+    format_table(&["CODENAME", "ARCH", "RESULT", "WORKDIR", "ERROR"], &rows)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::pkg_config::parse;
+
+    const CONFIG: &str = r#"
+[package_fields]
+spec_file = "hello-world.sss"
+package_name = "hello-world"
+version_number = "1.0.0"
+revision_number = "1"
+homepage="https://github.com/eth-pkg/pkg-builder#examples"
+
+[package_type]
+package_type="virtual"
+
+[build_env]
+codename="bookworm"
+arch = "amd64"
+pkg_builder_version="0.2.1"
+debcrafter_version = "2711b53"
+lintian_version="2.116.3"
+piuparts_version="1.1.7"
+autopkgtest_version="5.28"
+sbuild_version="0.85.6"
+workdir="~/.pkg-builder/packages"
+
+[matrix]
+codenames = ["bookworm", "noble numbat"]
+arches = ["amd64", "arm64"]
+
+[[matrix.overrides]]
+codename = "noble numbat"
+autopkgtest_version = "5.20"
+
+[[matrix.overrides]]
+codename = "noble numbat"
+arch = "arm64"
+run_autopkgtest = false
+workdir = "/tmp/noble-arm64"
+"#;
+
+    #[test]
+    fn test_matrix_entries() {
+        let config = parse::<PkgConfig>(CONFIG).unwrap();
+        let entries = get_matrix_entries(&config).unwrap();
+        let names: Vec<_> = entries.iter().map(|entry| (entry.codename.as_str(), entry.arch.as_str())).collect();
+        assert_eq!(names, vec![("bookworm", "amd64"), ("bookworm", "arm64"), ("noble numbat", "amd64"), ("noble numbat", "arm64")]);
+
+        let build_env = &entries[1].config.build_env;
+        assert_eq!(build_env.arch, "arm64");
+        assert_eq!(build_env.autopkgtest_version, "5.28");
+        assert_eq!(build_env.workdir.as_deref(), Some("~/.pkg-builder/packages/bookworm-arm64"));
+        assert!(entries[1].config.matrix.is_none());
+
+        let build_env = &entries[2].config.build_env;
+        assert_eq!(build_env.codename, "noble numbat");
+        assert_eq!(build_env.autopkgtest_version, "5.20");
+        assert_eq!(build_env.run_autopkgtest, None);
+        assert_eq!(build_env.workdir.as_deref(), Some("~/.pkg-builder/packages/noble-amd64"));
+
+        let build_env = &entries[3].config.build_env;
+        assert_eq!(build_env.autopkgtest_version, "5.20");
+        assert_eq!(build_env.run_autopkgtest, Some(false));
+        assert_eq!(build_env.workdir.as_deref(), Some("/tmp/noble-arm64"));
+    }
+
+    #[test]
+    fn test_config_without_matrix_is_one_entry() {
+        let config = parse::<PkgConfig>(&CONFIG[..CONFIG.find("[matrix]").unwrap()]).unwrap();
+        let entries = get_matrix_entries(&config).unwrap();
+        assert_eq!(entries.len(), 1);
+        assert_eq!(entries[0].config, config);
+    }
+
+    #[test]
+    fn test_override_of_unknown_codename_fails() {
+        let err = parse::<PkgConfig>(&CONFIG.replace("codename = \"noble numbat\"\nautopkgtest", "codename = \"jammy\"\nautopkgtest"))
+            .unwrap_err()
+            .to_string();
+        assert!(err.contains("overrides codename jammy is not one of the matrix codenames"), "{}", err);
+    }
+
+    #[test]
+    fn test_override_by_suite() {
+        let config = parse::<PkgConfig>(&CONFIG.replace("codename = \"noble numbat\"\narch", "codename = \"noble\"\narch")).unwrap();
+        let entries = get_matrix_entries(&config).unwrap();
+        assert_eq!(entries[2].config.build_env.run_autopkgtest, None);
+        assert_eq!(entries[3].config.build_env.run_autopkgtest, Some(false));
+        assert_eq!(entries[3].config.build_env.workdir.as_deref(), Some("/tmp/noble-arm64"));
+    }
+
+    #[test]
+    fn test_format_matrix_summary() {
+        let results = vec![
+            MatrixResult {
+                codename: "bookworm".to_string(),
+                arch: "amd64".to_string(),
+                workdir: "/w/bookworm-amd64".to_string(),
+                error: None,
+            },
+            MatrixResult {
+                codename: "noble numbat".to_string(),
+                arch: "arm64".to_string(),
+                workdir: "/w/noble-arm64".to_string(),
+                error: Some("sbuild exited with non-zero status code.".to_string()),
+            },
+        ];
+        assert_eq!(format_matrix_summary(&results),
+            "CODENAME      ARCH   RESULT  WORKDIR            ERROR\n\
+             bookworm      amd64  passed  /w/bookworm-amd64\n\
+             noble numbat  arm64  failed  /w/noble-arm64     sbuild exited with non-zero status code.\n");
+    }
+
+    #[test]
+    fn test_entry_verify_config() {
+        assert_eq!(get_entry_verify_config(None, "noble", "arm64"), "pkg-builder-verify-noble-arm64.toml");
+        assert_eq!(get_entry_verify_config(Some("examples/hello/pkg-builder-verify.toml"), "noble", "arm64"),
+                   "examples/hello/pkg-builder-verify-noble-arm64.toml");
+        let dir = tempfile::tempdir().unwrap();
+        assert_eq!(get_entry_verify_config(dir.path().to_str(), "bookworm", "amd64"),
+                   dir.path().join("pkg-builder-verify-bookworm-amd64.toml").to_str().unwrap());
+    }
+}
@@ -2,6 +2,10 @@
 pub mod cli;
 pub mod build;
 pub mod packager;
+pub mod distribution;
+pub mod matrix;
+pub mod git_lock;
+pub mod deb822;
 mod args;
 pub mod pkg_config;
 pub mod pkg_config_verify;
@@ -1,5 +1,10 @@
 use eyre::{eyre, Result};
 use crate::v1::build::sbuild_packager::SbuildPackager;
+use crate::v1::build::backend_packager::BackendPackager;
+use crate::v1::build::container::Container;
+use crate::v1::build::host::Host;
+use crate::v1::build::env_info::{EnvInfo, InstalledPackage};
+use crate::v1::distribution::resolve_distribution;
 
 
 use crate::v1::pkg_config::PkgConfig;
@@ -7,20 +12,19 @@ use crate::v1::pkg_config_verify::PkgVerifyConfig;
 
 
 pub trait Packager {
-    type BuildEnv: BackendBuildEnv;
-    fn new(config: PkgConfig, config_root: String) -> Self;
     fn package(&self) -> Result<()>;
-    fn get_build_env(&self) -> Result<Self::BuildEnv>;
-}
-
-pub struct DistributionPackager {
-    config: PkgConfig,
-    config_root: String,
+    fn get_build_env(&self) -> Result<Box<dyn BackendBuildEnv>>;
 }
 
 pub trait BackendBuildEnv {
     fn clean(&self) -> Result<()>;
     fn create(&self) -> Result<()>;
+    /// Upgrades the packages of the build env in place.
+    fn update(&self) -> Result<()>;
+    /// Build envs of the backend available on this machine.
+    fn list(&self) -> Result<Vec<EnvInfo>>;
+    /// Packages installed in the build env.
+    fn info(&self) -> Result<Vec<InstalledPackage>>;
     fn package(&self) -> Result<()>;
 
     fn verify(&self, verify_config: PkgVerifyConfig) -> Result<()>;
@@ -30,146 +34,209 @@ pub trait BackendBuildEnv {
     fn run_autopkgtests(&self) -> Result<()>;
 }
 
+/// Creates the packager of a backend from the config and the directory of the config file.
+pub type PackagerFactory = Box<dyn Fn(PkgConfig, String) -> Result<Box<dyn Packager>>>;
+
+/// Build backends selectable with `build_env.backend`.
+///
+/// Library users can register their own backends before creating a `DistributionPackager`.
+pub struct BackendRegistry {
+    backends: Vec<(String, PackagerFactory)>,
+}
+
+impl Default for BackendRegistry {
+    fn default() -> Self {
+        let mut registry = BackendRegistry::empty();
+        registry.register("sbuild", Box::new(|config, config_root| {
+            Ok(Box::new(SbuildPackager::new(config, config_root)))
+        }));
+        registry.register("container", Box::new(|config, config_root| {
+            Container::check_config(&config)?;
+            Ok(Box::new(BackendPackager::new(config, config_root, |config, build_files_dir| {
+                Box::new(Container::new(config, build_files_dir))
+            })))
+        }));
+        registry.register("host", Box::new(|config, config_root| {
+            Ok(Box::new(BackendPackager::new(config, config_root, |config, build_files_dir| {
+                Box::new(Host::new(config, build_files_dir))
+            })))
+        }));
+        registry
+    }
+}
+
+impl BackendRegistry {
+    pub fn empty() -> Self {
+        BackendRegistry { backends: vec![] }
+    }
+
+    /// Registers a backend, replacing any backend already registered with the same name.
+    pub fn register(&mut self, name: &str, factory: PackagerFactory) {
+        self.backends.retain(|(backend_name, _)| backend_name != name);
+        self.backends.push((name.to_string(), factory));
+    }
+
+    pub fn backend_names(&self) -> Vec<&str> {
+        self.backends.iter().map(|(name, _)| name.as_str()).collect()
+    }
+
+    pub fn create(&self, name: &str, config: PkgConfig, config_root: String) -> Result<Box<dyn Packager>> {
+        let (_, factory) = self.backends
+            .iter()
+            .find(|(backend_name, _)| backend_name == name)
+            .ok_or_else(|| eyre!(
+                "Unknown backend '{}'. Available backends are: {}",
+                name,
+                self.backend_names().join(", ")
+            ))?;
+        factory(config, config_root)
+    }
+}
+
+pub struct DistributionPackager {
+    config: PkgConfig,
+    config_root: String,
+    registry: BackendRegistry,
+    packager: Box<dyn Packager>,
+}
+
 impl DistributionPackager {
-    pub fn new(config: PkgConfig, config_root: String) -> Self {
-        DistributionPackager {
+    pub fn new(config: PkgConfig, config_root: String) -> Result<Self> {
+        DistributionPackager::with_registry(config, config_root, BackendRegistry::default())
+    }
+
+    pub fn with_registry(config: PkgConfig, config_root: String, registry: BackendRegistry) -> Result<Self> {
+        resolve_distribution(&config)?;
+        let packager = registry.create(&config.build_env.get_backend(), config.clone(), config_root.clone())?;
+        Ok(DistributionPackager {
             config,
             config_root,
-        }
+            registry,
+            packager,
+        })
     }
+
     pub fn package(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-                packager.package()?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
-        }
-        Ok(())
+        self.packager.package()
     }
     pub fn run_lintian(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-                let build_env = packager.get_build_env()?;
-                build_env.run_lintian()?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
-        }
-        Ok(())
+        self.packager.get_build_env()?.run_lintian()
     }
     pub fn run_piuparts(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-                let build_env = packager.get_build_env()?;
-                build_env.run_piuparts()?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
-        }
-        Ok(())
+        self.packager.get_build_env()?.run_piuparts()
     }
     pub fn run_autopkgtests(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-                let build_env = packager.get_build_env()?;
-                build_env.run_autopkgtests()?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
-        }
-        Ok(())
+        self.packager.get_build_env()?.run_autopkgtests()
     }
     pub fn clean_build_env(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-
-                let build_env = packager.get_build_env()?;
-                build_env.clean()?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
-        }
-        Ok(())
+        self.packager.get_build_env()?.clean()
     }
     pub fn create_build_env(&self) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-                let build_env = packager.get_build_env()?;
-                build_env.create()?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
-        }
-        Ok(())
+        self.packager.get_build_env()?.create()
+    }
+    pub fn update_build_env(&self) -> Result<()> {
+        self.packager.get_build_env()?.update()
+    }
+    pub fn list_build_envs(&self) -> Result<Vec<EnvInfo>> {
+        self.packager.get_build_env()?.list()
+    }
+    pub fn build_env_info(&self) -> Result<Vec<InstalledPackage>> {
+        self.packager.get_build_env()?.info()
     }
 
+    /// Builds without lintian, piuparts and autopkgtest whatever the config says,
+    /// so only the build outputs are compared.
     pub fn verify(&self, verify_config: PkgVerifyConfig, package: bool) -> Result<()> {
-        let config = self.config.clone();
-
-        match self.config.build_env.codename.clone().as_str() {
-            "bookworm" | "noble numbat" | "jammy jellyfish" => {
-                let mut config = config.clone();
-                config.build_env.run_autopkgtest = Some(false);
-                config.build_env.run_lintian = Some(false);
-                config.build_env.run_piuparts = Some(false);
-                let packager = SbuildPackager::new(config, self.config_root.clone());
-                if package {
-                    packager.package()?;
-                }
-                let build_env = packager.get_build_env()?;
-                // files to verify
-                build_env.verify(verify_config)?;
-            }
-            invalid_codename => {
-                return Err(eyre!(format!(
-                    "Invalid codename '{}' specified",
-                    invalid_codename
-                )));
-            }
+        let mut config = self.config.clone();
+        config.build_env.run_lintian = Some(false);
+        config.build_env.run_piuparts = Some(false);
+        config.build_env.run_autopkgtest = Some(false);
+        let packager = self.registry.create(&config.build_env.get_backend(), config, self.config_root.clone())?;
+        if package {
+            packager.package()?;
         }
-        Ok(())
+        // files to verify
+        packager.get_build_env()?.verify(verify_config)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::v1::build::container::PIUPARTS_UNSUPPORTED;
+    use std::cell::RefCell;
+    use std::rc::Rc;
+
+    struct NoopPackager;
+
+    impl Packager for NoopPackager {
+        fn package(&self) -> Result<()> {
+            Ok(())
+        }
+        fn get_build_env(&self) -> Result<Box<dyn BackendBuildEnv>> {
+            Err(eyre!("noop has no build env"))
+        }
+    }
+
+    fn config(backend: Option<&str>) -> PkgConfig {
+        let mut config = PkgConfig::default();
+        config.build_env.codename = "bookworm".to_string();
+        config.build_env.arch = "amd64".to_string();
+        config.build_env.backend = backend.map(|backend| backend.to_string());
+        config
+    }
+
+    #[test]
+    fn test_default_registry_has_builtin_backends() {
+        let registry = BackendRegistry::default();
+        assert_eq!(registry.backend_names(), vec!["sbuild", "container", "host"]);
+        assert!(DistributionPackager::new(config(None), "/tmp".to_string()).is_ok());
+        assert!(DistributionPackager::new(config(Some("container")), "/tmp".to_string()).is_ok());
+        let mut piuparts_config = config(Some("container"));
+        piuparts_config.build_env.run_piuparts = Some(true);
+        let err = DistributionPackager::new(piuparts_config, "/tmp".to_string()).err().unwrap();
+        assert_eq!(err.to_string(), PIUPARTS_UNSUPPORTED);
+    }
+
+    #[test]
+    fn test_unknown_backend_lists_available() {
+        let result = DistributionPackager::new(config(Some("chroot")), "/tmp".to_string());
+        let err = result.err().unwrap().to_string();
+        assert_eq!(err, "Unknown backend 'chroot'. Available backends are: sbuild, container, host");
+    }
+
+    #[test]
+    fn test_verify_turns_off_tests() {
+        let configs = Rc::new(RefCell::new(vec![]));
+        let mut registry = BackendRegistry::default();
+        let created = configs.clone();
+        registry.register("recording", Box::new(move |config: PkgConfig, _| {
+            let build_env = &config.build_env;
+            created.borrow_mut().push((build_env.run_lintian, build_env.run_piuparts, build_env.run_autopkgtest));
+            Ok(Box::new(NoopPackager))
+        }));
+        let mut config = config(Some("recording"));
+        config.build_env.run_lintian = Some(true);
+        config.build_env.run_piuparts = Some(true);
+        config.build_env.run_autopkgtest = Some(true);
+        // the build env is only set up by the commands using it
+        let packager = DistributionPackager::with_registry(config, "/tmp".to_string(), registry).unwrap();
+        assert_eq!(*configs.borrow(), vec![(Some(true), Some(true), Some(true))]);
+
+        let err = packager.verify(PkgVerifyConfig::default(), true).unwrap_err();
+        assert_eq!(err.to_string(), "noop has no build env");
+        assert_eq!(*configs.borrow(), vec![
+            (Some(true), Some(true), Some(true)),
+            (Some(false), Some(false), Some(false)),
+        ]);
+    }
+
+    #[test]
+    fn test_register_custom_backend() {
+        let mut registry = BackendRegistry::default();
+        registry.register("noop", Box::new(|_, _| Ok(Box::new(NoopPackager))));
+        let packager = registry.create("noop", config(Some("noop")), "/tmp".to_string()).unwrap();
+        assert!(packager.package().is_ok());
+        assert!(packager.get_build_env().is_err());
     }
 }
@@ -1,9 +1,13 @@
+use std::collections::BTreeMap;
+use std::env;
 use std::fs;
 use std::path::Path;
 use eyre::{eyre, Report, Result};
 use serde::{Deserialize, Deserializer};
 use std::str::FromStr;
 use serde::de::DeserializeOwned;
+use crate::v1::distribution::{Distribution, DistributionRegistry};
+use regex::Regex;
 
 pub fn deserialize_option_empty_string<'de, T, D>(deserializer: D) -> Result<Option<T>, D::Error>
     where
@@ -30,11 +34,84 @@ pub fn validate_not_empty(name: &str, value: &str) -> Result<()> {
     Ok(())
 }
 
+/// A toolchain binary for one build architecture.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+pub struct ToolchainBinary {
+    pub url: String,
+    /// checksum line of the binary, for rust the gpg signature of it
+    pub checksum: String,
+}
+
+impl Validation for ToolchainBinary {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Err(err) = validate_not_empty("url", &self.url) {
+            errors.push(err);
+        }
+        if let Err(err) = validate_not_empty("checksum", &self.checksum) {
+            errors.push(err);
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// The toolchain for `build_arch`, the binary configured for it or the default one.
+fn select_binary(binaries: &BTreeMap<String, ToolchainBinary>, build_arch: &str, url: &str, checksum: &str) -> ToolchainBinary {
+    binaries.get(build_arch).cloned().unwrap_or_else(|| ToolchainBinary {
+        url: url.to_string(),
+        checksum: checksum.to_string(),
+    })
+}
+
+fn validate_binaries(binaries: &BTreeMap<String, ToolchainBinary>, errors: &mut Vec<Report>) {
+    for binary in binaries.values() {
+        if let Err(mut binary_errors) = binary.validate() {
+            errors.append(&mut binary_errors);
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, PartialEq, Clone, Default)]
 pub struct RustConfig {
     pub rust_version: String,
     pub rust_binary_url: String,
     pub rust_binary_gpg_asc: String,
+    /// toolchains of other build architectures, keyed by architecture
+    #[serde(default)]
+    pub binaries: BTreeMap<String, ToolchainBinary>,
+}
+
+impl RustConfig {
+    pub fn get_binary(&self, build_arch: &str) -> ToolchainBinary {
+        select_binary(&self.binaries, build_arch, &self.rust_binary_url, &self.rust_binary_gpg_asc)
+    }
+
+    /// The rust-std component cross builds for `arch` need, next to rust_binary_url, its signature is at `<url>.asc`.
+    pub fn get_std_url(&self, arch: &str) -> Option<String> {
+        let dist_url = self.rust_binary_url.rsplit_once('/').map_or("", |(dist_url, _)| dist_url);
+        get_rust_target(arch).map(|target| format!("{}/rust-std-{}-{}.tar.xz", dist_url, self.rust_version, target))
+    }
+}
+
+/// The rust target triple of a Debian architecture.
+fn get_rust_target(arch: &str) -> Option<&'static str> {
+    match arch {
+        "amd64" => Some("x86_64-unknown-linux-gnu"),
+        "arm64" => Some("aarch64-unknown-linux-gnu"),
+        "armhf" => Some("armv7-unknown-linux-gnueabihf"),
+        "armel" => Some("arm-unknown-linux-gnueabi"),
+        "i386" => Some("i686-unknown-linux-gnu"),
+        "ppc64el" => Some("powerpc64le-unknown-linux-gnu"),
+        "riscv64" => Some("riscv64gc-unknown-linux-gnu"),
+        "s390x" => Some("s390x-unknown-linux-gnu"),
+        _ => None,
+    }
 }
 
 impl Validation for RustConfig {
@@ -53,6 +130,8 @@ impl Validation for RustConfig {
             errors.push(err);
         }
 
+        validate_binaries(&self.binaries, &mut errors);
+
         if errors.is_empty() {
             Ok(())
         } else {
@@ -66,6 +145,15 @@ pub struct GoConfig {
     pub go_version: String,
     pub go_binary_url: String,
     pub go_binary_checksum: String,
+    /// toolchains of other build architectures, keyed by architecture
+    #[serde(default)]
+    pub binaries: BTreeMap<String, ToolchainBinary>,
+}
+
+impl GoConfig {
+    pub fn get_binary(&self, build_arch: &str) -> ToolchainBinary {
+        select_binary(&self.binaries, build_arch, &self.go_binary_url, &self.go_binary_checksum)
+    }
 }
 
 impl Validation for GoConfig {
@@ -84,6 +172,8 @@ impl Validation for GoConfig {
             errors.push(err);
         }
 
+        validate_binaries(&self.binaries, &mut errors);
+
         if errors.is_empty() {
             Ok(())
         } else {
@@ -98,6 +188,15 @@ pub struct JavascriptConfig {
     pub node_binary_url: String,
     pub node_binary_checksum: String,
     pub yarn_version: Option<String>,
+    /// toolchains of other build architectures, keyed by architecture
+    #[serde(default)]
+    pub binaries: BTreeMap<String, ToolchainBinary>,
+}
+
+impl JavascriptConfig {
+    pub fn get_binary(&self, build_arch: &str) -> ToolchainBinary {
+        select_binary(&self.binaries, build_arch, &self.node_binary_url, &self.node_binary_checksum)
+    }
 }
 
 impl Validation for JavascriptConfig {
@@ -119,6 +218,8 @@ impl Validation for JavascriptConfig {
             }
         }
 
+        validate_binaries(&self.binaries, &mut errors);
+
         if errors.is_empty() {
             Ok(())
         } else {
@@ -162,6 +263,15 @@ pub struct JavaConfig {
     pub jdk_binary_url: String,
     pub jdk_binary_checksum: String,
     pub gradle: Option<GradleConfig>,
+    /// toolchains of other build architectures, keyed by architecture
+    #[serde(default)]
+    pub binaries: BTreeMap<String, ToolchainBinary>,
+}
+
+impl JavaConfig {
+    pub fn get_binary(&self, build_arch: &str) -> ToolchainBinary {
+        select_binary(&self.binaries, build_arch, &self.jdk_binary_url, &self.jdk_binary_checksum)
+    }
 }
 
 impl Validation for JavaConfig {
@@ -177,6 +287,8 @@ impl Validation for JavaConfig {
         if let Err(err) = validate_not_empty("jdk_binary_checksum", &self.jdk_binary_checksum) {
             errors.push(err);
         }
+        validate_binaries(&self.binaries, &mut errors);
+
         if errors.is_empty() {
             Ok(())
         } else {
@@ -214,6 +326,15 @@ pub struct NimConfig {
     pub nim_version: String,
     pub nim_binary_url: String,
     pub nim_version_checksum: String,
+    /// toolchains of other build architectures, keyed by architecture
+    #[serde(default)]
+    pub binaries: BTreeMap<String, ToolchainBinary>,
+}
+
+impl NimConfig {
+    pub fn get_binary(&self, build_arch: &str) -> ToolchainBinary {
+        select_binary(&self.binaries, build_arch, &self.nim_binary_url, &self.nim_version_checksum)
+    }
 }
 
 impl Validation for NimConfig {
@@ -230,6 +351,8 @@ impl Validation for NimConfig {
             errors.push(err);
         }
 
+        validate_binaries(&self.binaries, &mut errors);
+
         if errors.is_empty() {
             Ok(())
         } else {
@@ -326,11 +449,44 @@ impl Validation for SubModule {
     }
 }
 
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum SignatureFormat {
+    #[default]
+    Gpg,
+    Ssh,
+}
+
+/// Keys one of which has to have signed the tag.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+pub struct TagSignatureConfig {
+    #[serde(default)]
+    pub format: SignatureFormat,
+    /// gpg: public keys, binary or armored, ssh: allowed signers file, relative to the config file
+    pub keyring: String,
+}
+
+impl Validation for TagSignatureConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        match validate_not_empty("keyring", &self.keyring) {
+            Ok(()) => Ok(()),
+            Err(err) => Err(vec![err]),
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, PartialEq, Clone, Default)]
 pub struct GitPackageTypeConfig {
     pub git_tag: String,
     pub git_url: String,
+    /// commit the tag has to point to, so a moved tag fails the build
+    pub git_commit: Option<String>,
+    pub tag_signature: Option<TagSignatureConfig>,
     pub submodules: Vec<SubModule>,
+    /// fails the build if a submodule, at any depth, is not in submodules
+    pub strict_submodules: Option<bool>,
+    /// checks out the git-lfs objects, so the orig tarball has their content instead of the pointer files
+    pub lfs: Option<bool>,
     pub language_env: LanguageEnv,
 }
 
@@ -345,6 +501,18 @@ impl Validation for GitPackageTypeConfig {
         if let Err(err) = validate_not_empty("git_url", &self.git_url) {
             errors.push(err);
         }
+        if let Some(git_commit) = &self.git_commit {
+            let is_hash = (git_commit.len() == 40 || git_commit.len() == 64)
+                && git_commit.chars().all(|c| c.is_ascii_hexdigit());
+            if !is_hash {
+                errors.push(eyre!("field: git_commit must be a full commit hash, got {}", git_commit));
+            }
+        }
+        if let Some(tag_signature) = &self.tag_signature {
+            if let Err(mut tag_signature_errors) = tag_signature.validate() {
+                errors.append(&mut tag_signature_errors);
+            }
+        }
 
         if errors.is_empty() {
             Ok(())
@@ -380,6 +548,36 @@ pub struct PackageFields {
     pub version_number: String,
     pub revision_number: String,
     pub homepage: String,
+    /// appends ~<suite>1 to the revision, so the builds for several distributions can share an apt repository
+    pub version_suffix: Option<bool>,
+    /// upper bound for the mtime of the files in tarballs created by pkg-builder
+    pub source_date_epoch: Option<u64>,
+}
+
+impl PackageFields {
+    pub fn get_version_suffix(&self, suite: &str) -> Option<String> {
+        self.version_suffix.unwrap_or_default().then(|| format!("~{}1", suite))
+    }
+
+    /// From the config or the SOURCE_DATE_EPOCH environment variable, None to derive it from the source.
+    pub fn get_source_date_epoch(&self) -> Result<Option<u64>> {
+        if let Some(epoch) = self.source_date_epoch {
+            return Ok(Some(epoch));
+        }
+        match env::var("SOURCE_DATE_EPOCH") {
+            Ok(epoch) => epoch
+                .trim()
+                .parse()
+                .map(Some)
+                .map_err(|_| eyre!("SOURCE_DATE_EPOCH must be a unix timestamp, got {}", epoch)),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Debian revision of the built packages, e.g. 1~jammy1 with the version suffix.
+    pub fn get_revision(&self, suite: &str) -> String {
+        format!("{}{}", self.revision_number, self.get_version_suffix(suite).unwrap_or_default())
+    }
 }
 
 impl Validation for PackageFields {
@@ -409,14 +607,239 @@ impl Validation for PackageFields {
     }
 }
 
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ContainerRuntime {
+    #[default]
+    Podman,
+    Docker,
+}
+
+impl ContainerRuntime {
+    pub fn binary(&self) -> &str {
+        match self {
+            ContainerRuntime::Podman => "podman",
+            ContainerRuntime::Docker => "docker",
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+pub struct ContainerConfig {
+    #[serde(default)]
+    pub runtime: ContainerRuntime,
+    /// base image to build in, pinned by digest for reproducible builds
+    /// e.g. docker.io/library/debian:bookworm@sha256:...
+    pub image: Option<String>,
+    /// builds in an image that is not pinned by digest, e.g. the default image of the distribution
+    pub allow_unpinned_image: Option<bool>,
+}
+
+impl Validation for ContainerConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Some(image) = &self.image {
+            if let Err(err) = validate_not_empty("image", image) {
+                errors.push(err);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum ChrootCreator {
+    #[default]
+    #[serde(rename = "sbuild-createchroot")]
+    SbuildCreatechroot,
+    Mmdebstrap,
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+pub struct ChrootConfig {
+    #[serde(default)]
+    pub creator: ChrootCreator,
+    /// snapshot timestamp e.g. 20240601T000000Z, builds against snapshot.debian.org
+    /// or snapshot.ubuntu.com instead of the live archive, requires mmdebstrap
+    pub snapshot: Option<String>,
+    /// mirror used instead of the distribution's mirror or the snapshot, e.g. a local mirror
+    pub mirror: Option<String>,
+    /// sha256 of the created chroot tarball, checked after creation and before building
+    pub tarball_hash: Option<String>,
+}
+
+impl Validation for ChrootConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Some(snapshot) = &self.snapshot {
+            let re = Regex::new(r"^\d{8}T\d{6}Z$").unwrap();
+            if !re.is_match(snapshot) {
+                errors.push(eyre!("field: snapshot must be a timestamp like 20240601T000000Z"));
+            }
+            if self.creator != ChrootCreator::Mmdebstrap {
+                errors.push(eyre!("field: snapshot requires creator = \"mmdebstrap\""));
+            }
+        }
+        if let Some(mirror) = &self.mirror {
+            if let Err(err) = validate_not_empty("mirror", mirror) {
+                errors.push(err);
+            }
+        }
+        if let Some(tarball_hash) = &self.tarball_hash {
+            if let Err(err) = validate_not_empty("tarball_hash", tarball_hash) {
+                errors.push(err);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+pub struct AutopkgtestImageConfig {
+    /// disk size of the qemu image, e.g. 25G
+    pub size: Option<String>,
+    /// installed into the image on top of the base system
+    #[serde(default)]
+    pub extra_packages: Vec<String>,
+    /// age after which a warning to rebuild the image is shown, defaults to 30
+    pub max_age_days: Option<u64>,
+}
+
+impl Validation for AutopkgtestImageConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if let Some(size) = &self.size {
+            let re = Regex::new(r"^\d+[KMGT]?$").unwrap();
+            if !re.is_match(size) {
+                errors.push(eyre!("field: size must be a size like 25G"));
+            }
+        }
+        for package in self.extra_packages.iter() {
+            if let Err(err) = validate_not_empty("extra_packages", package) {
+                errors.push(err);
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+#[serde(rename_all = "lowercase")]
+pub enum AutopkgtestBackend {
+    #[default]
+    Qemu,
+    Unshare,
+    Schroot,
+    Podman,
+    Null,
+}
+
+impl AutopkgtestBackend {
+    pub fn name(&self) -> &str {
+        match self {
+            AutopkgtestBackend::Qemu => "qemu",
+            AutopkgtestBackend::Unshare => "unshare",
+            AutopkgtestBackend::Schroot => "schroot",
+            AutopkgtestBackend::Podman => "podman",
+            AutopkgtestBackend::Null => "null",
+        }
+    }
+}
+
+/// Options of the autopkgtest virtualization server, each used by one backend only.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+pub struct AutopkgtestOptions {
+    /// qemu: memory of the VM in MiB
+    pub ram_size: Option<u64>,
+    /// qemu: number of CPUs of the VM
+    pub cpus: Option<u32>,
+    /// unshare: tarball the tests run in, defaults to the chroot tarball of the build env
+    pub tarball: Option<String>,
+    /// schroot: name of the schroot, defaults to <suite>-<arch>-sbuild
+    pub schroot: Option<String>,
+    /// podman: image the tests run in, defaults to autopkgtest/<vendor>:<suite>
+    pub image: Option<String>,
+}
+
+impl AutopkgtestOptions {
+    fn validate_for(&self, backend: &AutopkgtestBackend) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        let options = [
+            ("ram_size", self.ram_size.is_some(), AutopkgtestBackend::Qemu),
+            ("cpus", self.cpus.is_some(), AutopkgtestBackend::Qemu),
+            ("tarball", self.tarball.is_some(), AutopkgtestBackend::Unshare),
+            ("schroot", self.schroot.is_some(), AutopkgtestBackend::Schroot),
+            ("image", self.image.is_some(), AutopkgtestBackend::Podman),
+        ];
+        for (name, is_set, option_backend) in options {
+            if is_set && &option_backend != backend {
+                errors.push(eyre!(
+                    "field: {} is only used with autopkgtest_backend = \"{}\"",
+                    name,
+                    option_backend.name()
+                ));
+            }
+        }
+        for (name, value) in [("tarball", &self.tarball), ("schroot", &self.schroot), ("image", &self.image)] {
+            if let Some(value) = value {
+                if let Err(err) = validate_not_empty(name, value) {
+                    errors.push(err);
+                }
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
 #[derive(Debug, Deserialize, PartialEq, Default, Clone)]
 pub struct BuildEnv {
     pub codename: String,
     pub arch: String,
+    /// architecture of the build machine and chroot, defaults to arch, cross builds for arch if it differs
+    pub build_arch: Option<String>,
     pub pkg_builder_version: String,
     pub debcrafter_version: String,
     pub sbuild_cache_dir: Option<String>,
+    /// downloaded tarballs by tarball_hash, defaults to ~/.cache/pkg-builder/downloads
+    pub download_cache_dir: Option<String>,
+    /// bare mirrors of the git sources, defaults to ~/.cache/pkg-builder/git
+    pub git_cache_dir: Option<String>,
+    /// only uses the mirrors in git_cache_dir, never the network
+    pub git_offline: Option<bool>,
+    /// sbuild | container | host, defaults to sbuild
+    pub backend: Option<String>,
     pub docker: Option<bool>,
+    pub container: Option<ContainerConfig>,
+    pub chroot: Option<ChrootConfig>,
+    pub autopkgtest_image: Option<AutopkgtestImageConfig>,
+    /// qemu | unshare | schroot | podman | null, defaults to qemu
+    pub autopkgtest_backend: Option<AutopkgtestBackend>,
+    pub autopkgtest_options: Option<AutopkgtestOptions>,
     pub run_lintian: Option<bool>,
     pub run_piuparts: Option<bool>,
     pub run_autopkgtest: Option<bool>,
@@ -428,6 +851,33 @@ pub struct BuildEnv {
     pub workdir: Option<String>,
 }
 
+impl BuildEnv {
+    pub fn get_build_arch(&self) -> String {
+        self.build_arch.clone().unwrap_or_else(|| self.arch.clone())
+    }
+
+    pub fn is_cross_build(&self) -> bool {
+        self.get_build_arch() != self.arch
+    }
+
+    pub fn get_download_cache_dir(&self) -> String {
+        self.download_cache_dir.clone().unwrap_or("~/.cache/pkg-builder/downloads".to_string())
+    }
+
+    pub fn get_git_cache_dir(&self) -> String {
+        self.git_cache_dir.clone().unwrap_or("~/.cache/pkg-builder/git".to_string())
+    }
+
+    pub fn get_backend(&self) -> String {
+        match &self.backend {
+            Some(backend) => backend.clone(),
+            // docker = true predates the backend setting
+            None if self.docker.unwrap_or_default() => "container".to_string(),
+            None => "sbuild".to_string(),
+        }
+    }
+}
+
 impl Validation for BuildEnv {
     fn validate(&self) -> Result<(), Vec<Report>> {
         let mut errors = Vec::new();
@@ -457,6 +907,168 @@ impl Validation for BuildEnv {
         if let Err(err) = validate_not_empty("sbuild_version", &self.sbuild_version) {
             errors.push(err);
         }
+        if let Some(backend) = &self.backend {
+            if let Err(err) = validate_not_empty("backend", backend) {
+                errors.push(err);
+            }
+        }
+        if let Some(container) = &self.container {
+            if let Err(mut container_errors) = container.validate() {
+                errors.append(&mut container_errors);
+            }
+        }
+        if let Some(chroot) = &self.chroot {
+            if let Err(mut chroot_errors) = chroot.validate() {
+                errors.append(&mut chroot_errors);
+            }
+        }
+        if let Some(autopkgtest_image) = &self.autopkgtest_image {
+            if let Err(mut autopkgtest_image_errors) = autopkgtest_image.validate() {
+                errors.append(&mut autopkgtest_image_errors);
+            }
+        }
+        if let Some(autopkgtest_options) = &self.autopkgtest_options {
+            let backend = self.autopkgtest_backend.clone().unwrap_or_default();
+            if let Err(mut autopkgtest_options_errors) = autopkgtest_options.validate_for(&backend) {
+                errors.append(&mut autopkgtest_options_errors);
+            }
+        }
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+pub struct PiupartsConfig {
+    /// directory with the .deb files of the previous version, piuparts upgrades from it to the new package
+    pub upgrade_from_dir: Option<String>,
+    /// apt repository with the previous version, e.g. "deb [trusted=yes] https://repo.example.com/debian bookworm main"
+    pub upgrade_from_repo: Option<String>,
+}
+
+impl Validation for PiupartsConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if self.upgrade_from_dir.is_some() && self.upgrade_from_repo.is_some() {
+            errors.push(eyre!("field: upgrade_from_dir and upgrade_from_repo cannot be used together"));
+        }
+        if let Some(upgrade_from_dir) = &self.upgrade_from_dir {
+            if let Err(err) = validate_not_empty("upgrade_from_dir", upgrade_from_dir) {
+                errors.push(err);
+            }
+        }
+        if let Some(upgrade_from_repo) = &self.upgrade_from_repo {
+            if !upgrade_from_repo.starts_with("deb ") {
+                errors.push(eyre!("field: upgrade_from_repo must be an apt source line starting with deb"));
+            }
+        }
+
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
+
+/// Fields of build_env that differ for some entries of the build matrix.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+pub struct MatrixOverride {
+    /// entries the override applies to, all codenames and arches if not set
+    pub codename: Option<String>,
+    pub arch: Option<String>,
+    pub build_arch: Option<String>,
+    pub run_lintian: Option<bool>,
+    pub run_piuparts: Option<bool>,
+    pub run_autopkgtest: Option<bool>,
+    pub lintian_version: Option<String>,
+    pub piuparts_version: Option<String>,
+    pub autopkgtest_version: Option<String>,
+    pub sbuild_version: Option<String>,
+    pub workdir: Option<String>,
+}
+
+impl MatrixOverride {
+    /// The codename may also be the suite of the matrix codename, like noble for "noble numbat".
+    pub fn matches(&self, registry: &DistributionRegistry, codename: &str, arch: &str) -> bool {
+        self.codename.as_ref().is_none_or(|override_codename| registry.is_same(override_codename, codename))
+            && self.arch.as_ref().is_none_or(|override_arch| override_arch == arch)
+    }
+
+    pub fn apply(&self, build_env: &mut BuildEnv) {
+        if let Some(build_arch) = &self.build_arch {
+            build_env.build_arch = Some(build_arch.clone());
+        }
+        if let Some(run_lintian) = self.run_lintian {
+            build_env.run_lintian = Some(run_lintian);
+        }
+        if let Some(run_piuparts) = self.run_piuparts {
+            build_env.run_piuparts = Some(run_piuparts);
+        }
+        if let Some(run_autopkgtest) = self.run_autopkgtest {
+            build_env.run_autopkgtest = Some(run_autopkgtest);
+        }
+        if let Some(lintian_version) = &self.lintian_version {
+            build_env.lintian_version = lintian_version.clone();
+        }
+        if let Some(piuparts_version) = &self.piuparts_version {
+            build_env.piuparts_version = piuparts_version.clone();
+        }
+        if let Some(autopkgtest_version) = &self.autopkgtest_version {
+            build_env.autopkgtest_version = autopkgtest_version.clone();
+        }
+        if let Some(sbuild_version) = &self.sbuild_version {
+            build_env.sbuild_version = sbuild_version.clone();
+        }
+        if let Some(workdir) = &self.workdir {
+            build_env.workdir = Some(workdir.clone());
+        }
+    }
+}
+
+/// Builds the package for every combination of codenames and arches.
+#[derive(Debug, Deserialize, PartialEq, Clone, Default)]
+pub struct MatrixConfig {
+    pub codenames: Vec<String>,
+    pub arches: Vec<String>,
+    /// applied in order to the entries they match
+    #[serde(default)]
+    pub overrides: Vec<MatrixOverride>,
+}
+
+impl Validation for MatrixConfig {
+    fn validate(&self) -> Result<(), Vec<Report>> {
+        let mut errors = Vec::new();
+
+        if self.codenames.is_empty() {
+            errors.push(eyre!("field: codenames cannot be empty"));
+        }
+        if self.arches.is_empty() {
+            errors.push(eyre!("field: arches cannot be empty"));
+        }
+        for codename in self.codenames.iter() {
+            if let Err(err) = validate_not_empty("codenames", codename) {
+                errors.push(err);
+            }
+        }
+        for arch in self.arches.iter() {
+            if let Err(err) = validate_not_empty("arches", arch) {
+                errors.push(err);
+            }
+        }
+        for matrix_override in self.overrides.iter() {
+            if let Some(arch) = &matrix_override.arch {
+                if !self.arches.contains(arch) {
+                    errors.push(eyre!("field: overrides arch {} is not one of the matrix arches", arch));
+                }
+            }
+        }
+
         if errors.is_empty() {
             Ok(())
         } else {
@@ -465,12 +1077,33 @@ impl Validation for BuildEnv {
     }
 }
 
+impl MatrixConfig {
+    /// Override codenames are resolved through the registry, so they can also name the suite of a matrix codename.
+    pub fn validate_override_codenames(&self, registry: &DistributionRegistry) -> Result<(), Vec<Report>> {
+        let errors: Vec<Report> = self
+            .overrides
+            .iter()
+            .filter_map(|matrix_override| matrix_override.codename.as_ref())
+            .filter(|codename| !self.codenames.iter().any(|matrix_codename| registry.is_same(codename, matrix_codename)))
+            .map(|codename| eyre!("field: overrides codename {} is not one of the matrix codenames", codename))
+            .collect();
+        if errors.is_empty() {
+            Ok(())
+        } else {
+            Err(errors)
+        }
+    }
+}
 
 #[derive(Debug, Deserialize, PartialEq, Clone, Default)]
 pub struct PkgConfig {
     pub package_fields: PackageFields,
     pub package_type: PackageType,
     pub build_env: BuildEnv,
+    pub piuparts: Option<PiupartsConfig>,
+    pub matrix: Option<MatrixConfig>,
+    #[serde(default)]
+    pub distributions: Vec<Distribution>,
 }
 
 impl Validation for PkgConfig {
@@ -491,6 +1124,27 @@ impl Validation for PkgConfig {
             errors.append(&mut build_env_errors);
         }
 
+        if let Some(piuparts) = &self.piuparts {
+            if let Err(mut piuparts_errors) = piuparts.validate() {
+                errors.append(&mut piuparts_errors);
+            }
+        }
+
+        if let Some(matrix) = &self.matrix {
+            if let Err(mut matrix_errors) = matrix.validate() {
+                errors.append(&mut matrix_errors);
+            }
+            if let Err(mut override_errors) = matrix.validate_override_codenames(&DistributionRegistry::new(&self.distributions)) {
+                errors.append(&mut override_errors);
+            }
+        }
+
+        for distribution in self.distributions.iter() {
+            if let Err(mut distribution_errors) = distribution.validate() {
+                errors.append(&mut distribution_errors);
+            }
+        }
+
         if errors.is_empty() {
             Ok(())
         } else {
@@ -580,6 +1234,8 @@ workdir="~/.pkg-builder/packages/jammy"
                 version_number: "1.0.0".to_string(),
                 revision_number: "1".to_string(),
                 homepage: "https://github.com/eth-pkg/pkg-builder#examples".to_string(),
+                version_suffix: None,
+                source_date_epoch: None,
             },
             package_type: PackageType::Default(DefaultPackageTypeConfig {
                 tarball_url: "hello-world-1.0.0.tar.gz".to_string(),
@@ -588,15 +1244,26 @@ workdir="~/.pkg-builder/packages/jammy"
                     rust_version: "1.22".to_string(),
                     rust_binary_url: "http:://example.com".to_string(),
                     rust_binary_gpg_asc: "binary_key".to_string(),
+                    binaries: BTreeMap::new(),
                 }),
             }),
             build_env: BuildEnv {
                 codename: "bookworm".to_string(),
                 arch: "amd64".to_string(),
+                build_arch: None,
                 pkg_builder_version: "0.2.1".to_string(),
                 debcrafter_version: "2711b53".to_string(),
                 sbuild_cache_dir: None,
+                download_cache_dir: None,
+                git_cache_dir: None,
+                git_offline: None,
+                backend: None,
                 docker: None,
+                container: None,
+                chroot: None,
+                autopkgtest_image: None,
+                autopkgtest_backend: None,
+                autopkgtest_options: None,
                 run_lintian: Some(false),
                 run_piuparts: Some(false),
                 run_autopkgtest: Some(false),
@@ -606,10 +1273,143 @@ workdir="~/.pkg-builder/packages/jammy"
                 sbuild_version: "0.85.6".to_string(),
                 workdir: Some("~/.pkg-builder/packages/jammy".to_string()),
             },
+            piuparts: None,
+            matrix: None,
+            distributions: vec![],
         };
         assert_eq!(parse::<PkgConfig>(config_str).unwrap(), config);
     }
 
+    #[test]
+    fn test_toolchain_binaries() {
+        let config_str = r#"
+go_version = "1.22.0"
+go_binary_url = "https://go.dev/dl/go1.22.0.linux-amd64.tar.gz"
+go_binary_checksum = "amd64sum"
+
+[binaries.arm64]
+url = "https://go.dev/dl/go1.22.0.linux-arm64.tar.gz"
+checksum = "arm64sum"
+"#;
+        let go = parse::<GoConfig>(config_str).unwrap();
+        assert_eq!(go.get_binary("arm64").url, "https://go.dev/dl/go1.22.0.linux-arm64.tar.gz");
+        assert_eq!(go.get_binary("arm64").checksum, "arm64sum");
+        assert_eq!(go.get_binary("amd64").url, "https://go.dev/dl/go1.22.0.linux-amd64.tar.gz");
+
+        let go: GoConfig = toml::from_str(&config_str.replace("arm64sum", "")).unwrap();
+        let errors = go.validate().unwrap_err();
+        assert_eq!(errors[0].to_string(), "field: checksum cannot be empty");
+    }
+
+    #[test]
+    fn test_rust_std_url() {
+        let rust = RustConfig {
+            rust_version: "1.77.2".to_string(),
+            rust_binary_url: "https://static.rust-lang.org/dist/rust-1.77.2-x86_64-unknown-linux-gnu.tar.xz".to_string(),
+            ..Default::default()
+        };
+        assert_eq!(rust.get_std_url("arm64").unwrap(), "https://static.rust-lang.org/dist/rust-std-1.77.2-aarch64-unknown-linux-gnu.tar.xz");
+        assert_eq!(rust.get_std_url("mips64el"), None);
+    }
+
+    #[test]
+    fn test_chroot_config() {
+        let config_str = r#"
+creator = "mmdebstrap"
+snapshot = "20240601T000000Z"
+tarball_hash = "abc"
+"#;
+        let chroot = parse::<ChrootConfig>(config_str).unwrap();
+        assert_eq!(chroot.creator, ChrootCreator::Mmdebstrap);
+        assert!(chroot.validate().is_ok());
+
+        let chroot: ChrootConfig = toml::from_str(r#"snapshot = "2024-06-01""#).unwrap();
+        let errors = chroot.validate().unwrap_err();
+        assert_eq!(errors.len(), 2);
+        assert_eq!(errors[0].to_string(), "field: snapshot must be a timestamp like 20240601T000000Z");
+        assert_eq!(errors[1].to_string(), "field: snapshot requires creator = \"mmdebstrap\"");
+    }
+
+    #[test]
+    fn test_autopkgtest_image_config() {
+        let config_str = r#"
+size = "30G"
+extra_packages = ["ca-certificates"]
+max_age_days = 14
+"#;
+        let autopkgtest_image = parse::<AutopkgtestImageConfig>(config_str).unwrap();
+        assert_eq!(autopkgtest_image, AutopkgtestImageConfig {
+            size: Some("30G".to_string()),
+            extra_packages: vec!["ca-certificates".to_string()],
+            max_age_days: Some(14),
+        });
+        let autopkgtest_image: AutopkgtestImageConfig = toml::from_str(r#"size = "30 GB""#).unwrap();
+        assert!(autopkgtest_image.validate().is_err());
+    }
+
+    #[test]
+    fn test_autopkgtest_options() {
+        let build_env: BuildEnv = toml::from_str(r#"
+codename = "bookworm"
+arch = "amd64"
+pkg_builder_version = "0.1.0"
+debcrafter_version = "latest"
+lintian_version = "2.116.3"
+piuparts_version = "1.1.7"
+autopkgtest_version = "5.28"
+sbuild_version = "0.85.6"
+workdir = ""
+autopkgtest_backend = "unshare"
+
+[autopkgtest_options]
+tarball = "~/.cache/sbuild/bookworm-amd64.tar.gz"
+"#).unwrap();
+        assert_eq!(build_env.autopkgtest_backend, Some(AutopkgtestBackend::Unshare));
+        assert!(build_env.autopkgtest_options.as_ref().unwrap().validate_for(&AutopkgtestBackend::Unshare).is_ok());
+        let errors = build_env.autopkgtest_options.unwrap().validate_for(&AutopkgtestBackend::Qemu).unwrap_err();
+        assert_eq!(errors[0].to_string(), "field: tarball is only used with autopkgtest_backend = \"unshare\"");
+    }
+
+    #[test]
+    fn test_piuparts_config() {
+        let piuparts = parse::<PiupartsConfig>(r#"upgrade_from_dir = "~/releases/hello-world""#).unwrap();
+        assert_eq!(piuparts.upgrade_from_dir, Some("~/releases/hello-world".to_string()));
+        let both: PiupartsConfig = toml::from_str(r#"
+upgrade_from_dir = "~/releases/hello-world"
+upgrade_from_repo = "https://repo.example.com/debian"
+"#).unwrap();
+        let errors = both.validate().unwrap_err();
+        assert_eq!(errors.len(), 2);
+    }
+
+    #[test]
+    fn test_container_config() {
+        let config_str = r#"
+codename="bookworm"
+arch = "amd64"
+pkg_builder_version="0.2.1"
+debcrafter_version = "2711b53"
+docker = true
+lintian_version="2.116.3"
+piuparts_version="1.1.7"
+autopkgtest_version="5.28"
+sbuild_version="0.85.6"
+workdir=""
+
+[container]
+runtime = "docker"
+image = "docker.io/library/debian:bookworm@sha256:abc"
+"#;
+        let build_env = parse::<BuildEnv>(config_str).unwrap();
+        assert_eq!(build_env.docker, Some(true));
+        assert_eq!(build_env.get_backend(), "container");
+        assert_eq!(build_env.container, Some(ContainerConfig {
+            runtime: ContainerRuntime::Docker,
+            image: Some("docker.io/library/debian:bookworm@sha256:abc".to_string()),
+            allow_unpinned_image: None,
+        }));
+    }
+
     #[test]
     fn test_empty_strings_are_error_rust_config() {
         let config = RustConfig::default();
@@ -815,6 +1615,34 @@ workdir="~/.pkg-builder/packages/jammy"
         }
     }
 
+    #[test]
+    fn test_git_commit_and_tag_signature_are_validated() {
+        let config = GitPackageTypeConfig {
+            git_tag: "v1.0.0".to_string(),
+            git_url: "https://github.com/eth-pkg/hello.git".to_string(),
+            git_commit: Some("v1.0.0".to_string()),
+            tag_signature: Some(TagSignatureConfig::default()),
+            ..Default::default()
+        };
+        let errors: Vec<String> = config.validate().unwrap_err().iter().map(|err| err.to_string()).collect();
+        assert_eq!(errors, vec![
+            "field: git_commit must be a full commit hash, got v1.0.0",
+            "field: keyring cannot be empty",
+        ]);
+
+        let config: GitPackageTypeConfig = toml::from_str(r#"
+            git_tag = "v1.0.0"
+            git_url = "https://github.com/eth-pkg/hello.git"
+            git_commit = "72523ee3f865e09f8a6117c1b5e74cbb2df4f60e"
+            submodules = []
+            tag_signature = { format = "ssh", keyring = "allowed_signers" }
+            [language_env]
+            language_env = "c"
+        "#).unwrap();
+        assert!(config.validate().is_ok());
+        assert_eq!(config.tag_signature.unwrap().format, SignatureFormat::Ssh);
+    }
+
     #[test]
     fn test_empty_strings_are_error_gradle_config() {
         let config = GradleConfig::default();
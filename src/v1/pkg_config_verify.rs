@@ -63,6 +63,6 @@ pub struct PkgVerifyConfig {
 
 impl Validation for PkgVerifyConfig {
     fn validate(&self) -> eyre::Result<(), Vec<Report>> {
-        return self.verify.validate();
+        self.verify.validate()
     }
 }
\ No newline at end of file